@@ -1,238 +1,585 @@
 use std::sync::atomic::Ordering;
 
-use actix_web::{post, web, Error, HttpResponse};
+use actix_web::{post, web, Error, HttpRequest, HttpResponse};
 use futures::future::join_all;
 use futures_util::StreamExt as _;
 use reqwest::StatusCode;
 
 use crate::{
-    log, proposer::PROPOSAL_NUMBER_TO_IGNORE, Accept, Propose, Role, CLIENT, NODE_ROLE,
-    PAXOS_ACCEPTOR_NODES,
+    leader::{fenced, leased_to_other},
+    membership::{is_system, membership},
+    messages::{Accept, Accepted, AcceptedEntry, Any, Encoding, ErrorBody, Nack, Prepare, Promise},
+    proposer::promise,
+    wal::{self, Record},
+    Ballot, Leader, PaxosNode, Role,
 };
 
+/// Why a promise was not given.
+enum Refused {
+    Promised(Ballot),
+    Compacted(u64),
+    // Another proposer holds a lease granted by this acceptor
+    Leased(Leader),
+    // The acceptor restarted less than a lease duration ago
+    Fenced,
+}
+
+/// Why an accept was not taken although its ballot was high enough.
+enum Rejected {
+    Compacted(u64),
+    // Sent by a client outside the fast window granted by the leader
+    NotGranted,
+    // Another value was accepted for the slot in the same ballot
+    Taken,
+}
+
+/// Answer to a proposer while another one holds a lease granted by this acceptor, the leader
+/// reads without asking the acceptors until the lease expires.
+async fn leased(node: &PaxosNode, ballot: Ballot, holder: &Leader) -> HttpResponse {
+    node.log("Acceptor: Lease held by", &holder.node_id.to_string())
+        .await;
+    HttpResponse::Conflict().json(ErrorBody {
+        error: format!(
+            "Ballot {} not promised, node {} holds the lease",
+            ballot, holder.node_id
+        ),
+    })
+}
+
+/// Answer to a proposer while the acceptor is fenced after a restart, it may have granted a lease
+/// it does not remember.
+async fn restarted(node: &PaxosNode, ballot: Ballot) -> HttpResponse {
+    node.log("Acceptor: Restarted, not promised", &ballot.to_string())
+        .await;
+    HttpResponse::ServiceUnavailable().json(ErrorBody {
+        error: format!(
+            "Ballot {} not promised, the acceptor restarted less than a lease ago",
+            ballot
+        ),
+    })
+}
+
+/// Answer to a proposal for a slot covered by the snapshot, the values accepted for it are gone
+/// so the acceptor can no longer take part in it.
+async fn compacted(node: &PaxosNode, slot: u64, log_start: u64) -> HttpResponse {
+    node.log("Acceptor: Slot compacted", &slot.to_string())
+        .await;
+    HttpResponse::Gone().json(ErrorBody {
+        error: format!("Slot {} compacted, the log starts at {}", slot, log_start),
+    })
+}
+
 #[post("/propose")]
-async fn propose(mut value: web::Payload) -> Result<HttpResponse, Error> {
-    log("Acceptor: Propose started", "").await;
-    let role = *NODE_ROLE.read().unwrap();
-    if role == Role::Learner {
-        return Ok(HttpResponse::Forbidden().finish());
-    }
+async fn propose(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    mut value: web::Payload,
+) -> Result<HttpResponse, Error> {
     let mut bytes = web::BytesMut::new();
     while let Some(item) = value.next().await {
         bytes.extend_from_slice(&item?);
     }
-    let proposal_number: Propose = String::from_utf8(bytes.to_vec())
-        .unwrap()
-        .parse::<u64>()
-        .unwrap();
-    if proposal_number < PROPOSAL_NUMBER_TO_IGNORE.load(Ordering::Acquire) {
-        log(
-            "Acceptor: Propose not acceptable",
-            &proposal_number.to_string(),
-        )
-        .await;
-        Ok(HttpResponse::NotAcceptable().finish())
-    } else {
-        PROPOSAL_NUMBER_TO_IGNORE.store(proposal_number, Ordering::Release);
-        log("Acceptor: promised", &proposal_number.to_string()).await;
-        Ok(HttpResponse::Ok().finish())
+    match Encoding::of(&req) {
+        Ok(encoding) => Ok(receive_prepare(&node, encoding, &bytes).await),
+        Err(e) => Ok(e.into()),
     }
 }
 
-#[post("/accept")]
-async fn accept(mut value: web::Payload) -> Result<HttpResponse, Error> {
-    log("Acceptor: Accept start", "").await;
-    let role = *NODE_ROLE.read().unwrap();
-    if role == Role::Learner {
-        return Ok(HttpResponse::Forbidden().finish());
+/// Phase 1 on the acceptor side, `bytes` is a `Prepare` in `encoding`.
+pub async fn receive_prepare(node: &PaxosNode, encoding: Encoding, bytes: &[u8]) -> HttpResponse {
+    node.log("Acceptor: Propose started", "").await;
+    if !node.has_role(Role::Acceptor) {
+        return HttpResponse::Forbidden().finish();
+    }
+    let Prepare { ballot, slot } = match encoding.decode::<Prepare>(bytes) {
+        Ok(prepare) => prepare,
+        Err(e) => {
+            node.log("Acceptor: Propose value not valid", &e.to_string())
+                .await;
+            return e.into();
+        }
+    };
+    // The read lock keeps an accept from slipping in between the promise and the read of the
+    // accepted values
+    let promise = node.accepted.read().map(|accepted| {
+        let log_start = node.log_start.load(Ordering::Acquire);
+        if slot < log_start {
+            return Err(Refused::Compacted(log_start));
+        }
+        if fenced(node) {
+            return Err(Refused::Fenced);
+        }
+        if let Some(holder) = leased_to_other(node, ballot) {
+            return Err(Refused::Leased(holder));
+        }
+        let promised = promise(node, ballot);
+        if ballot >= promised {
+            Ok(
+                wal::append(node, &Record::Promise(ballot)).map(|_| Promise {
+                    accepted: accepted
+                        .range(slot..)
+                        .map(|(slot, (ballot, value))| AcceptedEntry {
+                            slot: *slot,
+                            ballot: *ballot,
+                            value: value.clone(),
+                        })
+                        .collect(),
+                }),
+            )
+        } else {
+            Err(Refused::Promised(promised))
+        }
+    });
+    match promise {
+        Ok(Ok(Ok(promise))) => {
+            node.metrics.promises_granted.inc();
+            node.log("Acceptor: promised", &ballot.to_string()).await;
+            encoding.respond(StatusCode::OK, &promise)
+        }
+        Ok(Ok(Err(e))) => {
+            node.log("Acceptor: Promise not persisted", &e.to_string())
+                .await;
+            HttpResponse::InternalServerError().finish()
+        }
+        Ok(Err(Refused::Promised(promised))) => {
+            node.metrics.promises_rejected.inc();
+            node.log("Acceptor: Propose not acceptable", &ballot.to_string())
+                .await;
+            encoding.respond(StatusCode::NOT_ACCEPTABLE, &Nack { promised })
+        }
+        Ok(Err(Refused::Compacted(log_start))) => {
+            node.metrics.promises_rejected.inc();
+            compacted(node, slot, log_start).await
+        }
+        Ok(Err(Refused::Leased(holder))) => {
+            node.metrics.promises_rejected.inc();
+            leased(node, ballot, &holder).await
+        }
+        Ok(Err(Refused::Fenced)) => {
+            node.metrics.promises_rejected.inc();
+            restarted(node, ballot).await
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
     }
+}
+
+#[post("/accept")]
+async fn accept(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    mut value: web::Payload,
+) -> Result<HttpResponse, Error> {
     let mut bytes = web::BytesMut::new();
     while let Some(item) = value.next().await {
         bytes.extend_from_slice(&item?);
     }
-    if let Ok(value) = serde_json::from_slice::<Accept>(&bytes) {
-        let promised = PROPOSAL_NUMBER_TO_IGNORE.load(Ordering::Acquire);
-        if value.proposal_number < promised {
-            log(
-                "Acceptor: Accept not acceptable already promised higher number",
-                &promised.to_string(),
-            )
-            .await;
-            Ok(HttpResponse::NotAcceptable().finish())
-        } else {
-            log("Acceptor: Trying to accept", value.value).await;
-            let futures = PAXOS_ACCEPTOR_NODES.read().map(|paxos_acceptor_nodes| {
-                paxos_acceptor_nodes
-                    .iter()
-                    .map(|n| {
-                        CLIENT
-                            .post(format!("{}/update_value", n))
-                            .json(&value)
-                            .send()
-                    })
-                    .collect::<Vec<_>>()
+    match Encoding::of(&req) {
+        Ok(encoding) => Ok(receive_accept(&node, encoding, &bytes).await),
+        Err(e) => Ok(e.into()),
+    }
+}
+
+/// Phase 2 on the acceptor side, `bytes` is an `Accept` in `encoding`, every learner is told
+/// about the accepted value.
+pub async fn receive_accept(node: &PaxosNode, encoding: Encoding, bytes: &[u8]) -> HttpResponse {
+    node.log("Acceptor: Accept start", "").await;
+    if !node.has_role(Role::Acceptor) {
+        return HttpResponse::Forbidden().finish();
+    }
+    let value = match encoding.decode::<Accept>(bytes) {
+        Ok(value) => value,
+        Err(e) => {
+            node.log("Acceptor: Accept value not valid", &e.to_string())
+                .await;
+            return e.into();
+        }
+    };
+    // Only the leader proposes system entries, a client sending one in a fast window would
+    // skip the checks done before they are proposed
+    if value.fast && is_system(&value.value) {
+        node.metrics.accepts_rejected.inc();
+        node.log(
+            "Acceptor: Fast accept of system entry",
+            &value.slot.to_string(),
+        )
+        .await;
+        return HttpResponse::BadRequest().json(ErrorBody {
+            error: "System entries cannot be sent in a fast window".to_owned(),
+        });
+    }
+    let promised = node.accepted.write().map(|mut accepted| {
+        let log_start = node.log_start.load(Ordering::Acquire);
+        if value.slot < log_start {
+            return Ok(Err(Rejected::Compacted(log_start)));
+        }
+        let promised = promise(node, value.ballot);
+        if value.ballot >= promised {
+            if value.fast && !granted(node, value.ballot, value.slot) {
+                return Ok(Err(Rejected::NotGranted));
+            }
+            // A ballot carries a single value per slot, the clients racing for a slot of a
+            // fast window each get it only on some of the acceptors
+            if accepted.get(&value.slot).is_some_and(|(ballot, accepted)| {
+                *ballot == value.ballot && *accepted != value.value
+            }) {
+                return Ok(Err(Rejected::Taken));
+            }
+            wal::append(
+                node,
+                &Record::Accept {
+                    slot: value.slot,
+                    ballot: value.ballot,
+                    value: value.value.to_string(),
+                },
+            )?;
+            accepted.insert(value.slot, (value.ballot, value.value.to_string()));
+        }
+        Ok::<_, std::io::Error>(Ok(promised))
+    });
+    let promised = match promised {
+        Ok(Ok(Ok(promised))) => promised,
+        Ok(Ok(Err(Rejected::Compacted(log_start)))) => {
+            node.metrics.accepts_rejected.inc();
+            return compacted(node, value.slot, log_start).await;
+        }
+        Ok(Ok(Err(Rejected::NotGranted))) => {
+            node.metrics.accepts_rejected.inc();
+            node.log("Acceptor: Fast accept not granted", &value.slot.to_string())
+                .await;
+            return HttpResponse::Conflict().json(ErrorBody {
+                error: format!(
+                    "Slot {} not in a fast window of ballot {}",
+                    value.slot, value.ballot
+                ),
             });
-            let futures = if let Ok(futures) = futures {
-                join_all(futures).await
-            } else {
-                vec![]
-            };
-            for response in futures {
-                match response {
-                    Ok(v) => {
-                        if v.status() == StatusCode::OK {
-                            log("Acceptor: Value updated", "").await;
-                        } else {
-                            log(
-                                "Acceptor: Value updated with error",
-                                &v.status().to_string(),
-                            )
+        }
+        Ok(Ok(Err(Rejected::Taken))) => {
+            node.metrics.accepts_rejected.inc();
+            node.log("Acceptor: Slot taken in ballot", &value.slot.to_string())
+                .await;
+            return HttpResponse::Conflict().json(ErrorBody {
+                error: format!(
+                    "Slot {} already accepted another value in ballot {}",
+                    value.slot, value.ballot
+                ),
+            });
+        }
+        Ok(Err(e)) => {
+            node.log("Acceptor: Accept not persisted", &e.to_string())
+                .await;
+            return HttpResponse::InternalServerError().finish();
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if value.ballot < promised {
+        node.metrics.accepts_rejected.inc();
+        node.log(
+            "Acceptor: Accept not acceptable already promised higher number",
+            &promised.to_string(),
+        )
+        .await;
+        encoding.respond(StatusCode::NOT_ACCEPTABLE, &Nack { promised })
+    } else {
+        node.metrics.accepts_accepted.inc();
+        node.log("Acceptor: Trying to accept", &value.value).await;
+        let mut accepted = Accepted::new(node.node_id, value.slot, value.ballot, &value.value);
+        accepted.fast = value.fast;
+        let futures = membership(node, value.slot)
+            .learners
+            .iter()
+            .map(|n| node.send(n, "/update_value", &accepted))
+            .collect::<Vec<_>>();
+        let futures = join_all(futures).await;
+        for response in futures {
+            match response {
+                Ok(v) => {
+                    if v.status == StatusCode::OK {
+                        node.log("Acceptor: Value updated", "").await;
+                    } else {
+                        node.log("Acceptor: Value updated with error", &v.status.to_string())
                             .await;
-                        }
                     }
-                    Err(e) => log("Acceptor: Error", &e.to_string()).await,
                 }
+                Err(e) => node.log("Acceptor: Error", &e.to_string()).await,
             }
-            log("Acceptor: Accepted value", value.value).await;
-            Ok(HttpResponse::Accepted().finish())
         }
-    } else {
-        log("Acceptor: Accept value not valid", "").await;
-        Ok(HttpResponse::BadRequest().finish())
+        node.log("Acceptor: Accepted value", &value.value).await;
+        encoding.respond(StatusCode::ACCEPTED, &accepted)
     }
 }
 
+/// Whether the leader of the ballot opened a fast window holding the slot.
+fn granted(node: &PaxosNode, ballot: Ballot, slot: u64) -> bool {
+    node.fast_grant.read().is_ok_and(|grant| {
+        grant.is_some_and(|grant| grant.ballot == ballot && (grant.from..grant.to).contains(&slot))
+    })
+}
+
+#[post("/any")]
+async fn any(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    mut value: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let mut bytes = web::BytesMut::new();
+    while let Some(item) = value.next().await {
+        bytes.extend_from_slice(&item?);
+    }
+    match Encoding::of(&req) {
+        Ok(encoding) => Ok(receive_any(&node, encoding, &bytes).await),
+        Err(e) => Ok(e.into()),
+    }
+}
+
+/// Fast window opened by the leader, `bytes` is an `Any` in `encoding`. It replaces the window
+/// granted before, which the leader closed first. The ballot is promised like in phase 1, but
+/// the grant is kept in memory only: a restarted acceptor refuses the fast accepts until the
+/// leader opens a new window, the clients send their values to the leader meanwhile.
+pub async fn receive_any(node: &PaxosNode, encoding: Encoding, bytes: &[u8]) -> HttpResponse {
+    if !node.has_role(Role::Acceptor) {
+        return HttpResponse::Forbidden().finish();
+    }
+    let window = match encoding.decode::<Any>(bytes) {
+        Ok(window) => window,
+        Err(e) => {
+            node.log("Acceptor: Any not valid", &e.to_string()).await;
+            return e.into();
+        }
+    };
+    if fenced(node) {
+        return restarted(node, window.ballot).await;
+    }
+    if let Some(holder) = leased_to_other(node, window.ballot) {
+        return leased(node, window.ballot, &holder).await;
+    }
+    let promised = promise(node, window.ballot);
+    if window.ballot < promised {
+        node.log("Acceptor: Any not acceptable", &promised.to_string())
+            .await;
+        return encoding.respond(StatusCode::NOT_ACCEPTABLE, &Nack { promised });
+    }
+    if let Err(e) = wal::append(node, &Record::Promise(window.ballot)) {
+        node.log("Acceptor: Promise not persisted", &e.to_string())
+            .await;
+        return HttpResponse::InternalServerError().finish();
+    }
+    if let Ok(mut grant) = node.fast_grant.write() {
+        *grant = Some(window);
+    }
+    node.log("Acceptor: Fast window granted", &format!("{:?}", window))
+        .await;
+    encoding.respond(StatusCode::OK, &window)
+}
+
 #[cfg(test)]
 mod tests {
     use actix_web::{http::StatusCode, test, App};
 
-    use crate::{tests::reset_values, Role, CURRENT_VALUE, NODE_ROLE};
+    use std::time::Duration;
+
+    use crate::{leader::grant_lease, tests::new_node, wal::tests::wal_path};
 
     use super::*;
 
+    fn prepare(ballot: Ballot, slot: u64) -> Vec<u8> {
+        Encoding::Json.encode(&Prepare::new(ballot, slot)).unwrap()
+    }
+
     #[actix_web::test]
     async fn should_promise_for_value() {
-        reset_values();
-        if let Ok(mut node_role) = NODE_ROLE.write() {
-            *node_role = Role::Acceptor;
-        }
-        let app = test::init_service(App::new().service(propose)).await;
+        let node = new_node(&[Role::Acceptor]);
+        let app = test::init_service(App::new().app_data(node.clone()).service(propose)).await;
         let req = test::TestRequest::post()
             .uri("/propose")
-            .set_payload(1483472389.to_string())
+            .set_payload(prepare(Ballot::new(148347238, 9), 0))
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert!(node.decided_values.read().unwrap().is_empty());
     }
 
     #[actix_web::test]
     async fn should_promise_with_higher_promise() {
-        reset_values();
-        if let Ok(mut node_role) = NODE_ROLE.write() {
-            *node_role = Role::Acceptor;
-        }
-        let app = test::init_service(App::new().service(propose)).await;
+        let node = new_node(&[Role::Acceptor]);
+        let app = test::init_service(App::new().app_data(node.clone()).service(propose)).await;
         let req = test::TestRequest::post()
             .uri("/propose")
-            .set_payload(1483472389.to_string())
+            .set_payload(prepare(Ballot::new(148347238, 9), 0))
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert!(node.decided_values.read().unwrap().is_empty());
 
         let req = test::TestRequest::post()
             .uri("/propose")
-            .set_payload(1483472388.to_string())
+            .set_payload(prepare(Ballot::new(148347238, 8), 0))
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        let nack: Nack = Encoding::Json.decode(&test::read_body(resp).await).unwrap();
+        assert_eq!(
+            nack,
+            Nack {
+                promised: Ballot::new(148347238, 9)
+            }
+        );
+        assert!(node.decided_values.read().unwrap().is_empty());
     }
 
     #[actix_web::test]
     async fn should_promise_with_accept_with_higher_value() {
-        reset_values();
-        if let Ok(mut node_role) = NODE_ROLE.write() {
-            *node_role = Role::Acceptor;
-        }
+        let node = new_node(&[Role::Acceptor]);
         let mut server = mockito::Server::new();
-        if let Ok(mut paxos_acceptor_nodes) = PAXOS_ACCEPTOR_NODES.write() {
-            paxos_acceptor_nodes.push(server.url());
+        if let Ok(mut paxos_learner_nodes) = node.learner_nodes.write() {
+            paxos_learner_nodes.push(server.url());
         }
         let mock_update_value = server
             .mock("POST", "/update_value")
             .with_status(StatusCode::OK.as_u16() as usize)
             .create();
-        let app = test::init_service(App::new().service(propose).service(accept)).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(accept),
+        )
+        .await;
         let req = test::TestRequest::post()
             .uri("/propose")
-            .set_payload(1483472389.to_string())
+            .set_payload(prepare(Ballot::new(148347238, 9), 0))
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert!(node.decided_values.read().unwrap().is_empty());
         let req = test::TestRequest::post()
             .uri("/accept")
-            .set_payload(serde_json::to_string(&Accept::new(1483472389, "value")).unwrap())
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accept::new(0, Ballot::new(148347238, 9), "value"))
+                    .unwrap(),
+            )
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::ACCEPTED);
         mock_update_value.assert();
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert!(node.decided_values.read().unwrap().is_empty());
 
         let req = test::TestRequest::post()
             .uri("/propose")
-            .set_payload(1483472388.to_string())
+            .set_payload(prepare(Ballot::new(148347238, 8), 0))
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert!(node.decided_values.read().unwrap().is_empty());
     }
 
     #[actix_web::test]
-    async fn should_accept_value() {
-        reset_values();
-        if let Ok(mut node_role) = NODE_ROLE.write() {
-            *node_role = Role::Acceptor;
+    async fn should_promise_with_accepted_proposal_and_value() {
+        let node = new_node(&[Role::Acceptor]);
+        let mut server = mockito::Server::new();
+        if let Ok(mut paxos_learner_nodes) = node.learner_nodes.write() {
+            paxos_learner_nodes.push(server.url());
         }
+        let mock_update_value = server
+            .mock("POST", "/update_value")
+            .with_status(StatusCode::OK.as_u16() as usize)
+            .create();
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(accept),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload(prepare(Ballot::new(1, 1), 0))
+            .to_request();
+        let promise: Promise = Encoding::Json
+            .decode(&test::call_and_read_body(&app, req).await)
+            .unwrap();
+        assert_eq!(promise, Promise::default());
+
+        let req = test::TestRequest::post()
+            .uri("/accept")
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accept::new(0, Ballot::new(1, 1), "value"))
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        mock_update_value.assert();
+
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload(prepare(Ballot::new(2, 1), 0))
+            .to_request();
+        let promise: Promise = Encoding::Json
+            .decode(&test::call_and_read_body(&app, req).await)
+            .unwrap();
+        assert_eq!(
+            promise.accepted,
+            vec![AcceptedEntry {
+                slot: 0,
+                ballot: Ballot::new(1, 1),
+                value: "value".to_owned()
+            }]
+        );
+        assert_eq!(
+            node.accepted.read().unwrap().get(&0),
+            Some(&(Ballot::new(1, 1), "value".to_owned()))
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_accept_value() {
+        let node = new_node(&[Role::Acceptor]);
         let mut server = mockito::Server::new();
-        if let Ok(mut paxos_acceptor_nodes) = PAXOS_ACCEPTOR_NODES.write() {
-            paxos_acceptor_nodes.push(server.url());
+        if let Ok(mut paxos_learner_nodes) = node.learner_nodes.write() {
+            paxos_learner_nodes.push(server.url());
         }
         let mock_update_value = server
             .mock("POST", "/update_value")
             .with_status(StatusCode::OK.as_u16() as usize)
             .create();
-        let app = test::init_service(App::new().service(accept)).await;
+        let app = test::init_service(App::new().app_data(node.clone()).service(accept)).await;
         let req = test::TestRequest::post()
             .uri("/accept")
-            .set_payload(serde_json::to_string(&Accept::new(1001, "value")).unwrap())
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accept::new(0, Ballot::new(100, 1), "value"))
+                    .unwrap(),
+            )
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::ACCEPTED);
         mock_update_value.assert();
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert!(node.decided_values.read().unwrap().is_empty());
     }
 
     #[actix_web::test]
     async fn should_promise_and_accept_value() {
-        reset_values();
-        if let Ok(mut node_role) = NODE_ROLE.write() {
-            *node_role = Role::Acceptor;
-        }
-        let app = test::init_service(App::new().service(propose).service(accept)).await;
+        let node = new_node(&[Role::Acceptor]);
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(accept),
+        )
+        .await;
         let req = test::TestRequest::post()
             .uri("/propose")
-            .set_payload(1483472389.to_string())
+            .set_payload(prepare(Ballot::new(148347238, 9), 0))
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert!(node.decided_values.read().unwrap().is_empty());
 
         let mut server = mockito::Server::new();
-        if let Ok(mut paxos_acceptor_nodes) = PAXOS_ACCEPTOR_NODES.write() {
-            paxos_acceptor_nodes.push(server.url());
+        if let Ok(mut paxos_learner_nodes) = node.learner_nodes.write() {
+            paxos_learner_nodes.push(server.url());
         }
         let mock_update_value = server
             .mock("POST", "/update_value")
@@ -240,32 +587,39 @@ mod tests {
             .create();
         let req = test::TestRequest::post()
             .uri("/accept")
-            .set_payload(serde_json::to_string(&Accept::new(1483472389, "value")).unwrap())
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accept::new(0, Ballot::new(148347238, 9), "value"))
+                    .unwrap(),
+            )
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::ACCEPTED);
         mock_update_value.assert();
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert!(node.decided_values.read().unwrap().is_empty());
     }
 
     #[actix_web::test]
     async fn should_not_accept_with_higher_promise() {
-        reset_values();
-        if let Ok(mut node_role) = NODE_ROLE.write() {
-            *node_role = Role::Acceptor;
-        }
-        let app = test::init_service(App::new().service(propose).service(accept)).await;
+        let node = new_node(&[Role::Acceptor]);
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(accept),
+        )
+        .await;
         let req = test::TestRequest::post()
             .uri("/propose")
-            .set_payload(1483472389.to_string())
+            .set_payload(prepare(Ballot::new(148347238, 9), 0))
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert!(node.decided_values.read().unwrap().is_empty());
 
         let mut server = mockito::Server::new();
-        if let Ok(mut paxos_acceptor_nodes) = PAXOS_ACCEPTOR_NODES.write() {
-            paxos_acceptor_nodes.push(server.url());
+        if let Ok(mut paxos_learner_nodes) = node.learner_nodes.write() {
+            paxos_learner_nodes.push(server.url());
         }
         let mock_update_value = server
             .mock("POST", "/update_value")
@@ -273,11 +627,287 @@ mod tests {
             .create();
         let req = test::TestRequest::post()
             .uri("/accept")
-            .set_payload(serde_json::to_string(&Accept::new(1483472388, "value")).unwrap())
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accept::new(0, Ballot::new(148347238, 8), "value"))
+                    .unwrap(),
+            )
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
         mock_update_value.expect_at_most(0);
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert!(node.decided_values.read().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn should_answer_in_the_encoding_of_the_request() {
+        let node = new_node(&[Role::Acceptor]);
+        let app = test::init_service(App::new().app_data(node.clone()).service(propose)).await;
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .insert_header(("content-type", "application/x-bincode"))
+            .set_payload(
+                Encoding::Bincode
+                    .encode(&Prepare::new(Ballot::new(1, 1), 0))
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let promise: Promise = Encoding::Bincode
+            .decode(&test::read_body(resp).await)
+            .unwrap();
+        assert_eq!(promise, Promise::default());
+
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload("12")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let error: ErrorBody = test::read_body_json(resp).await;
+        assert!(error.error.starts_with("Message not valid"));
+
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .insert_header(("content-type", "text/plain"))
+            .set_payload(prepare(Ballot::new(2, 1), 0))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[actix_web::test]
+    async fn should_not_promise_or_accept_without_acceptor_role() {
+        let node = new_node(&[Role::Proposer, Role::Learner]);
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(accept),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload(prepare(Ballot::new(1, 1), 0))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let req = test::TestRequest::post()
+            .uri("/accept")
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accept::new(0, Ballot::new(1, 1), "value"))
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(node.accepted.read().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn should_keep_promise_after_restart() {
+        let node = new_node(&[Role::Acceptor]);
+        let path = wal_path("acceptor_restart");
+        wal::open(&node, &path).unwrap();
+        let mut server = mockito::Server::new();
+        let mock_update_value = server
+            .mock("POST", "/update_value")
+            .with_status(StatusCode::OK.as_u16() as usize)
+            .create();
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(accept),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload(prepare(Ballot::new(2, 1), 0))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Crash between phase 1 and phase 2, everything in memory is lost. Without a lease the
+        // acceptor is not fenced after the restart
+        let node = new_node(&[Role::Acceptor]);
+        node.lease_ms.store(0, Ordering::Release);
+        if let Ok(mut paxos_learner_nodes) = node.learner_nodes.write() {
+            paxos_learner_nodes.push(server.url());
+        }
+        assert_eq!(wal::open(&node, &path).unwrap(), 1);
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(accept),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload(prepare(Ballot::new(1, 1), 0))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+        let req = test::TestRequest::post()
+            .uri("/accept")
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accept::new(0, Ballot::new(1, 1), "older value"))
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+        let req = test::TestRequest::post()
+            .uri("/accept")
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accept::new(0, Ballot::new(2, 1), "value"))
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        mock_update_value.assert();
+
+        // The accepted value survives a second restart as well
+        let node = new_node(&[Role::Acceptor]);
+        assert_eq!(wal::open(&node, &path).unwrap(), 2);
+        assert_eq!(
+            node.accepted.read().unwrap().get(&0),
+            Some(&(Ballot::new(2, 1), "value".to_owned()))
+        );
+        wal::close(&node);
+    }
+
+    #[actix_web::test]
+    async fn should_keep_fast_window_promise_after_restart() {
+        let node = new_node(&[Role::Acceptor]);
+        let path = wal_path("acceptor_any_restart");
+        wal::open(&node, &path).unwrap();
+        let window = Any {
+            ballot: Ballot::new(3, 1),
+            from: 0,
+            to: 2,
+        };
+        let app = test::init_service(App::new().app_data(node.clone()).service(any)).await;
+        let req = test::TestRequest::post()
+            .uri("/any")
+            .set_payload(Encoding::Json.encode(&window).unwrap())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // The promise survives the restart, the grant does not
+        let node = new_node(&[Role::Acceptor]);
+        node.lease_ms.store(0, Ordering::Release);
+        assert_eq!(wal::open(&node, &path).unwrap(), 1);
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(accept),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload(prepare(Ballot::new(2, 2), 0))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+        let req = test::TestRequest::post()
+            .uri("/accept")
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accept {
+                        fast: true,
+                        ..Accept::new(0, window.ballot, "value")
+                    })
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        wal::close(&node);
+    }
+
+    #[actix_web::test]
+    async fn should_refuse_leases_and_promises_for_a_lease_after_restart() {
+        let lease = Duration::from_millis(200);
+        let node = new_node(&[Role::Acceptor]);
+        let path = wal_path("acceptor_lease_restart");
+        wal::open(&node, &path).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(grant_lease),
+        )
+        .await;
+        let holder = Leader {
+            node_id: 1,
+            url: "http://holder".to_string(),
+        };
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload(prepare(Ballot::new(1, 1), 0))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = test::TestRequest::post()
+            .uri("/lease")
+            .set_payload(Encoding::Json.encode(&holder).unwrap())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // The grant is lost with the restart, the holder still counts on it until it expires
+        let node = new_node(&[Role::Acceptor]);
+        node.lease_ms
+            .store(lease.as_millis() as u64, Ordering::Release);
+        assert_eq!(wal::open(&node, &path).unwrap(), 1);
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(any)
+                .service(grant_lease),
+        )
+        .await;
+        let other = Leader {
+            node_id: 2,
+            url: "http://other".to_string(),
+        };
+        let window = Any {
+            ballot: Ballot::new(2, 2),
+            from: 0,
+            to: 2,
+        };
+        for (uri, payload) in [
+            ("/propose", prepare(Ballot::new(2, 2), 0)),
+            ("/any", Encoding::Json.encode(&window).unwrap()),
+            ("/lease", Encoding::Json.encode(&other).unwrap()),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_payload(payload)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE, "{}", uri);
+        }
+
+        actix_web::rt::time::sleep(lease).await;
+        let req = test::TestRequest::post()
+            .uri("/lease")
+            .set_payload(Encoding::Json.encode(&other).unwrap())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload(prepare(Ballot::new(2, 2), 0))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        wal::close(&node);
     }
 }
@@ -0,0 +1,234 @@
+use std::{collections::HashSet, sync::atomic::Ordering, time::Duration};
+
+use actix_web::{dev::ServerHandle, get, rt::signal, web, Error, HttpResponse};
+use futures::future::{join_all, select};
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use crate::{
+    learner::first_undecided_slot,
+    membership::latest_membership,
+    messages::{AcceptedEntry, ErrorBody},
+    quorum::quorums,
+    Ballot, PaxosNode, Role, CLIENT,
+};
+
+/// Answer of `GET /status`, what the node knows of the consensus.
+#[derive(Serialize)]
+pub struct NodeStatus {
+    node_id: u64,
+    roles: Vec<Role>,
+    promised_ballot: Ballot,
+    // Value accepted by this acceptor in the highest slot it accepted
+    accepted: Option<AcceptedEntry>,
+    // Ballot this node leads with, while it is the leader
+    leader_ballot: Option<Ballot>,
+    first_undecided_slot: u64,
+    stopping: bool,
+}
+
+#[get("/health")]
+async fn get_health() -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/ready")]
+async fn get_ready(node: web::Data<PaxosNode>) -> Result<HttpResponse, Error> {
+    match readiness(&node).await {
+        Ok(()) => Ok(HttpResponse::Ok().finish()),
+        Err(error) => {
+            node.log("Admin: Not ready", &error).await;
+            Ok(HttpResponse::ServiceUnavailable().json(ErrorBody { error }))
+        }
+    }
+}
+
+/// Whether the node can take requests: a proposer needs a phase 1 and a phase 2 quorum of the
+/// acceptors to answer `/health` within the request timeout, the other nodes are ready once
+/// they serve. A node shutting down is no longer ready.
+pub async fn readiness(node: &PaxosNode) -> Result<(), String> {
+    if node.stopping.load(Ordering::Acquire) {
+        return Err("Node shutting down".to_owned());
+    }
+    if !node.has_role(Role::Proposer) {
+        return Ok(());
+    }
+    let acceptors = latest_membership(node).acceptors;
+    let replies = join_all(acceptors.iter().map(|n| node.fetch(n, "/health"))).await;
+    let reachable = acceptors
+        .iter()
+        .zip(replies)
+        .filter(|(_, reply)| reply.as_ref().is_ok_and(|r| r.status == StatusCode::OK))
+        .map(|(acceptor, _)| acceptor)
+        .collect::<HashSet<_>>();
+    let quorums = quorums(node);
+    if quorums.is_phase1(&acceptors, &reachable) && quorums.is_phase2(&acceptors, &reachable) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Only {} of {} acceptors reachable",
+            reachable.len(),
+            acceptors.len()
+        ))
+    }
+}
+
+#[get("/status")]
+async fn get_status(node: web::Data<PaxosNode>) -> Result<HttpResponse, Error> {
+    let mut roles = node.roles.iter().copied().collect::<Vec<_>>();
+    roles.sort_unstable();
+    let accepted = node.accepted.read().map_or(None, |accepted| {
+        accepted
+            .iter()
+            .next_back()
+            .map(|(slot, (ballot, value))| AcceptedEntry {
+                slot: *slot,
+                ballot: *ballot,
+                value: value.clone(),
+            })
+    });
+    Ok(HttpResponse::Ok().json(NodeStatus {
+        node_id: node.node_id,
+        roles,
+        promised_ballot: node
+            .promised_ballot
+            .read()
+            .map_or(Ballot::default(), |b| *b),
+        accepted,
+        leader_ballot: node.leader_proposal.read().map_or(None, |l| *l),
+        first_undecided_slot: first_undecided_slot(&node),
+        stopping: node.stopping.load(Ordering::Acquire),
+    }))
+}
+
+/// Waits for Ctrl-C or `SIGTERM` (sent by `docker stop`), then the node is no longer ready, the
+/// server stops taking connections and lets the requests in flight, with the rounds they run,
+/// finish before it exits. Only Ctrl-C is waited for if `SIGTERM` cannot be listened to.
+pub async fn shutdown_on_signal(node: web::Data<PaxosNode>, server: ServerHandle) {
+    match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            select(Box::pin(signal::ctrl_c()), Box::pin(terminate.recv())).await;
+        }
+        Err(e) => {
+            node.log("Admin: SIGTERM not handled", &e.to_string()).await;
+            let _ = signal::ctrl_c().await;
+        }
+    }
+    node.stopping.store(true, Ordering::Release);
+    node.log("Admin: Shutting down", "").await;
+    server.stop(true).await;
+}
+
+/// Asks an endpoint of the server listening on the port of this host, fails unless it answers
+/// `200 OK`. The docker compose health checks run it, the image has no HTTP client.
+pub async fn probe(port: u16, path: &str) -> std::io::Result<()> {
+    let response = CLIENT
+        .get(format!("http://127.0.0.1:{}{}", port, path))
+        .timeout(Duration::from_secs(1))
+        .send()
+        .await
+        .map_err(std::io::Error::other)?;
+    if response.status() == StatusCode::OK {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "{} answered {}",
+            path,
+            response.status()
+        )))
+    }
+}
+
+/// How long the requests in flight are waited for on shutdown: a round is retried until the
+/// retry deadline, and a forwarded request waits one request timeout more.
+pub fn shutdown_timeout(node: &PaxosNode) -> Duration {
+    node.request_timeout() + Duration::from_millis(node.retry_deadline_ms.load(Ordering::Acquire))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        messages::{Accept, Envelope, Prepare},
+        tests::{new_node_with_id, start_node},
+    };
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn should_be_ready_with_acceptor_quorum() {
+        let acceptors = (2..=3)
+            .map(|node_id| new_node_with_id(node_id, &[Role::Acceptor]))
+            .collect::<Vec<_>>();
+        let servers = acceptors.iter().map(start_node).collect::<Vec<_>>();
+        let node = new_node_with_id(1, &[Role::Proposer]);
+        let server = start_node(&node);
+        node.acceptor_nodes.write().unwrap().extend(
+            servers
+                .iter()
+                .map(|server| format!("http://{}", server.addr())),
+        );
+        // Never listening
+        node.acceptor_nodes
+            .write()
+            .unwrap()
+            .push("http://127.0.0.1:1".to_owned());
+        let client = reqwest::Client::new();
+        let resp = client.get(server.url("/health")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client.get(server.url("/ready")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        // An acceptor is ready as soon as it serves
+        let resp = client.get(servers[0].url("/ready")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        drop(servers);
+        let resp = client.get(server.url("/ready")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: ErrorBody = resp.json().await.unwrap();
+        assert_eq!(body.error, "Only 0 of 3 acceptors reachable");
+    }
+
+    #[actix_web::test]
+    async fn should_dump_status() {
+        let node = new_node_with_id(1, &[Role::Acceptor, Role::Learner]);
+        let server = start_node(&node);
+        let client = reqwest::Client::new();
+        let ballot = Ballot::new(2, 3);
+        client
+            .post(server.url("/propose"))
+            .json(&Envelope::new(Prepare::new(ballot, 0)))
+            .send()
+            .await
+            .unwrap();
+        client
+            .post(server.url("/accept"))
+            .json(&Envelope::new(Accept::new(4, ballot, "value")))
+            .send()
+            .await
+            .unwrap();
+        node.stopping.store(true, Ordering::Release);
+
+        let status: serde_json::Value = client
+            .get(server.url("/status"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            status,
+            serde_json::json!({
+                "node_id": 1,
+                "roles": ["acceptor", "learner"],
+                "promised_ballot": { "round": 2, "node_id": 3 },
+                "accepted": { "slot": 4, "ballot": { "round": 2, "node_id": 3 }, "value": "value" },
+                "leader_ballot": null,
+                "first_undecided_slot": 0,
+                "stopping": true
+            })
+        );
+        let resp = client.get(server.url("/ready")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::VecDeque, sync::atomic::Ordering};
+
+use futures::channel::oneshot;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    membership,
+    proposer::{propose_value, ProposeError},
+    PaxosNode,
+};
+
+pub const DEFAULT_BATCH_SIZE: u64 = 16;
+pub const DEFAULT_PIPELINE: u64 = 3;
+
+/// Values decided together in one slot, stored as JSON (`{"Batch":[...]}`) in the value of
+/// the slot.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Batch {
+    #[serde(rename = "Batch")]
+    values: Vec<String>,
+}
+
+type Waiting = (String, oneshot::Sender<Result<(u64, usize), ProposeError>>);
+
+/// Values waiting for a slot and how many slots are being proposed.
+#[derive(Default)]
+pub struct Batcher {
+    queue: VecDeque<Waiting>,
+    in_flight: u64,
+}
+
+/// Frees the slot taken from the pipeline, even when the proposal is dropped.
+struct InFlight<'a>(&'a PaxosNode);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut batcher) = self.0.batcher.lock() {
+            batcher.in_flight -= 1;
+        }
+    }
+}
+
+/// Values decided in a slot, in the order they were submitted.
+pub fn values(value: &str) -> Vec<String> {
+    match serde_json::from_str::<Batch>(value) {
+        Ok(batch) => batch.values,
+        Err(_) => vec![value.to_owned()],
+    }
+}
+
+/// Value of a slot holding the values, a single value is used as it is unless it could be
+/// mistaken for a batch or a system entry.
+fn encode(mut values: Vec<String>) -> Result<String, ProposeError> {
+    if values.len() == 1
+        && serde_json::from_str::<Batch>(&values[0]).is_err()
+        && !membership::is_system(&values[0])
+    {
+        return Ok(values.remove(0));
+    }
+    serde_json::to_string(&Batch { values })
+        .map_err(|e| ProposeError::Storage(std::io::Error::other(e)))
+}
+
+/// Proposes the value, returns the slot it was decided in and its position in the slot. Up to
+/// `PAXOS_PIPELINE` slots are proposed at the same time, while they are all in flight the
+/// values wait and are proposed together, up to `PAXOS_BATCH_SIZE` per slot.
+pub async fn submit(node: &PaxosNode, value: &str) -> Result<(u64, usize), ProposeError> {
+    let (sender, receiver) = oneshot::channel();
+    match node.batcher.lock() {
+        Ok(mut batcher) => batcher.queue.push_back((value.to_owned(), sender)),
+        Err(_) => return Err(ProposeError::NotAccepted(None)),
+    }
+    // Every caller proposes the waiting values while a slot is free, the value of this caller
+    // can be in the batch of another one
+    while let Some((in_flight, batch)) = next_batch(node) {
+        let (values, senders): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+        let result = match encode(values) {
+            Ok(value) => propose_value(node, &value).await,
+            Err(e) => Err(e),
+        };
+        drop(in_flight);
+        for (index, sender) in senders.into_iter().enumerate() {
+            let _ = sender.send(match &result {
+                Ok(slot) => Ok((*slot, index)),
+                Err(ProposeError::NotAccepted(promised)) => {
+                    Err(ProposeError::NotAccepted(*promised))
+                }
+                Err(ProposeError::Storage(e)) => Err(ProposeError::Storage(std::io::Error::new(
+                    e.kind(),
+                    e.to_string(),
+                ))),
+            });
+        }
+    }
+    // Dropped when the proposal of the batch was dropped, the value may or may not be decided
+    receiver
+        .await
+        .unwrap_or(Err(ProposeError::NotAccepted(None)))
+}
+
+fn next_batch(node: &PaxosNode) -> Option<(InFlight<'_>, Vec<Waiting>)> {
+    let pipeline = node.pipeline.load(Ordering::Acquire).max(1);
+    let batch_size = node.batch_size.load(Ordering::Acquire).max(1) as usize;
+    let mut batcher = node.batcher.lock().ok()?;
+    if batcher.queue.is_empty() || batcher.in_flight >= pipeline {
+        return None;
+    }
+    batcher.in_flight += 1;
+    let size = batch_size.min(batcher.queue.len());
+    Some((InFlight(node), batcher.queue.drain(..size).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::join_all;
+
+    use crate::{
+        tests::{new_node, start_node},
+        Role,
+    };
+
+    use super::*;
+
+    #[test]
+    fn should_keep_single_values_unless_mistaken_for_batch() {
+        assert_eq!(encode(vec!["value".to_owned()]).unwrap(), "value");
+        let batch = encode(vec!["a".to_owned(), "b".to_owned()]).unwrap();
+        assert_eq!(batch, r#"{"Batch":["a","b"]}"#);
+        assert_eq!(values(&batch), ["a", "b"]);
+        let value = encode(vec![batch.clone()]).unwrap();
+        assert_ne!(value, batch);
+        assert_eq!(values(&value), [batch]);
+    }
+
+    #[actix_web::test]
+    async fn should_batch_values_while_pipeline_is_full() {
+        let node = new_node(&[Role::Proposer, Role::Acceptor, Role::Learner]);
+        let server = start_node(&node);
+        node.acceptor_nodes
+            .write()
+            .unwrap()
+            .push(format!("http://{}", server.addr()));
+        node.pipeline.store(1, Ordering::Release);
+        node.batch_size.store(3, Ordering::Release);
+
+        let submitted = (0..6).map(|i| format!("value {}", i)).collect::<Vec<_>>();
+        let positions = join_all(submitted.iter().map(|value| submit(&node, value))).await;
+        let positions = positions
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        // The first value is alone in flight, the others wait and fill the next slots
+        assert_eq!(positions, [(0, 0), (1, 0), (1, 1), (1, 2), (2, 0), (2, 1)]);
+        let decided_values = node.decided_values.read().unwrap();
+        assert_eq!(decided_values.len(), 3);
+        assert_eq!(values(&decided_values[&1]), submitted[1..4]);
+        assert_eq!(node.batcher.lock().unwrap().in_flight, 0);
+    }
+}
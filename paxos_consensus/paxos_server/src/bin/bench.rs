@@ -0,0 +1,67 @@
+use std::{collections::HashSet, time::Instant};
+
+use paxos_server::simulator::{Faults, Simulation};
+
+const NODES: usize = 3;
+const DEFAULT_COMMANDS: usize = 1000;
+const DEFAULT_PIPELINE: u64 = 3;
+const DEFAULT_BATCH_SIZES: [u64; 5] = [1, 4, 16, 64, 256];
+
+/// Measures the commits per second of an in-memory cluster for every batch size given as
+/// argument, e.g. `cargo run --release --bin bench -- 1 16 64`. `BENCH_COMMANDS` and
+/// `PAXOS_PIPELINE` change how many commands are submitted and how many slots are in flight.
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let batch_sizes = std::env::args()
+        .skip(1)
+        .map(|batch_size| {
+            batch_size
+                .parse::<u64>()
+                .expect("Batch size should be a number")
+        })
+        .collect::<Vec<_>>();
+    let batch_sizes = if batch_sizes.is_empty() {
+        DEFAULT_BATCH_SIZES.to_vec()
+    } else {
+        batch_sizes
+    };
+    let commands = std::env::var("BENCH_COMMANDS").map_or(DEFAULT_COMMANDS, |commands| {
+        commands
+            .parse::<usize>()
+            .expect("Commands should be a number")
+    });
+    let pipeline = std::env::var("PAXOS_PIPELINE").map_or(DEFAULT_PIPELINE, |pipeline| {
+        pipeline
+            .parse::<u64>()
+            .expect("Pipeline should be a number")
+    });
+
+    println!(
+        "{} nodes, {} commands, {} slots in flight",
+        NODES, commands, pipeline
+    );
+    println!(
+        "{:>10} {:>10} {:>10} {:>12}",
+        "batch size", "committed", "slots", "commits/s"
+    );
+    for batch_size in batch_sizes {
+        let mut simulation = Simulation::new(0, NODES, Faults::none())?;
+        simulation.set_batching(batch_size, pipeline);
+        let start = Instant::now();
+        for command in 0..commands {
+            simulation.submit(0, &format!("command {}", command));
+        }
+        let slots = simulation.settle().await;
+        let elapsed = start.elapsed();
+        let committed = slots.iter().flatten().count();
+        let used = slots.iter().flatten().collect::<HashSet<_>>().len();
+        println!(
+            "{:>10} {:>10} {:>10} {:>12.0}",
+            batch_size,
+            committed,
+            used,
+            committed as f64 / elapsed.as_secs_f64()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,308 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
+use actix_web::{get, http::StatusCode, web, Error, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::{
+    compaction::truncate,
+    kv::{self, StateMachine},
+    learner::{first_undecided_slot, learn},
+    membership::latest_membership,
+    messages::{Chosen, Decided, Encoding, Snapshot},
+    wal, PaxosNode, Role,
+};
+
+pub const DEFAULT_CATCH_UP_MS: u64 = 500;
+pub const DEFAULT_SNAPSHOT_GAP: u64 = 1000;
+/// Most values returned by a single `/decided` response.
+const MAX_DECIDED_BATCH: usize = 100;
+
+#[derive(Deserialize)]
+pub struct DecidedQuery {
+    pub from: Option<u64>,
+}
+
+/// Returns the values decided from the `from` slot, in the encoding of the request, so a
+/// learner that missed them can learn them.
+#[get("/decided")]
+async fn get_decided(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    query: web::Query<DecidedQuery>,
+) -> Result<HttpResponse, Error> {
+    match Encoding::of(&req) {
+        Ok(encoding) => Ok(serve_decided(
+            &node,
+            encoding,
+            query.from.unwrap_or_default(),
+        )),
+        Err(e) => Ok(e.into()),
+    }
+}
+
+pub fn serve_decided(node: &PaxosNode, encoding: Encoding, from: u64) -> HttpResponse {
+    if !node.has_role(Role::Learner) {
+        return HttpResponse::Forbidden().finish();
+    }
+    let log_start = node.log_start.load(Ordering::Acquire);
+    let Ok(decided_values) = node.decided_values.read() else {
+        return HttpResponse::InternalServerError().finish();
+    };
+    let entries = decided_values
+        .range(from.max(log_start)..)
+        .take(MAX_DECIDED_BATCH)
+        .map(|(slot, value)| Chosen {
+            slot: *slot,
+            value: value.clone(),
+        })
+        .collect();
+    drop(decided_values);
+    encoding.respond(
+        StatusCode::OK,
+        &Decided {
+            entries,
+            log_start,
+            first_undecided: first_undecided_slot(node),
+        },
+    )
+}
+
+/// Returns the applied state of the node, used by the learners too far behind to learn every
+/// value they missed.
+#[get("/snapshot")]
+async fn get_snapshot(node: web::Data<PaxosNode>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    match Encoding::of(&req) {
+        Ok(encoding) => Ok(serve_snapshot(&node, encoding)),
+        Err(e) => Ok(e.into()),
+    }
+}
+
+pub fn serve_snapshot(node: &PaxosNode, encoding: Encoding) -> HttpResponse {
+    if !node.has_role(Role::Learner) {
+        return HttpResponse::Forbidden().finish();
+    }
+    match snapshot(node) {
+        Some(snapshot) => encoding.respond(StatusCode::OK, &snapshot),
+        None => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// State of the node up to the last applied slot.
+pub fn snapshot(node: &PaxosNode) -> Option<Snapshot> {
+    let (slot, data, sessions) = node.state_machine.read().ok().map(|state_machine| {
+        (
+            state_machine.applied(),
+            state_machine.data().clone(),
+            state_machine.sessions().clone(),
+        )
+    })?;
+    let reconfigurations = node
+        .reconfigurations
+        .read()
+        .ok()?
+        .range(..slot)
+        .map(|(slot, reconfiguration)| (*slot, reconfiguration.clone()))
+        .collect();
+    Some(Snapshot {
+        slot,
+        data,
+        sessions,
+        reconfigurations,
+    })
+}
+
+/// Persists the snapshot and replaces the state of the node with it, returns false when the
+/// node already applied every slot the snapshot covers.
+pub fn install(node: &PaxosNode, snapshot: Snapshot) -> std::io::Result<bool> {
+    let applied = node
+        .state_machine
+        .read()
+        .map_or(0, |state_machine| state_machine.applied());
+    if snapshot.slot <= applied {
+        return Ok(false);
+    }
+    // The snapshot replaces the records of the slots it covers
+    wal::compact(node, &snapshot)?;
+    restore(node, snapshot);
+    Ok(true)
+}
+
+/// Replaces the state of the node with the snapshot if it is ahead, the state of the slots it
+/// covers is not kept.
+pub fn restore(node: &PaxosNode, snapshot: Snapshot) {
+    match node.state_machine.write() {
+        Ok(mut state_machine) if snapshot.slot > state_machine.applied() => {
+            *state_machine = StateMachine::restore(snapshot.slot, snapshot.data, snapshot.sessions);
+        }
+        _ => return,
+    }
+    truncate(node, snapshot.slot);
+    if let Ok(mut reconfigurations) = node.reconfigurations.write() {
+        reconfigurations.extend(snapshot.reconfigurations);
+    }
+    kv::apply_decided(node);
+}
+
+/// Pulls the values this learner missed from the other learners, every learner runs it in
+/// background so a node that was down converges without a new proposal.
+pub async fn run(node: web::Data<PaxosNode>) {
+    loop {
+        actix_web::rt::time::sleep(Duration::from_millis(
+            node.catch_up_ms.load(Ordering::Acquire),
+        ))
+        .await;
+        catch_up(&node).await;
+    }
+}
+
+/// Asks every other learner for the values decided after the first slot this node has not
+/// learned, a snapshot is installed instead when the gap is larger than the snapshot gap or the
+/// peer no longer has the values.
+pub async fn catch_up(node: &PaxosNode) {
+    let node_url = node
+        .node_url
+        .read()
+        .map_or(String::new(), |url| url.clone());
+    let peers = latest_membership(node)
+        .learners
+        .into_iter()
+        .filter(|learner| *learner != node_url);
+    for peer in peers {
+        loop {
+            let from = first_undecided_slot(node);
+            let decided = match node.fetch(&peer, &format!("/decided?from={}", from)).await {
+                Ok(reply) if reply.status == reqwest::StatusCode::OK => reply.decode::<Decided>(),
+                _ => break,
+            };
+            let Ok(decided) = decided else {
+                break;
+            };
+            let gap = decided.first_undecided.saturating_sub(from);
+            if decided.log_start > from || gap > node.snapshot_gap.load(Ordering::Acquire) {
+                if let Err(e) = install_from(node, &peer).await {
+                    node.log("Learner: Snapshot not installed", &e.to_string())
+                        .await;
+                    break;
+                }
+            } else {
+                for chosen in decided.entries {
+                    if let Err(e) = learn(node, chosen.slot, &chosen.value) {
+                        node.log("Learner: Value not persisted", &e.to_string())
+                            .await;
+                        return;
+                    }
+                }
+            }
+            // Stops once the peer has nothing more to give
+            if first_undecided_slot(node) <= from {
+                break;
+            }
+        }
+    }
+}
+
+async fn install_from(node: &PaxosNode, peer: &str) -> std::io::Result<()> {
+    let reply = node.fetch(peer, "/snapshot").await?;
+    if reply.status != reqwest::StatusCode::OK {
+        return Err(std::io::Error::other(format!(
+            "snapshot not available: {}",
+            reply.status
+        )));
+    }
+    let snapshot = reply
+        .decode::<Snapshot>()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let slot = snapshot.slot;
+    if install(node, snapshot)? {
+        node.log("Learner: Snapshot installed", &slot.to_string())
+            .await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App};
+
+    use crate::{
+        kv::Command,
+        tests::{new_node, start_node},
+    };
+
+    use super::*;
+
+    fn put(key: &str, value: &str) -> String {
+        serde_json::to_string(&Command::Put {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+        .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn should_return_decided_values_from_slot() {
+        let node = new_node(&[Role::Learner]);
+        for (slot, value) in ["a", "b", "c"].iter().enumerate() {
+            learn(&node, slot as u64, value).unwrap();
+        }
+        learn(&node, 5, "f").unwrap();
+        let app = test::init_service(App::new().app_data(node.clone()).service(get_decided)).await;
+        let req = test::TestRequest::get()
+            .uri("/decided?from=1")
+            .insert_header(("content-type", Encoding::Bincode.content_type()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = test::read_body(resp).await;
+        let decided: Decided = Encoding::Bincode.decode(&body).unwrap();
+        assert_eq!(
+            decided.entries.iter().map(|c| c.slot).collect::<Vec<_>>(),
+            [1, 2, 5]
+        );
+        assert_eq!(decided.log_start, 0);
+        assert_eq!(decided.first_undecided, 3);
+    }
+
+    #[actix_web::test]
+    async fn should_install_snapshot_when_far_behind() {
+        let peer = new_node(&[Role::Learner]);
+        let server = start_node(&peer);
+        for slot in 0..5 {
+            learn(&peer, slot, &put(&format!("key {}", slot), "value")).unwrap();
+        }
+        let node = new_node(&[Role::Learner]);
+        node.learner_nodes
+            .write()
+            .unwrap()
+            .push(format!("http://{}", server.addr()));
+        node.snapshot_gap.store(2, Ordering::Release);
+
+        catch_up(&node).await;
+        assert_eq!(node.log_start.load(Ordering::Acquire), 5);
+        assert!(node.decided_values.read().unwrap().is_empty());
+        assert_eq!(first_undecided_slot(&node), 5);
+        {
+            let state_machine = node.state_machine.read().unwrap();
+            assert_eq!(state_machine.applied(), 5);
+            assert_eq!(
+                state_machine.data(),
+                peer.state_machine.read().unwrap().data()
+            );
+        }
+
+        // A small gap is filled with the decided values
+        learn(&peer, 5, &put("key 0", "new value")).unwrap();
+        catch_up(&node).await;
+        assert_eq!(
+            node.decided_values.read().unwrap().get(&5),
+            Some(&put("key 0", "new value"))
+        );
+        assert_eq!(
+            node.state_machine.read().unwrap().get("key 0"),
+            Some(&"new value".to_owned())
+        );
+        // Values of the slots covered by the snapshot are not learned again
+        learn(&node, 1, "old").unwrap();
+        assert!(!node.decided_values.read().unwrap().contains_key(&1));
+    }
+}
@@ -0,0 +1,341 @@
+use std::{fmt, time::Duration};
+
+use futures::{future::join_all, lock::Mutex};
+use reqwest::{header::CONTENT_TYPE, Method, StatusCode};
+use serde::de::DeserializeOwned;
+
+pub use crate::kv::{Command, CommandResult};
+use crate::{
+    messages::{Accept, Encoding, FastWindow},
+    session::{ClientRequest, CLIENT_ID_HEADER, SEQUENCE_HEADER},
+};
+
+const JSON: &str = "application/json";
+const TEXT: &str = "text/plain";
+const DEFAULT_ATTEMPTS: u32 = 5;
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(50);
+// The leader keeps retrying a value up to its retry deadline
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reasons a request of the client failed.
+#[derive(Debug)]
+pub enum ClientError {
+    /// Every attempt failed, with the error of the last one
+    Unavailable(String),
+    /// The request was refused, retrying it would not help
+    Refused(StatusCode, String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable(e) => write!(f, "Cluster not available: {}", e),
+            Self::Refused(status, body) => write!(f, "Request refused ({}): {}", status, body),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Client of a Paxos cluster. Every write carries the id of the client and a sequence number,
+/// the nodes decide it at most once, so a write that timed out or failed on one node is retried
+/// on the next one without being applied twice. Writes are sent one at a time, the next
+/// sequence number is used only once the previous write got its answer.
+pub struct Client {
+    http: reqwest::Client,
+    nodes: Vec<String>,
+    client_id: String,
+    // Last sequence number used, held while a write is in progress
+    sequence: Mutex<u64>,
+    // Fast window the values are sent in, `from` is the next slot this client takes
+    fast_window: Mutex<Option<FastWindow>>,
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl Client {
+    /// Client sending its requests to `nodes` (e.g. `http://localhost:8080`), in turn when one
+    /// fails, with a random client id.
+    pub fn new(nodes: Vec<String>) -> Self {
+        Self::with_client_id(nodes, format!("{:016x}", rand::random::<u64>()))
+    }
+
+    /// Client using the given id, a client restarted with the same id has to start from a
+    /// higher sequence number than the last one it used (see `with_sequence`).
+    pub fn with_client_id(nodes: Vec<String>, client_id: String) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(DEFAULT_TIMEOUT)
+                .build()
+                .expect("HTTP client should be built"),
+            nodes,
+            client_id,
+            sequence: Mutex::new(0),
+            fast_window: Mutex::new(None),
+            attempts: DEFAULT_ATTEMPTS,
+            backoff: DEFAULT_BACKOFF,
+        }
+    }
+
+    /// Last sequence number already used by this client id.
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = Mutex::new(sequence);
+        self
+    }
+
+    /// How many times a request is sent before giving up, and the first backoff between two
+    /// attempts, doubled after each one.
+    pub fn with_retries(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Decides the value with `POST /consensus`, returns the answer of the node.
+    pub async fn propose(&self, value: &str) -> Result<String, ClientError> {
+        let body = self
+            .write("/consensus", TEXT, value.as_bytes().to_vec())
+            .await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Sends the value straight to the acceptors in a fast window opened by the leader, one
+    /// round trip instead of two while no other client takes the same slot. After a collision,
+    /// or when Fast Paxos is disabled, the value goes through `POST /consensus` with the same
+    /// sequence number, so it is decided once even if the collision recovery chose it.
+    pub async fn propose_fast(&self, value: &str) -> Result<String, ClientError> {
+        let mut sequence = self.sequence.lock().await;
+        *sequence += 1;
+        if let Some(slot) = self.accept_fast(*sequence, value).await {
+            return Ok(format!("Value {value} accepted in slot {slot}!"));
+        }
+        let body = self
+            .send(
+                Method::POST,
+                "/consensus",
+                Some(*sequence),
+                TEXT,
+                value.as_bytes().to_vec(),
+            )
+            .await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Slot the request was chosen in by a fast quorum, a new window is asked for when the
+    /// slots of the current one are used.
+    async fn accept_fast(&self, sequence: u64, value: &str) -> Option<u64> {
+        let mut fast_window = self.fast_window.lock().await;
+        if fast_window
+            .as_ref()
+            .is_none_or(|window| window.from >= window.to)
+        {
+            let body = self
+                .send(Method::POST, "/fast", None, TEXT, Vec::new())
+                .await
+                .ok()?;
+            *fast_window = Some(decode(&body).ok()?);
+        }
+        let window = fast_window.as_mut()?;
+        let slot = window.from;
+        window.from += 1;
+        let request = ClientRequest {
+            client: self.client_id.clone(),
+            sequence,
+            value: value.to_owned(),
+        }
+        .encode()
+        .ok()?;
+        let body = Encoding::Json
+            .encode(&Accept {
+                fast: true,
+                ..Accept::new(slot, window.ballot, &request)
+            })
+            .ok()?;
+        let replies = join_all(window.acceptors.iter().map(|acceptor| {
+            self.http
+                .post(format!("{}/accept", acceptor))
+                .header(CONTENT_TYPE, Encoding::Json.content_type())
+                .body(body.clone())
+                .send()
+        }))
+        .await;
+        let accepted = replies
+            .iter()
+            .filter(|reply| {
+                reply
+                    .as_ref()
+                    .is_ok_and(|reply| reply.status() == StatusCode::ACCEPTED)
+            })
+            .count();
+        if accepted >= window.fast_quorum {
+            Some(slot)
+        } else {
+            // Another client took the slot or the leader closed the window
+            *fast_window = None;
+            None
+        }
+    }
+
+    pub async fn put(&self, key: &str, value: &str) -> Result<CommandResult, ClientError> {
+        self.command(Command::Put {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<CommandResult, ClientError> {
+        self.command(Command::Delete {
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    /// Applies the command through `POST /kv`, returns its result.
+    pub async fn command(&self, command: Command) -> Result<CommandResult, ClientError> {
+        let body = serde_json::to_vec(&command)
+            .map_err(|e| ClientError::Refused(StatusCode::BAD_REQUEST, e.to_string()))?;
+        let result = self.write("/kv", JSON, body).await?;
+        decode(&result)
+    }
+
+    /// Linearizable read of the key, `None` when it is not set.
+    pub async fn get(&self, key: &str) -> Result<Option<String>, ClientError> {
+        match self
+            .send(
+                Method::GET,
+                &format!("/kv/{}?read=log", key),
+                None,
+                TEXT,
+                Vec::new(),
+            )
+            .await
+        {
+            Ok(body) => Ok(Some(String::from_utf8_lossy(&body).into_owned())),
+            Err(ClientError::Refused(StatusCode::NOT_FOUND, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn write(
+        &self,
+        path: &str,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<Vec<u8>, ClientError> {
+        let mut sequence = self.sequence.lock().await;
+        *sequence += 1;
+        self.send(Method::POST, path, Some(*sequence), content_type, body)
+            .await
+    }
+
+    /// Sends the request to the nodes in turn until one answers, a write keeps its sequence
+    /// number in every attempt. A write decided but not applied yet by the node (`202
+    /// Accepted`) is sent again to get its result.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        sequence: Option<u64>,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<Vec<u8>, ClientError> {
+        let mut backoff = self.backoff;
+        let mut last_error = String::from("no node configured");
+        for attempt in 0..self.attempts {
+            if attempt > 0 {
+                actix_web::rt::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            let Some(node) = self.nodes.get(attempt as usize % self.nodes.len().max(1)) else {
+                break;
+            };
+            let mut request = self
+                .http
+                .request(method.clone(), format!("{}{}", node, path))
+                .header(CONTENT_TYPE, content_type)
+                .body(body.clone());
+            if let Some(sequence) = sequence {
+                request = request
+                    .header(CLIENT_ID_HEADER, &self.client_id)
+                    .header(SEQUENCE_HEADER, sequence);
+            }
+            let response = match request.send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    last_error = e.to_string();
+                    continue;
+                }
+            };
+            let status = response.status();
+            let body = match response.bytes().await {
+                Ok(body) => body.to_vec(),
+                Err(e) => {
+                    last_error = e.to_string();
+                    continue;
+                }
+            };
+            if status == StatusCode::OK {
+                return Ok(body);
+            }
+            last_error = format!("{}: {}", status, String::from_utf8_lossy(&body));
+            let retry = status.is_server_error()
+                || status == StatusCode::ACCEPTED
+                || status == StatusCode::NOT_ACCEPTABLE;
+            if !retry {
+                return Err(ClientError::Refused(
+                    status,
+                    String::from_utf8_lossy(&body).into_owned(),
+                ));
+            }
+        }
+        Err(ClientError::Unavailable(last_error))
+    }
+}
+
+fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, ClientError> {
+    serde_json::from_slice(body)
+        .map_err(|e| ClientError::Unavailable(format!("Answer not valid: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{new_node, start_node},
+        Role,
+    };
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn should_retry_on_next_node() {
+        let node = new_node(&[Role::Proposer, Role::Acceptor, Role::Learner]);
+        let server = start_node(&node);
+        let url = format!("http://{}", server.addr());
+        node.acceptor_nodes.write().unwrap().push(url.clone());
+        let client = Client::new(vec!["http://127.0.0.1:1".to_owned(), url])
+            .with_retries(3, Duration::from_millis(1));
+
+        let result = client.put("key", "value").await.unwrap();
+        assert!(result.success);
+        assert_eq!(client.get("key").await.unwrap(), Some("value".to_owned()));
+        assert_eq!(client.get("missing").await.unwrap(), None);
+        // Both reads went through the log, in slots 1 and 2
+        assert_eq!(
+            client.propose("value").await.unwrap(),
+            "Value value accepted in slot 3!"
+        );
+        assert_eq!(
+            node.state_machine
+                .read()
+                .unwrap()
+                .session(client.client_id())
+                .map(|session| session.sequence),
+            Some(2)
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use std::sync::atomic::Ordering;
+
+use actix_web::{post, web, Error, HttpRequest, HttpResponse};
+use futures_util::StreamExt as _;
+use reqwest::StatusCode;
+
+use crate::{
+    catch_up::{install, snapshot},
+    membership::latest_membership,
+    messages::{Decided, Encoding, Snapshot},
+    wal, PaxosNode, Role,
+};
+
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1000;
+pub const DEFAULT_COMPACTION_MS: u64 = 1000;
+
+/// Installs the snapshot sent by a peer that truncated the slots this learner is missing.
+#[post("/snapshot")]
+async fn install_snapshot(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    mut value: web::Payload,
+) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Learner) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let mut bytes = web::BytesMut::new();
+    while let Some(item) = value.next().await {
+        bytes.extend_from_slice(&item?);
+    }
+    let snapshot = match Encoding::of(&req).and_then(|encoding| encoding.decode::<Snapshot>(&bytes))
+    {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            node.log("Learner: Snapshot not valid", &e.to_string())
+                .await;
+            return Ok(e.into());
+        }
+    };
+    let slot = snapshot.slot;
+    match install(&node, snapshot) {
+        Ok(installed) => {
+            if installed {
+                node.log("Learner: Snapshot installed", &slot.to_string())
+                    .await;
+            }
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(e) => {
+            node.log("Learner: Snapshot not persisted", &e.to_string())
+                .await;
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Forgets the state of the slots before `slot`, they are covered by a snapshot. The acceptor
+/// refuses the proposals for them from now on as it no longer knows what it accepted.
+pub fn truncate(node: &PaxosNode, slot: u64) {
+    if let Ok(mut accepted) = node.accepted.write() {
+        // Raised under the lock so no promise or accept sees the truncated slots
+        node.log_start.fetch_max(slot, Ordering::AcqRel);
+        *accepted = accepted.split_off(&slot);
+    }
+    if let Ok(mut decided_values) = node.decided_values.write() {
+        *decided_values = decided_values.split_off(&slot);
+    }
+    if let Ok(mut acceptances) = node.acceptances.write() {
+        *acceptances = acceptances.split_off(&slot);
+    }
+    if let Ok(mut state_machine) = node.state_machine.write() {
+        state_machine.truncate(slot);
+    }
+}
+
+/// Takes a snapshot of the applied state once `PAXOS_SNAPSHOT_INTERVAL` slots were applied
+/// since the previous one, the write-ahead log and the state of the slots before it are
+/// truncated. Returns the slot of the snapshot if one was taken.
+pub async fn compact(node: &PaxosNode) -> std::io::Result<Option<u64>> {
+    let interval = node.snapshot_interval.load(Ordering::Acquire);
+    let Some(snapshot) = snapshot(node) else {
+        return Err(std::io::Error::other("state not available"));
+    };
+    if interval == 0 || snapshot.slot < node.log_start.load(Ordering::Acquire) + interval {
+        return Ok(None);
+    }
+    wal::compact(node, &snapshot)?;
+    truncate(node, snapshot.slot);
+    node.log("Learner: Log compacted", &snapshot.slot.to_string())
+        .await;
+    send_to_lagging(node, &snapshot).await;
+    Ok(Some(snapshot.slot))
+}
+
+/// Sends the snapshot to the learners that did not learn every slot it covers, they can no
+/// longer get the truncated values from this node.
+async fn send_to_lagging(node: &PaxosNode, snapshot: &Snapshot) {
+    let node_url = node
+        .node_url
+        .read()
+        .map_or(String::new(), |url| url.clone());
+    let peers = latest_membership(node)
+        .learners
+        .into_iter()
+        .filter(|learner| *learner != node_url);
+    for peer in peers {
+        // No value is returned from the last slot, only where the peer is
+        let decided = match node
+            .fetch(&peer, &format!("/decided?from={}", u64::MAX))
+            .await
+        {
+            Ok(reply) if reply.status == StatusCode::OK => reply.decode::<Decided>().ok(),
+            _ => None,
+        };
+        if decided.is_some_and(|decided| decided.first_undecided < snapshot.slot) {
+            match node.send(&peer, "/snapshot", snapshot).await {
+                Ok(reply) if reply.status == StatusCode::OK => {
+                    node.log("Learner: Snapshot sent", &peer).await
+                }
+                Ok(reply) => {
+                    node.log(
+                        "Learner: Snapshot sent with errors",
+                        &reply.status.to_string(),
+                    )
+                    .await
+                }
+                Err(e) => node.log("Learner: Error", &e.to_string()).await,
+            }
+        }
+    }
+}
+
+/// Compacts the log of the node in background, every learner runs it.
+pub async fn run(node: web::Data<PaxosNode>) {
+    loop {
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(
+            node.compaction_ms.load(Ordering::Acquire),
+        ))
+        .await;
+        if let Err(e) = compact(&node).await {
+            node.log("Learner: Log not compacted", &e.to_string()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App};
+
+    use crate::{
+        acceptor::{accept, propose, receive_accept},
+        kv::Command,
+        learner::learn,
+        messages::{Accept, Prepare},
+        proposer::propose_value,
+        tests::{new_node, start_node},
+        wal::{tests::wal_path, Record},
+        Ballot,
+    };
+
+    use super::*;
+
+    fn put(key: &str, value: &str) -> String {
+        serde_json::to_string(&Command::Put {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+        .unwrap()
+    }
+
+    /// Accepts and learns the value, as a node with both roles would.
+    async fn decide(node: &PaxosNode, slot: u64, value: &str) {
+        let body = Encoding::Json
+            .encode(&Accept::new(slot, Ballot::new(1, 1), value))
+            .unwrap();
+        receive_accept(node, Encoding::Json, &body).await;
+        learn(node, slot, value).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn should_restore_same_state_from_compacted_log() {
+        let node = new_node(&[Role::Acceptor, Role::Learner]);
+        let path = wal_path("compaction");
+        wal::open(&node, &path).unwrap();
+        node.snapshot_interval.store(4, Ordering::Release);
+        for slot in 0..3 {
+            decide(&node, slot, &put("key", &slot.to_string())).await;
+        }
+        assert_eq!(compact(&node).await.unwrap(), None);
+        for slot in 3..6 {
+            decide(&node, slot, &put(&format!("key {}", slot), "value")).await;
+        }
+        assert_eq!(compact(&node).await.unwrap(), Some(6));
+        assert!(node.decided_values.read().unwrap().is_empty());
+        assert!(node.accepted.read().unwrap().is_empty());
+        let (records, _) = wal::read(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], Record::Promise(Ballot::new(1, 1)));
+        assert!(matches!(records[1], Record::Snapshot(_)));
+        decide(&node, 6, &put("key", "new value")).await;
+
+        let restored = new_node(&[Role::Acceptor, Role::Learner]);
+        wal::open(&restored, &path).unwrap();
+        assert_eq!(restored.log_start.load(Ordering::Acquire), 6);
+        assert_eq!(
+            *restored.decided_values.read().unwrap(),
+            *node.decided_values.read().unwrap()
+        );
+        assert_eq!(
+            *restored.accepted.read().unwrap(),
+            *node.accepted.read().unwrap()
+        );
+        let (state_machine, restored_state_machine) = (
+            node.state_machine.read().unwrap(),
+            restored.state_machine.read().unwrap(),
+        );
+        assert_eq!(restored_state_machine.applied(), 7);
+        assert_eq!(restored_state_machine.data(), state_machine.data());
+        assert_eq!(
+            restored_state_machine.get("key"),
+            Some(&"new value".to_owned())
+        );
+        wal::close(&node);
+        wal::close(&restored);
+    }
+
+    #[actix_web::test]
+    async fn should_refuse_proposals_for_compacted_slots() {
+        let node = new_node(&[Role::Acceptor, Role::Learner]);
+        node.snapshot_interval.store(2, Ordering::Release);
+        for slot in 0..2 {
+            decide(&node, slot, "value").await;
+        }
+        compact(&node).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(propose)
+                .service(accept),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload(
+                Encoding::Json
+                    .encode(&Prepare::new(Ballot::new(2, 1), 1))
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::GONE);
+        let req = test::TestRequest::post()
+            .uri("/accept")
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accept::new(0, Ballot::new(2, 1), "other value"))
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::GONE);
+        assert!(node.accepted.read().unwrap().is_empty());
+
+        let req = test::TestRequest::post()
+            .uri("/propose")
+            .set_payload(
+                Encoding::Json
+                    .encode(&Prepare::new(Ballot::new(2, 1), 2))
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn should_send_snapshot_to_learners_behind_truncation() {
+        let peer = new_node(&[Role::Learner]);
+        let server = start_node(&peer);
+        learn(&peer, 0, &put("key", "old value")).unwrap();
+        let node = new_node(&[Role::Learner]);
+        node.learner_nodes
+            .write()
+            .unwrap()
+            .push(format!("http://{}", server.addr()));
+        node.snapshot_interval.store(3, Ordering::Release);
+        for slot in 0..3 {
+            learn(&node, slot, &put("key", &slot.to_string())).unwrap();
+        }
+
+        assert_eq!(compact(&node).await.unwrap(), Some(3));
+        assert_eq!(peer.log_start.load(Ordering::Acquire), 3);
+        assert!(peer.decided_values.read().unwrap().is_empty());
+        let state_machine = peer.state_machine.read().unwrap();
+        assert_eq!(state_machine.applied(), 3);
+        assert_eq!(state_machine.get("key"), Some(&"2".to_owned()));
+    }
+
+    #[actix_web::test]
+    async fn should_catch_up_before_leading_from_compacted_slot() {
+        let acceptor = new_node(&[Role::Acceptor, Role::Learner]);
+        let server = start_node(&acceptor);
+        let url = format!("http://{}", server.addr());
+        acceptor.snapshot_interval.store(2, Ordering::Release);
+        for slot in 0..2 {
+            decide(&acceptor, slot, &put("key", &slot.to_string())).await;
+        }
+        assert_eq!(compact(&acceptor).await.unwrap(), Some(2));
+        // A proposer that learned nothing yet starts phase 1 from a truncated slot
+        let node = new_node(&[Role::Proposer, Role::Learner]);
+        node.acceptor_nodes.write().unwrap().push(url.clone());
+        node.learner_nodes.write().unwrap().push(url);
+
+        assert_eq!(propose_value(&node, &put("key", "value")).await.unwrap(), 2);
+        assert_eq!(node.log_start.load(Ordering::Acquire), 2);
+        assert_eq!(
+            node.state_machine.read().unwrap().get("key"),
+            Some(&"value".to_owned())
+        );
+    }
+}
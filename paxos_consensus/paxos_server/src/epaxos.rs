@@ -0,0 +1,732 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::atomic::Ordering,
+    time::Duration,
+};
+
+use actix_web::{get, post, rt::time::Instant, web, Error, HttpRequest, HttpResponse};
+use futures::future::join_all;
+use futures_util::StreamExt as _;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    kv::{self, Command},
+    membership::latest_membership,
+    messages::{Attributes, Encoding, Instance, InstanceId},
+    proposer::ProposeError,
+    quorum::Quorums,
+    wal::{self, Record},
+    PaxosNode, Role,
+};
+
+const EXECUTION_POLL: Duration = Duration::from_millis(1);
+
+/// Progress of an instance on a replica, an instance never goes back to a previous one.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Status {
+    PreAccepted,
+    Accepted,
+    Committed,
+    Executed,
+}
+
+/// Outcome of a command once executed, `value` is the value of the key before the command.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub instance: InstanceId,
+    pub success: bool,
+    pub value: Option<String>,
+}
+
+type Instances = BTreeMap<InstanceId, (Instance, Status)>;
+
+/// Egalitarian Paxos state of a replica, the instances it knows and the key-value store built
+/// executing the committed ones.
+#[derive(Default)]
+pub struct Replica {
+    // Next instance led by this node
+    next_instance: u64,
+    instances: Instances,
+    // Instances of every key, to find the interfering ones
+    keys: HashMap<String, BTreeSet<InstanceId>>,
+    // Instances in the order they were executed
+    executed: Vec<InstanceId>,
+    data: HashMap<String, String>,
+    // Outcomes of the instances led by this node, until the client gets them
+    outcomes: HashMap<InstanceId, Outcome>,
+    // Instances led by this node whose client was answered before they were executed, their
+    // outcome is not kept
+    uncollected: HashSet<InstanceId>,
+}
+
+impl Replica {
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.data.get(key)
+    }
+
+    /// The instance with the attributes it has on this replica: it depends on every interfering
+    /// instance known and its sequence number is higher than theirs.
+    fn pre_accept(&self, mut instance: Instance) -> Instance {
+        let interfering = self
+            .keys
+            .get(instance.command.key())
+            .into_iter()
+            .flatten()
+            .filter(|id| **id != instance.id)
+            .filter_map(|id| self.instances.get(id).map(|entry| (id, entry)));
+        for (id, (other, _)) in interfering {
+            if other.command.interferes(&instance.command) {
+                instance.deps.insert(*id);
+                instance.seq = instance.seq.max(other.seq + 1);
+            }
+        }
+        instance
+    }
+
+    /// Keeps the instance unless this replica already knows it in a later status, once
+    /// committed the attributes of an instance never change.
+    fn record(&mut self, instance: Instance, status: Status) -> bool {
+        if let Some((_, known)) = self.instances.get(&instance.id) {
+            if *known > status || *known >= Status::Committed {
+                return false;
+            }
+        }
+        self.keys
+            .entry(instance.command.key().to_owned())
+            .or_default()
+            .insert(instance.id);
+        self.instances.insert(instance.id, (instance, status));
+        true
+    }
+
+    fn attributes(&self, id: &InstanceId) -> Attributes {
+        self.instances
+            .get(id)
+            .map_or(Attributes::default(), |(instance, _)| Attributes {
+                seq: instance.seq,
+                deps: instance.deps.clone(),
+            })
+    }
+
+    /// Executes every committed instance whose dependencies are all committed. The instances
+    /// not executed yet are split in strongly connected components, executed dependencies
+    /// first and, inside a component, by sequence number and then by id, so every replica
+    /// executes the interfering commands in the same order.
+    fn execute(&mut self, node_id: u64) {
+        let committed = self
+            .instances
+            .iter()
+            .filter(|(_, (_, status))| *status == Status::Committed)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in committed {
+            if self.instances.get(&id).map(|(_, status)| *status) != Some(Status::Committed) {
+                continue;
+            }
+            let mut components = Components::default();
+            if components.visit(&self.instances, id).is_none() {
+                continue;
+            }
+            for mut component in components.components {
+                component.sort_by_key(|id| (self.instances.get(id).map(|(i, _)| i.seq), *id));
+                for id in component {
+                    self.apply(id, node_id);
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, id: InstanceId, node_id: u64) {
+        if let Some((instance, status)) = self.instances.get_mut(&id) {
+            *status = Status::Executed;
+            let (success, value) = kv::run(&mut self.data, instance.command.clone());
+            self.executed.push(id);
+            if id.replica == node_id && !self.uncollected.remove(&id) {
+                self.outcomes.insert(
+                    id,
+                    Outcome {
+                        instance: id,
+                        success,
+                        value,
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Tarjan's algorithm over the committed instances not executed yet, the components are found
+/// dependencies first.
+#[derive(Default)]
+struct Components {
+    indexes: HashMap<InstanceId, usize>,
+    stack: Vec<InstanceId>,
+    on_stack: HashSet<InstanceId>,
+    components: Vec<Vec<InstanceId>>,
+}
+
+impl Components {
+    /// Lowest index reachable from the instance, `None` when it depends on an instance not
+    /// committed yet: nothing can be executed before it is. Instances are not recovered, one
+    /// whose command leader stopped before committing it blocks the instances depending on it.
+    fn visit(&mut self, instances: &Instances, id: InstanceId) -> Option<usize> {
+        let index = self.indexes.len();
+        self.indexes.insert(id, index);
+        self.stack.push(id);
+        self.on_stack.insert(id);
+        let mut low = index;
+        let (instance, _) = instances.get(&id)?;
+        for dep in &instance.deps {
+            match instances.get(dep) {
+                // Executed with everything it depends on
+                Some((_, Status::Executed)) => continue,
+                Some((_, Status::Committed)) => {}
+                _ => return None,
+            }
+            match self.indexes.get(dep) {
+                None => low = low.min(self.visit(instances, *dep)?),
+                Some(dep_index) if self.on_stack.contains(dep) => low = low.min(*dep_index),
+                Some(_) => {}
+            }
+        }
+        if low == index {
+            let mut component = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(&member);
+                component.push(member);
+                if member == id {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+        Some(low)
+    }
+}
+
+/// Commits the command through Egalitarian Paxos with this node as command leader and returns
+/// its outcome once executed by the local replica.
+#[post("/epaxos/kv")]
+async fn submit_epaxos(
+    node: web::Data<PaxosNode>,
+    command: web::Json<Command>,
+) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Proposer) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let id = match commit(&node, command.into_inner()).await {
+        Ok(id) => id,
+        Err(e) => {
+            node.log("EPaxos: Command not committed", &format!("{:?}", e))
+                .await;
+            return Ok(e.into());
+        }
+    };
+    match outcome(&node, id).await {
+        Some(outcome) => Ok(HttpResponse::Ok().json(outcome)),
+        // Committed, executed once the instances it depends on are committed
+        None => Ok(HttpResponse::Accepted().json(id)),
+    }
+}
+
+/// Reads the key from the store of the local replica.
+#[get("/epaxos/kv/{key}")]
+async fn get_epaxos_key(
+    node: web::Data<PaxosNode>,
+    key: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Acceptor) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    match node.epaxos.read().map(|replica| replica.get(&key).cloned()) {
+        Ok(Some(value)) => Ok(HttpResponse::Ok().body(value)),
+        Ok(None) => Ok(HttpResponse::NotFound().finish()),
+        Err(_) => Ok(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+#[post("/epaxos/pre_accept")]
+async fn pre_accept(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse, Error> {
+    read_instance(node, req, payload, Status::PreAccepted).await
+}
+
+#[post("/epaxos/accept")]
+async fn accept_epaxos(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse, Error> {
+    read_instance(node, req, payload, Status::Accepted).await
+}
+
+#[post("/epaxos/commit")]
+async fn commit_epaxos(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse, Error> {
+    read_instance(node, req, payload, Status::Committed).await
+}
+
+async fn read_instance(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    mut payload: web::Payload,
+    status: Status,
+) -> Result<HttpResponse, Error> {
+    let mut bytes = web::BytesMut::new();
+    while let Some(item) = payload.next().await {
+        bytes.extend_from_slice(&item?);
+    }
+    match Encoding::of(&req) {
+        Ok(encoding) => Ok(receive_instance(&node, encoding, &bytes, status).await),
+        Err(e) => Ok(e.into()),
+    }
+}
+
+/// An EPaxos message on the replica side, `bytes` is an `Instance` in `encoding` and `status`
+/// tells the phase: a pre-accept is given the attributes of this replica, an accept and a
+/// commit are kept as they are. Answers with the attributes of the instance on the replica.
+pub async fn receive_instance(
+    node: &PaxosNode,
+    encoding: Encoding,
+    bytes: &[u8],
+    status: Status,
+) -> HttpResponse {
+    if !node.has_role(Role::Acceptor) {
+        return HttpResponse::Forbidden().finish();
+    }
+    let instance = match encoding.decode::<Instance>(bytes) {
+        Ok(instance) => instance,
+        Err(e) => {
+            node.log("EPaxos: Instance not valid", &e.to_string()).await;
+            return e.into();
+        }
+    };
+    let id = instance.id;
+    node.log(
+        "EPaxos: Instance received",
+        &format!("{:?} {}.{}", status, id.replica, id.instance),
+    )
+    .await;
+    let attributes = node.epaxos.write().map(|mut replica| {
+        let instance = match status {
+            Status::PreAccepted => replica.pre_accept(instance),
+            _ => instance,
+        };
+        if replica.record(instance.clone(), status) {
+            wal::append(node, &Record::Instance { instance, status })?;
+        }
+        if status == Status::Committed {
+            replica.execute(node.node_id);
+        }
+        Ok::<_, std::io::Error>(replica.attributes(&id))
+    });
+    match attributes {
+        Ok(Ok(attributes)) => encoding.respond(StatusCode::OK, &attributes),
+        Ok(Err(e)) => {
+            node.log("EPaxos: Instance not persisted", &e.to_string())
+                .await;
+            HttpResponse::InternalServerError().finish()
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Commits the command in the next instance led by this node. The replicas answer the
+/// pre-accept with the interfering instances they know: when a fast quorum agrees the
+/// attributes are committed straight away, otherwise their union is accepted by a majority
+/// first. Rejected rounds are retried until the retry deadline expires.
+pub async fn commit(node: &PaxosNode, command: Command) -> Result<InstanceId, ProposeError> {
+    let id = node
+        .epaxos
+        .write()
+        .map(|mut replica| {
+            replica.next_instance += 1;
+            InstanceId {
+                replica: node.node_id,
+                instance: replica.next_instance - 1,
+            }
+        })
+        .map_err(|_| ProposeError::NotAccepted(None))?;
+    let instance = Instance {
+        id,
+        command,
+        seq: 0,
+        deps: BTreeSet::new(),
+    };
+    let deadline =
+        Instant::now() + Duration::from_millis(node.retry_deadline_ms.load(Ordering::Acquire));
+    loop {
+        match agree(node, &instance).await {
+            Ok(instance) => {
+                let replicas = latest_membership(node).acceptors;
+                join_all(
+                    replicas
+                        .iter()
+                        .map(|n| node.send(n, "/epaxos/commit", &instance)),
+                )
+                .await;
+                node.log("EPaxos: Command committed", &format!("{:?}", instance))
+                    .await;
+                return Ok(id);
+            }
+            Err(e) if Instant::now() >= deadline => return Err(e),
+            Err(_) => {
+                let backoff = node.retry_backoff_ms.load(Ordering::Acquire);
+                actix_web::rt::time::sleep(Duration::from_millis(backoff)).await;
+            }
+        }
+    }
+}
+
+/// Attributes to commit the instance with, after the fast path or the slow one.
+async fn agree(node: &PaxosNode, instance: &Instance) -> Result<Instance, ProposeError> {
+    let replicas = latest_membership(node).acceptors;
+    let majority = Quorums::Majority.phase2_size(replicas.len());
+    let answers = send(node, &replicas, "/epaxos/pre_accept", instance).await;
+    if answers.len() < majority {
+        return Err(ProposeError::NotAccepted(None));
+    }
+    let mut agreed = instance.clone();
+    if answers.len() >= Quorums::Majority.fast_size(replicas.len())
+        && answers.iter().all(|attributes| *attributes == answers[0])
+    {
+        agreed.seq = answers[0].seq;
+        agreed.deps = answers[0].deps.clone();
+        node.log("EPaxos: Fast path", &format!("{:?}", agreed.id))
+            .await;
+        return Ok(agreed);
+    }
+    for attributes in answers {
+        agreed.seq = agreed.seq.max(attributes.seq);
+        agreed.deps.extend(attributes.deps);
+    }
+    node.log("EPaxos: Slow path", &format!("{:?}", agreed.id))
+        .await;
+    if send(node, &replicas, "/epaxos/accept", &agreed).await.len() < majority {
+        return Err(ProposeError::NotAccepted(None));
+    }
+    Ok(agreed)
+}
+
+/// Attributes answered by the replicas that took the instance.
+async fn send(
+    node: &PaxosNode,
+    replicas: &[String],
+    path: &str,
+    instance: &Instance,
+) -> Vec<Attributes> {
+    let replies = join_all(replicas.iter().map(|n| node.send(n, path, instance))).await;
+    let mut answers = Vec::new();
+    for reply in replies {
+        match reply {
+            Ok(reply) if reply.status == StatusCode::OK => match reply.decode::<Attributes>() {
+                Ok(attributes) => answers.push(attributes),
+                Err(e) => node.log("EPaxos: Answer not valid", &e.to_string()).await,
+            },
+            Ok(reply) => {
+                node.log("EPaxos: Instance refused", reply.status.as_str())
+                    .await
+            }
+            Err(e) => node.log("EPaxos: Error", &e.to_string()).await,
+        }
+    }
+    answers
+}
+
+/// Outcome of the instance once the local replica executed it, `None` when this node is not a
+/// replica or the instances it depends on are not committed before the retry deadline, the
+/// outcome is then dropped when the instance is executed.
+async fn outcome(node: &PaxosNode, id: InstanceId) -> Option<Outcome> {
+    if !node.has_role(Role::Acceptor) {
+        return None;
+    }
+    let deadline =
+        Instant::now() + Duration::from_millis(node.retry_deadline_ms.load(Ordering::Acquire));
+    loop {
+        let expired = Instant::now() >= deadline;
+        let outcome = node.epaxos.write().map_or(None, |mut replica| {
+            let outcome = replica.outcomes.remove(&id);
+            if outcome.is_none() && expired {
+                replica.uncollected.insert(id);
+            }
+            outcome
+        });
+        if outcome.is_some() || expired {
+            return outcome;
+        }
+        actix_web::rt::time::sleep(EXECUTION_POLL).await;
+    }
+}
+
+/// Keeps an instance replayed from the write-ahead log, the instances led by this node before
+/// the restart are not used again.
+pub fn restore(node: &PaxosNode, instance: Instance, status: Status) {
+    if let Ok(mut replica) = node.epaxos.write() {
+        if instance.id.replica == node.node_id {
+            replica.next_instance = replica.next_instance.max(instance.id.instance + 1);
+        }
+        replica.record(instance, status);
+    }
+}
+
+/// Executes the committed instances replayed from the write-ahead log, their clients are gone
+/// so the outcomes are not kept.
+pub fn execute(node: &PaxosNode) {
+    if let Ok(mut replica) = node.epaxos.write() {
+        replica.execute(node.node_id);
+        replica.outcomes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        messages::Envelope,
+        tests::{new_node_with_id, start_node},
+    };
+
+    use super::*;
+
+    fn put(key: &str, value: &str) -> Command {
+        Command::Put {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    fn id(replica: u64, instance: u64) -> InstanceId {
+        InstanceId { replica, instance }
+    }
+
+    fn instance(id: InstanceId, command: Command, seq: u64, deps: &[InstanceId]) -> Instance {
+        Instance {
+            id,
+            command,
+            seq,
+            deps: deps.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn should_depend_on_interfering_instances_only() {
+        let mut replica = Replica::default();
+        replica.record(
+            instance(id(1, 0), put("a", "1"), 1, &[]),
+            Status::PreAccepted,
+        );
+        replica.record(
+            instance(
+                id(2, 0),
+                Command::Get {
+                    key: "a".to_owned(),
+                },
+                2,
+                &[],
+            ),
+            Status::Committed,
+        );
+        replica.record(instance(id(3, 0), put("b", "1"), 7, &[]), Status::Accepted);
+
+        let read = replica.pre_accept(instance(
+            id(1, 1),
+            Command::Get {
+                key: "a".to_owned(),
+            },
+            0,
+            &[],
+        ));
+        assert_eq!(read.deps, BTreeSet::from([id(1, 0)]));
+        assert_eq!(read.seq, 2);
+        let write = replica.pre_accept(instance(id(3, 1), put("a", "2"), 0, &[]));
+        assert_eq!(write.deps, BTreeSet::from([id(1, 0), id(2, 0)]));
+        assert_eq!(write.seq, 3);
+        // Committed attributes are final
+        assert!(!replica.record(instance(id(2, 0), put("a", "3"), 9, &[]), Status::Accepted));
+    }
+
+    #[test]
+    fn should_execute_components_in_dependency_order() {
+        let mut replica = Replica::default();
+        // 1.0 and 2.0 depend on each other, 1.0 runs first with the lower sequence number
+        replica.record(
+            instance(id(2, 0), put("a", "2"), 2, &[id(1, 0)]),
+            Status::Committed,
+        );
+        replica.record(
+            instance(id(3, 0), put("a", "3"), 3, &[id(2, 0)]),
+            Status::Committed,
+        );
+        replica.record(
+            instance(id(3, 1), put("b", "1"), 1, &[id(3, 2)]),
+            Status::Committed,
+        );
+        replica.record(
+            instance(id(3, 2), put("b", "2"), 1, &[]),
+            Status::PreAccepted,
+        );
+        replica.execute(1);
+        assert!(replica.executed.is_empty());
+
+        replica.record(
+            instance(id(1, 0), put("a", "1"), 1, &[id(2, 0)]),
+            Status::Committed,
+        );
+        replica.execute(1);
+        assert_eq!(replica.executed, [id(1, 0), id(2, 0), id(3, 0)]);
+        assert_eq!(replica.get("a"), Some(&"3".to_owned()));
+        assert_eq!(
+            replica.outcomes.get(&id(1, 0)),
+            Some(&Outcome {
+                instance: id(1, 0),
+                success: true,
+                value: None
+            })
+        );
+
+        // 3.1 waits for the instance it depends on
+        replica.record(instance(id(3, 2), put("b", "2"), 1, &[]), Status::Committed);
+        replica.execute(1);
+        assert_eq!(replica.executed[3..], [id(3, 2), id(3, 1)]);
+        assert_eq!(replica.get("b"), Some(&"1".to_owned()));
+    }
+
+    #[actix_web::test]
+    async fn should_execute_conflicting_commands_in_same_order() {
+        let nodes = (1..=3)
+            .map(|node_id| {
+                new_node_with_id(node_id, &[Role::Proposer, Role::Acceptor, Role::Learner])
+            })
+            .collect::<Vec<_>>();
+        let servers = nodes.iter().map(start_node).collect::<Vec<_>>();
+        let urls = servers
+            .iter()
+            .map(|server| format!("http://{}", server.addr()))
+            .collect::<Vec<_>>();
+        for node in &nodes {
+            node.acceptor_nodes
+                .write()
+                .unwrap()
+                .extend_from_slice(&urls);
+        }
+        let http = reqwest::Client::new();
+
+        // Without interference the command is committed on the fast path
+        let outcome: Outcome = http
+            .post(format!("{}/epaxos/kv", urls[0]))
+            .json(&put("other", "value"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(outcome.instance, id(1, 0));
+
+        // Every node leads writes of the same key at the same time
+        let writes = (0..15).map(|i| {
+            http.post(format!("{}/epaxos/kv", urls[i % 3]))
+                .json(&put("key", &i.to_string()))
+                .send()
+        });
+        for reply in join_all(writes).await {
+            assert!(reply.unwrap().status().is_success());
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while nodes
+            .iter()
+            .any(|node| node.epaxos.read().unwrap().executed.len() < 16)
+        {
+            assert!(Instant::now() < deadline, "commands not executed");
+            actix_web::rt::time::sleep(EXECUTION_POLL).await;
+        }
+
+        let orders = nodes
+            .iter()
+            .map(|node| node.epaxos.read().unwrap().executed.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(orders[0], orders[1]);
+        assert_eq!(orders[1], orders[2]);
+        let values = nodes
+            .iter()
+            .map(|node| node.epaxos.read().unwrap().get("key").cloned())
+            .collect::<Vec<_>>();
+        assert!(values[0].is_some());
+        assert_eq!(values[0], values[1]);
+        assert_eq!(values[1], values[2]);
+        let resp = http
+            .get(format!("{}/epaxos/kv/key", urls[2]))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.text().await.unwrap(), values[2].clone().unwrap());
+    }
+
+    #[actix_web::test]
+    async fn should_block_key_after_leader_stopped_before_commit() {
+        let nodes = (1..=3)
+            .map(|node_id| {
+                new_node_with_id(node_id, &[Role::Proposer, Role::Acceptor, Role::Learner])
+            })
+            .collect::<Vec<_>>();
+        let servers = nodes.iter().map(start_node).collect::<Vec<_>>();
+        let urls = servers
+            .iter()
+            .map(|server| format!("http://{}", server.addr()))
+            .collect::<Vec<_>>();
+        for node in &nodes {
+            node.acceptor_nodes
+                .write()
+                .unwrap()
+                .extend_from_slice(&urls);
+            node.retry_deadline_ms.store(100, Ordering::Release);
+        }
+        let http = reqwest::Client::new();
+        // Node 4 stopped once the replicas pre-accepted its instance
+        let stopped = instance(id(4, 0), put("key", "stopped"), 0, &[]);
+        for url in &urls {
+            let resp = http
+                .post(format!("{}/epaxos/pre_accept", url))
+                .json(&Envelope::new(&stopped))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        // The interfering command is committed but never executed, the client is told so
+        let resp = http
+            .post(format!("{}/epaxos/kv", urls[0]))
+            .json(&put("key", "value"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert_eq!(resp.json::<InstanceId>().await.unwrap(), id(1, 0));
+        for node in &nodes {
+            let replica = node.epaxos.read().unwrap();
+            assert_eq!(replica.instances[&id(1, 0)].1, Status::Committed);
+            assert!(replica.executed.is_empty());
+        }
+
+        // Once the blocking instance is committed the command runs, its outcome is not kept
+        for url in &urls {
+            http.post(format!("{}/epaxos/commit", url))
+                .json(&Envelope::new(&stopped))
+                .send()
+                .await
+                .unwrap();
+        }
+        let replica = nodes[0].epaxos.read().unwrap();
+        assert_eq!(replica.executed, [id(4, 0), id(1, 0)]);
+        assert_eq!(replica.get("key"), Some(&"value".to_owned()));
+        assert!(replica.outcomes.is_empty());
+        assert!(replica.uncollected.is_empty());
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::atomic::Ordering,
+    time::Duration,
+};
+
+use actix_web::{post, rt::time::Instant, web, Error, HttpRequest, HttpResponse};
+use futures::future::join_all;
+use reqwest::{Method, StatusCode};
+
+use crate::{
+    leader::{forward, leadership, Leadership},
+    membership::{self, membership},
+    messages::{Any, ErrorBody, FastWindow},
+    proposer::{is_prepared, lead, nack_promised, step_down, ProposeError},
+    quorum::{quorums, Quorums},
+    Ballot, PaxosNode, Role,
+};
+
+pub const DEFAULT_FAST_WINDOW: u64 = 0;
+const MEMBERSHIP_POLL: Duration = Duration::from_millis(1);
+
+/// Value accepted for a slot by an acceptor that promised, with the ballot it was accepted in.
+pub type Vote<'a> = (&'a String, Ballot, String);
+
+/// Opens a fast window on the leader, the clients then send their values straight to the
+/// acceptors (see `FastWindow`).
+#[post("/fast")]
+async fn open_window(node: web::Data<PaxosNode>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Proposer) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    if node.fast_window_size.load(Ordering::Acquire) == 0 {
+        return Ok(HttpResponse::Forbidden().json(ErrorBody {
+            error: "Fast Paxos is disabled, PAXOS_FAST_WINDOW is 0".to_owned(),
+        }));
+    }
+    match leadership(&node, &req) {
+        Leadership::Local => {}
+        Leadership::Remote(leader) => {
+            return Ok(forward(&node, &req, &leader, Method::POST, "/fast", Vec::new()).await)
+        }
+        Leadership::Unknown => return Ok(HttpResponse::ServiceUnavailable().finish()),
+    }
+    match open(&node).await {
+        Ok(window) => Ok(HttpResponse::Ok().json(window)),
+        Err(e) => {
+            node.log("Fast: Window not opened", &format!("{:?}", e))
+                .await;
+            Ok(e.into())
+        }
+    }
+}
+
+/// Closes the window opened before, reserves the next slots of the log and lets the acceptors
+/// accept any value in them with the ballot of the leader. The window holds at most `alpha`
+/// slots, all in charge of the same acceptors.
+pub async fn open(node: &PaxosNode) -> Result<FastWindow, ProposeError> {
+    close(node).await;
+    let ballot = lead(node).await?;
+    let size = node
+        .fast_window_size
+        .load(Ordering::Acquire)
+        .min(membership::alpha(node))
+        .max(1);
+    let from = node.next_slot.fetch_add(size, Ordering::AcqRel);
+    let deadline =
+        Instant::now() + Duration::from_millis(node.retry_deadline_ms.load(Ordering::Acquire));
+    while !membership::is_known(node, from + size - 1) {
+        if Instant::now() >= deadline {
+            return Err(ProposeError::NotAccepted(None));
+        }
+        actix_web::rt::time::sleep(MEMBERSHIP_POLL).await;
+    }
+    let acceptors = membership(node, from).acceptors;
+    let to = (from..from + size)
+        .take_while(|slot| membership(node, *slot).acceptors == acceptors)
+        .last()
+        .map_or(from, |slot| slot + 1);
+    let window = FastWindow {
+        ballot,
+        from,
+        to,
+        fast_quorum: quorums(node).fast_size(acceptors.len()),
+        acceptors,
+    };
+    // Recorded before asking the acceptors, whatever they answer the slots of the window are
+    // completed when it closes
+    if let Ok(mut fast_window) = node.fast_window.write() {
+        *fast_window = Some(window.clone());
+    }
+    if !is_prepared(node, ballot, &window.acceptors) {
+        close(node).await;
+        return Err(ProposeError::NotAccepted(None));
+    }
+    let any = Any { ballot, from, to };
+    let replies = join_all(window.acceptors.iter().map(|n| node.send(n, "/any", &any))).await;
+    let mut granted = 0;
+    let mut highest_promised = None;
+    for reply in replies {
+        match reply {
+            Ok(reply) if reply.status == StatusCode::OK => granted += 1,
+            Ok(reply) => highest_promised = highest_promised.max(nack_promised(&reply)),
+            Err(e) => node.log("Fast: Error", &e.to_string()).await,
+        }
+    }
+    if granted < window.fast_quorum {
+        close(node).await;
+        return Err(ProposeError::NotAccepted(highest_promised));
+    }
+    node.log("Fast: Window opened", &format!("{:?}", window))
+        .await;
+    Ok(window)
+}
+
+/// Closes the fast window of this leader, if any: phase 1 runs again with a higher ballot, so
+/// the acceptors stop accepting the values of the clients, and the slots of the window are
+/// completed with the value that may have been chosen in each, or any value after a collision.
+pub async fn close(node: &PaxosNode) {
+    let window = node
+        .fast_window
+        .write()
+        .map_or(None, |mut fast_window| fast_window.take());
+    let Some(window) = window else {
+        return;
+    };
+    node.log("Fast: Window closed", &format!("{:?}", window))
+        .await;
+    step_down(node, window.ballot);
+    if let Err(e) = lead(node).await {
+        // The next proposal runs phase 1 again
+        node.log("Fast: Window not completed", &format!("{:?}", e))
+            .await;
+    }
+}
+
+/// Value to propose in phase 1 for a slot given the votes of the acceptors that promised. Only
+/// the values accepted with the highest ballot count: a single one is proposed again as in
+/// classic Paxos. Several values mean clients collided in a fast window, a value chosen by a
+/// fast quorum was accepted by at least `fast quorum + promised - acceptors` of the acceptors
+/// that promised, and only one value can reach that, otherwise any value can be proposed.
+pub fn choose(
+    quorums: &Quorums,
+    acceptors: &[String],
+    promised: &HashSet<&String>,
+    votes: Vec<Vote>,
+) -> Option<(Ballot, String)> {
+    let highest = votes.iter().map(|(_, ballot, _)| *ballot).max()?;
+    let mut values: BTreeMap<String, usize> = BTreeMap::new();
+    for (acceptor, ballot, value) in votes {
+        if ballot == highest {
+            *values.entry(value).or_default() += usize::from(acceptors.contains(acceptor));
+        }
+    }
+    if values.len() > 1 {
+        let promised = acceptors.iter().filter(|n| promised.contains(n)).count();
+        let threshold =
+            (quorums.fast_size(acceptors.len()) + promised).saturating_sub(acceptors.len());
+        if let Some((value, _)) = values.iter().find(|(_, votes)| **votes >= threshold) {
+            return Some((highest, value.clone()));
+        }
+    }
+    values
+        .into_iter()
+        .max_by_key(|(_, votes)| *votes)
+        .map(|(value, _)| (highest, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::web;
+
+    use crate::{
+        client::Client,
+        messages::{Accept, Encoding},
+        session::ClientRequest,
+        tests::{new_node_with_id, start_node},
+        NO_OP,
+    };
+
+    use super::*;
+
+    fn request(client: &str, value: &str) -> String {
+        ClientRequest {
+            client: client.to_owned(),
+            sequence: 1,
+            value: value.to_owned(),
+        }
+        .encode()
+        .unwrap()
+    }
+
+    /// Three acceptors and learners, the first node is the only proposer.
+    fn start_cluster() -> (
+        Vec<web::Data<PaxosNode>>,
+        Vec<actix_test::TestServer>,
+        Vec<String>,
+    ) {
+        let nodes = (1..=3)
+            .map(|node_id| {
+                let roles: &[Role] = if node_id == 1 {
+                    &[Role::Proposer, Role::Acceptor, Role::Learner]
+                } else {
+                    &[Role::Acceptor, Role::Learner]
+                };
+                new_node_with_id(node_id, roles)
+            })
+            .collect::<Vec<_>>();
+        let servers = nodes.iter().map(start_node).collect::<Vec<_>>();
+        let urls = servers
+            .iter()
+            .map(|server| format!("http://{}", server.addr()))
+            .collect::<Vec<_>>();
+        for node in &nodes {
+            node.acceptor_nodes
+                .write()
+                .unwrap()
+                .extend_from_slice(&urls);
+            node.learner_nodes.write().unwrap().extend_from_slice(&urls);
+        }
+        nodes[0].fast_window_size.store(3, Ordering::Release);
+        (nodes, servers, urls)
+    }
+
+    fn decided(node: &PaxosNode, slot: u64) -> Option<String> {
+        node.decided_values.read().unwrap().get(&slot).cloned()
+    }
+
+    #[test]
+    fn should_choose_value_possibly_chosen_after_collision() {
+        let acceptors = (0..5)
+            .map(|i| format!("http://acceptor-{}", i))
+            .collect::<Vec<_>>();
+        let promised = acceptors[..3].iter().collect::<HashSet<_>>();
+        let fast = Ballot::new(2, 1);
+        let vote = |i: usize, ballot, value: &str| (&acceptors[i], ballot, value.to_owned());
+        // A fast quorum of 4 acceptors may have chosen a: 2 of the 3 that promised accepted it
+        let votes = vec![vote(0, fast, "a"), vote(1, fast, "a"), vote(2, fast, "b")];
+        assert_eq!(
+            choose(&Quorums::Majority, &acceptors, &promised, votes),
+            Some((fast, "a".to_owned()))
+        );
+        // Only the values of the highest ballot count
+        let votes = vec![
+            vote(0, Ballot::new(1, 1), "a"),
+            vote(1, Ballot::new(1, 1), "a"),
+            vote(2, fast, "b"),
+        ];
+        assert_eq!(
+            choose(&Quorums::Majority, &acceptors, &promised, votes),
+            Some((fast, "b".to_owned()))
+        );
+        assert_eq!(
+            choose(&Quorums::Majority, &acceptors, &promised, Vec::new()),
+            None
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_choose_value_sent_to_acceptors_with_fast_quorum() {
+        let (nodes, _servers, urls) = start_cluster();
+        let client = Client::new(vec![urls[0].clone()]);
+
+        assert_eq!(
+            client.propose_fast("fast value").await.unwrap(),
+            "Value fast value accepted in slot 0!"
+        );
+        // Every learner counted the fast quorum, the leader took no part in the slot
+        for node in &nodes {
+            let value = decided(node, 0).unwrap();
+            assert_eq!(ClientRequest::decode(&value).unwrap().value, "fast value");
+        }
+        assert!(nodes[0].fast_window.read().unwrap().is_some());
+
+        // A classic proposal closes the window, its unused slots are completed with no-ops
+        let classic = Client::new(vec![urls[0].clone()]);
+        assert_eq!(
+            classic.propose("classic value").await.unwrap(),
+            "Value classic value accepted in slot 3!"
+        );
+        assert!(nodes[0].fast_window.read().unwrap().is_none());
+        assert_eq!(decided(&nodes[0], 1).as_deref(), Some(NO_OP));
+        assert_eq!(decided(&nodes[0], 2).as_deref(), Some(NO_OP));
+    }
+
+    #[actix_web::test]
+    async fn should_recover_slot_after_collision() {
+        let (nodes, _servers, urls) = start_cluster();
+        let window = open(&nodes[0]).await.unwrap();
+        assert_eq!((window.from, window.to, window.fast_quorum), (0, 3, 3));
+
+        // Two clients race for the first slot of the window, each gets it on some acceptors
+        let http = reqwest::Client::new();
+        let send = |acceptor: &String, value: &str| {
+            http.post(format!("{}/accept", acceptor))
+                .header("Content-Type", Encoding::Json.content_type())
+                .body(
+                    Encoding::Json
+                        .encode(&Accept {
+                            fast: true,
+                            ..Accept::new(window.from, window.ballot, value)
+                        })
+                        .unwrap(),
+                )
+                .send()
+        };
+        let a = request("a", "value a");
+        let b = request("b", "value b");
+        let system = serde_json::json!({
+            "System": { "Remove": { "role": "acceptor", "node": &urls[2] } }
+        })
+        .to_string();
+        for (acceptor, value, status) in [
+            (&urls[0], &system, reqwest::StatusCode::BAD_REQUEST),
+            (&urls[0], &a, reqwest::StatusCode::ACCEPTED),
+            (&urls[1], &a, reqwest::StatusCode::ACCEPTED),
+            (&urls[2], &b, reqwest::StatusCode::ACCEPTED),
+            (&urls[0], &b, reqwest::StatusCode::CONFLICT),
+        ] {
+            assert_eq!(send(acceptor, value).await.unwrap().status(), status);
+        }
+        for node in &nodes {
+            assert_eq!(decided(node, 0), None);
+        }
+
+        // Both fall back to the leader with the same sequence number, the recovery keeps one of
+        // the values in the slot and every value is applied once
+        let mut slots = Vec::new();
+        for (client, value) in [("a", "value a"), ("b", "value b")] {
+            let answer = Client::with_client_id(vec![urls[0].clone()], client.to_owned())
+                .propose(value)
+                .await
+                .unwrap();
+            let session = nodes[0]
+                .state_machine
+                .read()
+                .unwrap()
+                .session(client)
+                .cloned()
+                .unwrap();
+            assert_eq!(session.sequence, 1);
+            assert_eq!(
+                answer,
+                format!("Value {} accepted in slot {}!", value, session.slot)
+            );
+            slots.push(session.slot);
+        }
+        assert!(slots.contains(&0));
+        assert_ne!(slots[0], slots[1]);
+        let chosen = decided(&nodes[0], 0).unwrap();
+        assert!(chosen == a || chosen == b);
+
+        // The window is closed, the acceptors refuse the values sent in it
+        assert_eq!(
+            send(&urls[2], &request("c", "value c"))
+                .await
+                .unwrap()
+                .status(),
+            reqwest::StatusCode::NOT_ACCEPTABLE
+        );
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::{delete, get, post, put, web, Error, HttpRequest, HttpResponse};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    batch::{self, submit},
+    leader::{forward, leadership, Leadership},
+    read::{prepare_read, served_at, ReadQuery},
+    session::{self, ClientRequest, Session},
+    PaxosNode, Role,
+};
+
+/// Command agreed through Paxos, stored as JSON in the value of a log slot.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum Command {
+    Put {
+        key: String,
+        value: String,
+    },
+    Get {
+        key: String,
+    },
+    Delete {
+        key: String,
+    },
+    CompareAndSwap {
+        key: String,
+        expected: Option<String>,
+        value: String,
+    },
+}
+
+impl Command {
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Put { key, .. }
+            | Self::Get { key }
+            | Self::Delete { key }
+            | Self::CompareAndSwap { key, .. } => key,
+        }
+    }
+
+    /// Two commands interfere when they use the same key and one of them writes it, the result
+    /// depends on the order they are applied in.
+    pub fn interferes(&self, other: &Command) -> bool {
+        self.key() == other.key()
+            && !(matches!(self, Self::Get { .. }) && matches!(other, Self::Get { .. }))
+    }
+}
+
+/// Applies the command to the store, returns whether it succeeded and the value of the key
+/// before it.
+pub fn run(data: &mut HashMap<String, String>, command: Command) -> (bool, Option<String>) {
+    match command {
+        Command::Put { key, value } => (true, data.insert(key, value)),
+        Command::Get { key } => (true, data.get(&key).cloned()),
+        Command::Delete { key } => (true, data.remove(&key)),
+        Command::CompareAndSwap {
+            key,
+            expected,
+            value,
+        } => {
+            let current = data.get(&key).cloned();
+            if current == expected {
+                data.insert(key, value);
+                (true, current)
+            } else {
+                (false, current)
+            }
+        }
+    }
+}
+
+/// Outcome of a command once applied, `value` is the value of the key before the command.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CommandResult {
+    pub slot: u64,
+    pub success: bool,
+    pub value: Option<String>,
+}
+
+#[derive(Default)]
+pub struct StateMachine {
+    // Next slot to apply
+    applied: u64,
+    data: HashMap<String, String>,
+    // Results by slot and position of the command in the slot
+    results: BTreeMap<(u64, usize), CommandResult>,
+    // Last request applied for every client
+    sessions: HashMap<String, Session>,
+}
+
+impl StateMachine {
+    fn apply(&mut self, slot: u64, command: Command) -> CommandResult {
+        let (success, value) = run(&mut self.data, command);
+        CommandResult {
+            slot,
+            success,
+            value,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.data.get(key)
+    }
+
+    pub fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    pub fn data(&self) -> &HashMap<String, String> {
+        &self.data
+    }
+
+    pub fn session(&self, client: &str) -> Option<&Session> {
+        self.sessions.get(client)
+    }
+
+    pub fn sessions(&self) -> &HashMap<String, Session> {
+        &self.sessions
+    }
+
+    /// Applies the value in position `index` of the slot. A request of a client is applied
+    /// only if it follows the last one applied, a retry gets the result of the first attempt.
+    fn apply_value(&mut self, slot: u64, index: usize, value: &str) {
+        let request = ClientRequest::decode(value);
+        let value = request.as_ref().map_or(value, |request| &request.value);
+        if let Some(request) = &request {
+            if let Some(session) = self.sessions.get(&request.client) {
+                if request.sequence <= session.sequence {
+                    if let (true, Some(result)) =
+                        (request.sequence == session.sequence, &session.result)
+                    {
+                        self.results.insert((slot, index), result.clone());
+                    }
+                    return;
+                }
+            }
+        }
+        let result = serde_json::from_str::<Command>(value)
+            .ok()
+            .map(|command| self.apply(slot, command));
+        if let Some(result) = &result {
+            self.results.insert((slot, index), result.clone());
+        }
+        if let Some(request) = request {
+            self.sessions.insert(
+                request.client,
+                Session {
+                    sequence: request.sequence,
+                    slot,
+                    result,
+                },
+            );
+        }
+    }
+
+    /// Applies the value of the next slot, the commands batched in it in the order they were
+    /// submitted. Values that are not commands (e.g. no-ops or plain `/consensus` values) only
+    /// move the applied slot forward.
+    pub fn apply_next(&mut self, value: &str) {
+        let slot = self.applied;
+        for (index, value) in batch::values(value).iter().enumerate() {
+            self.apply_value(slot, index, value);
+        }
+        self.applied += 1;
+    }
+
+    /// Result of the command in position `index` of the slot, once, for the client waiting for
+    /// it.
+    pub fn take_result(&mut self, slot: u64, index: usize) -> Option<CommandResult> {
+        self.results.remove(&(slot, index))
+    }
+
+    /// Forgets the results of the commands before the slot, the clients waiting for them got
+    /// them already.
+    pub fn truncate(&mut self, slot: u64) {
+        self.results = self.results.split_off(&(slot, 0));
+    }
+
+    /// State machine of a snapshot, every slot before `applied` is applied. The results of
+    /// the commands are not part of it, the results kept in the sessions are.
+    pub fn restore(
+        applied: u64,
+        data: HashMap<String, String>,
+        sessions: HashMap<String, Session>,
+    ) -> Self {
+        Self {
+            applied,
+            data,
+            results: BTreeMap::new(),
+            sessions,
+        }
+    }
+}
+
+/// Applies, in order, every decided slot following the last applied one.
+pub fn apply_decided(node: &PaxosNode) {
+    if let (Ok(mut state_machine), Ok(decided_values)) =
+        (node.state_machine.write(), node.decided_values.read())
+    {
+        while let Some(value) = decided_values.get(&state_machine.applied) {
+            state_machine.apply_next(value);
+        }
+    }
+}
+
+/// Decides the command and returns its result from the local state machine, followers send the
+/// command to the leader.
+async fn execute(node: &PaxosNode, req: &HttpRequest, command: Command) -> HttpResponse {
+    if !node.has_role(Role::Proposer) {
+        return HttpResponse::Forbidden().finish();
+    }
+    let value = match serde_json::to_string(&command) {
+        Ok(value) => value,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let request = match ClientRequest::of(req, value.clone()) {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    match leadership(node, req) {
+        Leadership::Local => {}
+        Leadership::Remote(leader) => {
+            return forward(node, req, &leader, Method::POST, "/kv", value.into_bytes()).await
+        }
+        Leadership::Unknown => return HttpResponse::ServiceUnavailable().finish(),
+    }
+    let value = match &request {
+        Some(request) => match session::applied(node, request) {
+            Ok(Some(session)) => {
+                return match session.result {
+                    Some(result) => HttpResponse::Ok().json(result),
+                    None => HttpResponse::Accepted().json(session.slot),
+                }
+            }
+            Ok(None) => match request.encode() {
+                Ok(value) => value,
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            },
+            Err(response) => return response,
+        },
+        None => value,
+    };
+    node.log("KV: Command started", &value).await;
+    match submit(node, &value).await {
+        Ok((slot, index)) => {
+            let result = node
+                .state_machine
+                .write()
+                .map_or(None, |mut state_machine| {
+                    state_machine.take_result(slot, index)
+                });
+            match result {
+                Some(result) => HttpResponse::Ok().json(result),
+                // Chosen but not applied yet because of a gap in the local log
+                None => HttpResponse::Accepted().json(slot),
+            }
+        }
+        Err(e) => {
+            node.log("KV: Command not decided", &format!("{:?}", e))
+                .await;
+            e.into()
+        }
+    }
+}
+
+/// Reads the key from the local store, `?read=log` or `?read=lease` make the read linearizable.
+#[get("/kv/{key}")]
+async fn get_key(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    key: web::Path<String>,
+    query: web::Query<ReadQuery>,
+) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Learner) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let ballot = match prepare_read(&node, &req, query.read).await {
+        Ok(ballot) => ballot,
+        Err(response) => return Ok(response),
+    };
+    let read = node.state_machine.read().map_or(None, |state_machine| {
+        Some((state_machine.get(&key).cloned(), state_machine.applied))
+    });
+    match read {
+        Some((Some(value), applied)) => {
+            Ok(served_at(HttpResponse::Ok().body(value), applied, ballot))
+        }
+        Some((None, applied)) => Ok(served_at(
+            HttpResponse::NotFound().finish(),
+            applied,
+            ballot,
+        )),
+        None => Ok(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+#[put("/kv/{key}")]
+async fn put_key(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    key: web::Path<String>,
+    value: String,
+) -> Result<HttpResponse, Error> {
+    Ok(execute(
+        &node,
+        &req,
+        Command::Put {
+            key: key.into_inner(),
+            value,
+        },
+    )
+    .await)
+}
+
+#[delete("/kv/{key}")]
+async fn delete_key(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    key: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    Ok(execute(
+        &node,
+        &req,
+        Command::Delete {
+            key: key.into_inner(),
+        },
+    )
+    .await)
+}
+
+#[post("/kv")]
+async fn submit_command(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    command: web::Json<Command>,
+) -> Result<HttpResponse, Error> {
+    Ok(execute(&node, &req, command.into_inner()).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::to_bytes, http::StatusCode, test, App};
+
+    use crate::{
+        acceptor::{accept, propose},
+        learner::{learn, update_value},
+        tests::new_node,
+    };
+
+    use super::*;
+
+    fn put(key: &str, value: &str) -> String {
+        serde_json::to_string(&Command::Put {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+        .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn should_apply_commands_in_slot_order() {
+        let node = new_node(&[Role::Learner]);
+        learn(&node, 1, &put("key", "second")).unwrap();
+        assert_eq!(node.state_machine.read().unwrap().get("key"), None);
+        learn(&node, 0, &put("key", "first")).unwrap();
+        learn(&node, 2, "not a command").unwrap();
+        learn(
+            &node,
+            3,
+            &serde_json::to_string(&Command::CompareAndSwap {
+                key: "key".to_owned(),
+                expected: Some("first".to_owned()),
+                value: "third".to_owned(),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        let state_machine = node.state_machine.read().unwrap();
+        assert_eq!(state_machine.applied, 4);
+        assert_eq!(state_machine.get("key"), Some(&"second".to_owned()));
+        assert_eq!(
+            state_machine.results.get(&(3, 0)),
+            Some(&CommandResult {
+                slot: 3,
+                success: false,
+                value: Some("second".to_owned())
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_put_and_get_key() {
+        let node = new_node(&[Role::Proposer, Role::Acceptor, Role::Learner]);
+        let data = node.clone();
+        let acceptor = actix_test::start(move || {
+            App::new()
+                .app_data(data.clone())
+                .service(propose)
+                .service(accept)
+                .service(update_value)
+        });
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.push(format!("http://{}", acceptor.addr()));
+        }
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(get_key)
+                .service(put_key)
+                .service(delete_key)
+                .service(submit_command),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/kv/key").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::put()
+            .uri("/kv/key")
+            .set_payload("value")
+            .to_request();
+        let result: CommandResult = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            result,
+            CommandResult {
+                slot: 0,
+                success: true,
+                value: None
+            }
+        );
+        let req = test::TestRequest::get().uri("/kv/key").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(to_bytes(resp.into_body()).await.unwrap(), "value");
+
+        let req = test::TestRequest::post()
+            .uri("/kv")
+            .set_json(Command::CompareAndSwap {
+                key: "key".to_owned(),
+                expected: Some("other value".to_owned()),
+                value: "new value".to_owned(),
+            })
+            .to_request();
+        let result: CommandResult = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            result,
+            CommandResult {
+                slot: 1,
+                success: false,
+                value: Some("value".to_owned())
+            }
+        );
+
+        let req = test::TestRequest::delete().uri("/kv/key").to_request();
+        let result: CommandResult = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            result,
+            CommandResult {
+                slot: 2,
+                success: true,
+                value: Some("value".to_owned())
+            }
+        );
+        let req = test::TestRequest::get().uri("/kv/key").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn should_not_write_without_proposer_role() {
+        let node = new_node(&[Role::Learner]);
+        let app = test::init_service(App::new().app_data(node.clone()).service(put_key)).await;
+        let req = test::TestRequest::put()
+            .uri("/kv/key")
+            .set_payload("value")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+}
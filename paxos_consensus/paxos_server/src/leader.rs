@@ -0,0 +1,640 @@
+use std::{collections::HashSet, sync::atomic::Ordering, time::Duration};
+
+use actix_web::{
+    get, http::StatusCode, post, rt::time::Instant, web, Error, HttpRequest, HttpResponse,
+};
+use futures::future::{join, join_all};
+use futures_util::StreamExt as _;
+use reqwest::Method;
+
+use crate::{
+    membership::latest_membership,
+    messages::{Encoding, Lease},
+    proposer::{restore_ballot, step_down},
+    quorum::quorums,
+    session::{CLIENT_ID_HEADER, SEQUENCE_HEADER},
+    Ballot, Leader, PaxosNode, Role, CLIENT,
+};
+
+pub const DEFAULT_LEASE_MS: u64 = 1000;
+
+/// Header set on the requests forwarded to the leader, they are never forwarded again.
+pub const FORWARDED_HEADER: &str = "x-paxos-forwarded";
+
+/// Where a request that needs phase 1 has to be handled.
+pub enum Leadership {
+    /// This node is the leader, or the only proposer
+    Local,
+    Remote(Leader),
+    /// No lease is valid, a new leader is being elected
+    Unknown,
+}
+
+pub fn leadership(node: &PaxosNode, req: &HttpRequest) -> Leadership {
+    if req.headers().contains_key(FORWARDED_HEADER) || !election_enabled(node) {
+        return Leadership::Local;
+    }
+    match current_leader(node) {
+        Some(leader) if leader.node_id == node.node_id => Leadership::Local,
+        Some(leader) => Leadership::Remote(leader),
+        None => Leadership::Unknown,
+    }
+}
+
+/// Sends the request to the leader and returns its response as is, the leader can keep retrying
+/// the value until the retry deadline so it is waited for that long on top of the request
+/// timeout.
+pub async fn forward(
+    node: &PaxosNode,
+    req: &HttpRequest,
+    leader: &Leader,
+    method: Method,
+    path: &str,
+    body: Vec<u8>,
+) -> HttpResponse {
+    node.log("Leader: Forwarding to", &leader.url).await;
+    let timeout = node.request_timeout()
+        + Duration::from_millis(node.retry_deadline_ms.load(Ordering::Acquire));
+    let mut request = CLIENT
+        .request(method, format!("{}{}", leader.url, path))
+        .timeout(timeout)
+        .header(FORWARDED_HEADER, node.node_id.to_string())
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    // The leader drops the duplicates of the client as well
+    for name in [CLIENT_ID_HEADER, SEQUENCE_HEADER] {
+        if let Some(Ok(value)) = req.headers().get(name).map(|value| value.to_str()) {
+            request = request.header(name, value);
+        }
+    }
+    let response = request.body(body).send().await;
+    match response {
+        Ok(response) => {
+            let status = StatusCode::from_u16(response.status().as_u16())
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            let mut builder = HttpResponse::build(status);
+            // The body is sent again so its length and encoding are set again
+            for (name, value) in response.headers() {
+                if matches!(
+                    name.as_str(),
+                    "content-length" | "transfer-encoding" | "connection"
+                ) {
+                    continue;
+                }
+                if let Ok(value) = value.to_str() {
+                    builder.insert_header((name.as_str(), value));
+                }
+            }
+            match response.bytes().await {
+                Ok(body) => builder.body(body),
+                Err(_) => HttpResponse::BadGateway().finish(),
+            }
+        }
+        Err(e) => {
+            node.log("Leader: Forward failed", &e.to_string()).await;
+            if e.is_timeout() {
+                HttpResponse::GatewayTimeout().finish()
+            } else {
+                HttpResponse::BadGateway().finish()
+            }
+        }
+    }
+}
+
+/// Whether this node is the elected leader and its lease is still valid: a phase 2 quorum of the
+/// acceptors granted it, so no other proposer can run phase 1 before the lease expires.
+pub fn holds_lease(node: &PaxosNode) -> bool {
+    election_enabled(node)
+        && current_leader(node).is_some_and(|leader| leader.node_id == node.node_id)
+}
+
+fn election_enabled(node: &PaxosNode) -> bool {
+    node.proposer_nodes
+        .read()
+        .is_ok_and(|paxos_proposer_nodes| paxos_proposer_nodes.len() > 1)
+}
+
+fn current_leader(node: &PaxosNode) -> Option<Leader> {
+    node.lease
+        .read()
+        .map_or(None, |lease| match lease.as_ref() {
+            Some((leader, expires)) if *expires > Instant::now() => Some(leader.clone()),
+            _ => None,
+        })
+}
+
+fn lease_duration(node: &PaxosNode) -> Duration {
+    Duration::from_millis(node.lease_ms.load(Ordering::Acquire))
+}
+
+/// Follows `leader` if the current lease expired or `leader` has a higher or equal node id,
+/// returns the leader followed afterwards.
+fn follow(node: &PaxosNode, leader: Leader) -> Option<Leader> {
+    let mut lease = node.lease.write().ok()?;
+    let now = Instant::now();
+    let takes_over = match lease.as_ref() {
+        Some((current, expires)) if *expires > now => leader.node_id >= current.node_id,
+        _ => true,
+    };
+    if takes_over {
+        if leader.node_id != node.node_id {
+            // Phase 1 has to run again once this node is back being the leader
+            if let Ok(mut leader_proposal) = node.leader_proposal.write() {
+                *leader_proposal = None;
+            }
+        }
+        *lease = Some((leader, now + lease_duration(node)));
+    }
+    lease.as_ref().map(|(leader, _)| leader.clone())
+}
+
+/// Makes this node the leader until the lease the acceptors granted from `sent` expires, unless
+/// it follows another proposer meanwhile.
+fn renew(node: &PaxosNode, this_node: Leader, sent: Instant) {
+    if let Ok(mut lease) = node.lease.write() {
+        let follows_other = lease.as_ref().is_some_and(|(current, expires)| {
+            *expires > Instant::now() && current.node_id != node.node_id
+        });
+        if !follows_other {
+            *lease = Some((this_node, sent + lease_duration(node)));
+        }
+    }
+}
+
+/// Proposer other than the one of `ballot` holding a lease granted by this acceptor, the ballot
+/// is not promised before the lease expires.
+pub fn leased_to_other(node: &PaxosNode, ballot: Ballot) -> Option<Leader> {
+    node.lease_grant
+        .read()
+        .map_or(None, |grant| match grant.as_ref() {
+            Some((holder, expires))
+                if *expires > Instant::now() && holder.node_id != ballot.node_id =>
+            {
+                Some(holder.clone())
+            }
+            _ => None,
+        })
+}
+
+/// Keeps the acceptor from granting a lease or promising a ballot for a lease duration, called
+/// once its log was replayed: the grant is not logged, a lease granted before the restart may
+/// still be counted on by its holder.
+pub fn fence(node: &PaxosNode) {
+    if let Ok(mut fence) = node.lease_fence.write() {
+        *fence = Some(Instant::now() + lease_duration(node));
+    }
+}
+
+/// Whether this acceptor restarted less than a lease duration ago.
+pub fn fenced(node: &PaxosNode) -> bool {
+    node.lease_fence
+        .read()
+        .is_ok_and(|fence| fence.is_some_and(|until| until > Instant::now()))
+}
+
+/// Grants the lease to the proposer unless another one holds a lease granted by this acceptor,
+/// answers with the proposer holding it. The grant expires a lease duration after it is
+/// received, never before the lease counted by the proposer from when it asked.
+#[post("/lease")]
+async fn grant_lease(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    mut value: web::Payload,
+) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Acceptor) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let mut bytes = web::BytesMut::new();
+    while let Some(item) = value.next().await {
+        bytes.extend_from_slice(&item?);
+    }
+    let encoding = match Encoding::of(&req) {
+        Ok(encoding) => encoding,
+        Err(e) => return Ok(e.into()),
+    };
+    let leader = match encoding.decode::<Leader>(&bytes) {
+        Ok(leader) => leader,
+        Err(e) => {
+            node.log("Leader: Lease request not valid", &e.to_string())
+                .await;
+            return Ok(e.into());
+        }
+    };
+    if fenced(&node) {
+        node.log(
+            "Leader: Lease refused after restart",
+            &leader.node_id.to_string(),
+        )
+        .await;
+        return Ok(HttpResponse::ServiceUnavailable().finish());
+    }
+    let granted = node.lease_grant.write().map(|mut grant| {
+        let now = Instant::now();
+        match grant.as_ref() {
+            Some((holder, expires)) if *expires > now && holder.node_id != leader.node_id => {
+                Err(holder.clone())
+            }
+            _ => {
+                *grant = Some((leader.clone(), now + lease_duration(&node)));
+                Ok(leader)
+            }
+        }
+    });
+    let promised = node
+        .promised_ballot
+        .read()
+        .map_or(Ballot::default(), |b| *b);
+    match granted {
+        Ok(Ok(holder)) => Ok(encoding.respond(StatusCode::OK, &Lease { holder, promised })),
+        Ok(Err(holder)) => {
+            node.log("Leader: Lease held by", &holder.node_id.to_string())
+                .await;
+            Ok(encoding.respond(StatusCode::CONFLICT, &Lease { holder, promised }))
+        }
+        Err(_) => Ok(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+#[post("/heartbeat")]
+async fn heartbeat(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    mut value: web::Payload,
+) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Proposer) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let mut bytes = web::BytesMut::new();
+    while let Some(item) = value.next().await {
+        bytes.extend_from_slice(&item?);
+    }
+    let encoding = match Encoding::of(&req) {
+        Ok(encoding) => encoding,
+        Err(e) => return Ok(e.into()),
+    };
+    let leader = match encoding.decode::<Leader>(&bytes) {
+        Ok(leader) => leader,
+        Err(e) => {
+            node.log("Leader: Heartbeat not valid", &e.to_string())
+                .await;
+            return Ok(e.into());
+        }
+    };
+    match follow(&node, leader) {
+        Some(leader) => Ok(encoding.respond(StatusCode::OK, &leader)),
+        None => Ok(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+#[get("/leader")]
+async fn get_leader(node: web::Data<PaxosNode>) -> Result<HttpResponse, Error> {
+    let leader = if election_enabled(&node) {
+        current_leader(&node)
+    } else {
+        node.node_url.read().ok().map(|url| Leader {
+            node_id: node.node_id,
+            url: url.clone(),
+        })
+    };
+    match leader {
+        Some(leader) => Ok(HttpResponse::Ok().json(leader)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Keeps the lease of this node alive while it is the leader and runs for leadership once the
+/// lease of the leader expired, every proposer runs it in background.
+pub async fn run(node: web::Data<PaxosNode>) {
+    // Gives the current leader the time to announce itself before running for leadership
+    actix_web::rt::time::sleep(lease_duration(&node)).await;
+    loop {
+        tick(&node).await;
+        actix_web::rt::time::sleep(lease_duration(&node) / 3).await;
+    }
+}
+
+/// Sends the heartbeats if this node is the leader or no lease is valid, a proposer with a higher
+/// node id wins. The lease of this node is renewed only once a phase 2 quorum of the acceptors
+/// granted it, counted from when it was asked for.
+pub async fn tick(node: &PaxosNode) {
+    let node_id = node.node_id;
+    if current_leader(node).is_some_and(|leader| leader.node_id != node_id) {
+        return;
+    }
+    let Ok(url) = node.node_url.read().map(|url| url.clone()) else {
+        return;
+    };
+    let this_node = Leader { node_id, url };
+    let proposers = node
+        .proposer_nodes
+        .read()
+        .map_or(Vec::new(), |paxos_proposer_nodes| {
+            paxos_proposer_nodes
+                .iter()
+                .filter(|n| **n != this_node.url)
+                .cloned()
+                .collect::<Vec<_>>()
+        });
+    let acceptors = latest_membership(node).acceptors;
+    let sent = Instant::now();
+    let (futures, grants) = join(
+        join_all(
+            proposers
+                .iter()
+                .map(|n| node.send(n, "/heartbeat", &this_node)),
+        ),
+        join_all(acceptors.iter().map(|n| node.send(n, "/lease", &this_node))),
+    )
+    .await;
+    for response in futures {
+        match response {
+            Ok(v) if v.status == reqwest::StatusCode::OK => {
+                if let Ok(leader) = v.decode::<Leader>() {
+                    if leader.node_id > node_id {
+                        node.log("Leader: Following", &leader.node_id.to_string())
+                            .await;
+                        follow(node, leader);
+                    }
+                }
+            }
+            Ok(v) => {
+                node.log("Leader: Heartbeat sent with errors", &v.status.to_string())
+                    .await
+            }
+            Err(e) => node.log("Leader: Error", &e.to_string()).await,
+        }
+    }
+    let mut granted = HashSet::new();
+    for (acceptor, reply) in acceptors.iter().zip(grants) {
+        let Ok((status, Ok(Lease { promised, .. }))) =
+            reply.map(|reply| (reply.status, reply.decode::<Lease>()))
+        else {
+            continue;
+        };
+        // Another proposer was promised before the grant, it may decide values this node does
+        // not know of until it runs phase 1 with a higher ballot
+        restore_ballot(node, promised);
+        let leader_proposal = node.leader_proposal.read().map_or(None, |l| *l);
+        if let Some(ballot) = leader_proposal.filter(|ballot| promised > *ballot) {
+            node.log("Leader: Higher ballot promised", &promised.to_string())
+                .await;
+            step_down(node, ballot);
+        }
+        if status == StatusCode::OK {
+            granted.insert(acceptor);
+        }
+    }
+    if quorums(node).is_phase2(&acceptors, &granted) {
+        renew(node, this_node, sent);
+    } else {
+        node.log(
+            "Leader: Lease not granted",
+            &format!("{} of {} acceptors", granted.len(), acceptors.len()),
+        )
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::to_bytes, test, App};
+    use mockito::Matcher;
+
+    use crate::{
+        messages::Envelope,
+        proposer::{consensus_start, propose_value},
+        tests::{new_node_with_id, start_node},
+    };
+
+    use super::*;
+
+    fn leader(node_id: u64, url: &str) -> Leader {
+        Leader {
+            node_id,
+            url: url.to_owned(),
+        }
+    }
+
+    fn enable_election(
+        node_id: u64,
+        url: &str,
+        other_proposers: &[String],
+    ) -> web::Data<PaxosNode> {
+        let node = new_node_with_id(node_id, &[Role::Proposer]);
+        if let Ok(mut node_url) = node.node_url.write() {
+            *node_url = url.to_owned();
+        }
+        if let Ok(mut paxos_proposer_nodes) = node.proposer_nodes.write() {
+            paxos_proposer_nodes.push(url.to_owned());
+            paxos_proposer_nodes.extend_from_slice(other_proposers);
+        }
+        node
+    }
+
+    #[actix_web::test]
+    async fn should_follow_proposer_with_higher_node_id() {
+        let node = enable_election(1, "http://proposer-1", &["http://proposer-2".to_owned()]);
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(heartbeat)
+                .service(get_leader),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/leader").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::post()
+            .uri("/heartbeat")
+            .set_json(Envelope::new(leader(2, "http://proposer-2")))
+            .to_request();
+        let followed: Leader = Encoding::Json
+            .decode(&test::call_and_read_body(&app, req).await)
+            .unwrap();
+        assert_eq!(followed, leader(2, "http://proposer-2"));
+
+        let req = test::TestRequest::post()
+            .uri("/heartbeat")
+            .set_json(Envelope::new(leader(0, "http://proposer-0")))
+            .to_request();
+        let followed: Leader = Encoding::Json
+            .decode(&test::call_and_read_body(&app, req).await)
+            .unwrap();
+        assert_eq!(followed, leader(2, "http://proposer-2"));
+
+        let req = test::TestRequest::get().uri("/leader").to_request();
+        let current: Leader = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(current, leader(2, "http://proposer-2"));
+    }
+
+    #[actix_web::test]
+    async fn should_forward_consensus_to_leader() {
+        let mut server = mockito::Server::new();
+        let node = enable_election(1, "http://proposer-1", &[server.url()]);
+        let mock_propose = server.mock("POST", "/propose").expect(0).create();
+        let mock_consensus = server
+            .mock("POST", "/consensus")
+            .match_header(FORWARDED_HEADER, "1")
+            .match_body("value")
+            .with_status(StatusCode::OK.as_u16() as usize)
+            .with_body("Value value accepted in slot 0!")
+            .create();
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.push(server.url());
+        }
+        follow(&node, leader(2, &server.url()));
+        let app =
+            test::init_service(App::new().app_data(node.clone()).service(consensus_start)).await;
+        let req = test::TestRequest::post()
+            .uri("/consensus")
+            .set_payload("value")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            to_bytes(resp.into_body()).await.unwrap(),
+            "Value value accepted in slot 0!"
+        );
+        mock_consensus.assert();
+        mock_propose.assert();
+    }
+
+    #[actix_web::test]
+    async fn should_not_run_phase_one_without_leader() {
+        let node = enable_election(1, "http://proposer-1", &["http://proposer-2".to_owned()]);
+        let app =
+            test::init_service(App::new().app_data(node.clone()).service(consensus_start)).await;
+        let req = test::TestRequest::post()
+            .uri("/consensus")
+            .set_payload("value")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn should_take_over_after_lease_expired() {
+        let mut server = mockito::Server::new();
+        let node = enable_election(1, "http://proposer-1", &[server.url()]);
+        node.lease_ms.store(50, Ordering::Release);
+        let mock_heartbeat = server
+            .mock("POST", "/heartbeat")
+            .match_body(Matcher::Json(
+                serde_json::to_value(Envelope::new(leader(1, "http://proposer-1"))).unwrap(),
+            ))
+            .with_status(StatusCode::OK.as_u16() as usize)
+            .with_body(
+                Encoding::Json
+                    .encode(&leader(1, "http://proposer-1"))
+                    .unwrap(),
+            )
+            .create();
+        // The server is the only acceptor as well
+        let mock_lease = server
+            .mock("POST", "/lease")
+            .with_status(StatusCode::OK.as_u16() as usize)
+            .with_body(
+                Encoding::Json
+                    .encode(&Lease {
+                        holder: leader(1, "http://proposer-1"),
+                        promised: Ballot::default(),
+                    })
+                    .unwrap(),
+            )
+            .create();
+        node.acceptor_nodes.write().unwrap().push(server.url());
+        follow(&node, leader(2, &server.url()));
+        tick(&node).await;
+        assert_eq!(current_leader(&node), Some(leader(2, &server.url())));
+
+        actix_web::rt::time::sleep(Duration::from_millis(60)).await;
+        tick(&node).await;
+        assert_eq!(current_leader(&node), Some(leader(1, "http://proposer-1")));
+        mock_heartbeat.assert();
+        mock_lease.assert();
+    }
+
+    #[actix_web::test]
+    async fn should_lose_lease_when_partitioned_from_acceptors() {
+        let lease = Duration::from_millis(200);
+        let acceptors = (3..=5)
+            .map(|node_id| new_node_with_id(node_id, &[Role::Acceptor, Role::Learner]))
+            .collect::<Vec<_>>();
+        let servers = acceptors.iter().map(start_node).collect::<Vec<_>>();
+        let urls = servers
+            .iter()
+            .map(|server| format!("http://{}", server.addr()))
+            .collect::<Vec<_>>();
+        // Never listening, the proposers do not hear from each other
+        let proposers = [
+            "http://127.0.0.1:1".to_owned(),
+            "http://127.0.0.1:2".to_owned(),
+        ];
+        let [old, new] = [1, 2].map(|node_id: usize| {
+            let node = enable_election(
+                node_id as u64,
+                &proposers[node_id - 1],
+                &[proposers[2 - node_id].clone()],
+            );
+            node.acceptor_nodes
+                .write()
+                .unwrap()
+                .extend_from_slice(&urls);
+            node.retry_deadline_ms.store(50, Ordering::Release);
+            node
+        });
+        for node in acceptors.iter().chain([&old, &new]) {
+            node.lease_ms
+                .store(lease.as_millis() as u64, Ordering::Release);
+        }
+
+        let sent = Instant::now();
+        tick(&old).await;
+        assert!(holds_lease(&old));
+        // Counted from when the acceptors were asked
+        let (_, expires) = old.lease.read().unwrap().clone().unwrap();
+        assert!(expires >= sent + lease && expires < Instant::now() + lease);
+        assert_eq!(propose_value(&old, "value").await.unwrap(), 0);
+
+        // The acceptors granted the lease to the old leader, they promise no other proposer
+        *old.acceptor_nodes.write().unwrap() = vec!["http://127.0.0.1:3".to_owned()];
+        tick(&new).await;
+        assert!(!holds_lease(&new));
+        assert!(propose_value(&new, "other value").await.is_err());
+
+        // Cut from the acceptors the old leader keeps its lease only until it expires
+        tick(&old).await;
+        assert!(holds_lease(&old));
+        actix_web::rt::time::sleep(lease).await;
+        assert!(!holds_lease(&old));
+
+        tick(&new).await;
+        assert!(holds_lease(&new));
+        assert_eq!(propose_value(&new, "other value").await.unwrap(), 1);
+
+        // Back with the acceptors, the old leader learns a higher ballot was promised
+        *old.acceptor_nodes.write().unwrap() = urls;
+        tick(&old).await;
+        assert!(!holds_lease(&old));
+        assert_eq!(*old.leader_proposal.read().unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn should_step_down_for_higher_node_id() {
+        let mut server = mockito::Server::new();
+        let node = enable_election(1, "http://proposer-1", &[server.url()]);
+        let mock_heartbeat = server
+            .mock("POST", "/heartbeat")
+            .with_status(StatusCode::OK.as_u16() as usize)
+            .with_body(Encoding::Json.encode(&leader(2, &server.url())).unwrap())
+            .create();
+        if let Ok(mut leader_proposal) = node.leader_proposal.write() {
+            *leader_proposal = Some(Ballot::new(1, 1));
+        }
+        tick(&node).await;
+        assert_eq!(current_leader(&node), Some(leader(2, &server.url())));
+        assert_eq!(*node.leader_proposal.read().unwrap(), None);
+        mock_heartbeat.assert();
+    }
+}
@@ -1,126 +1,463 @@
-use actix_web::{get, post, web, Error, HttpResponse};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::Ordering,
+};
+
+use actix_web::{get, post, web, Error, HttpRequest, HttpResponse};
 use futures_util::StreamExt as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    kv,
+    membership::{self, membership},
+    messages::{Accepted, Chosen, Encoding},
+    quorum::quorums,
+    read::{prepare_read, served_at, ReadMode},
+    wal::{self, Record},
+    Ballot, PaxosNode, Role, NO_OP,
+};
+
+/// Acceptors that accepted each ballot and value of a slot.
+pub type SlotAcceptances = HashMap<(Ballot, String), HashSet<u64>>;
+
+#[derive(Deserialize)]
+struct LogQuery {
+    from: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ValueQuery {
+    slot: Option<u64>,
+    #[serde(default)]
+    read: ReadMode,
+}
+
+/// State of a slot as seen by the learner, `accepted` lists the values accepted by fewer
+/// acceptors than a phase 2 quorum.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ValueState {
+    slot: u64,
+    chosen: Option<String>,
+    accepted: Vec<TentativeValue>,
+}
 
-use crate::{log, Accept, CURRENT_VALUE};
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct TentativeValue {
+    ballot: Ballot,
+    value: String,
+    acceptors: Vec<u64>,
+}
 
 #[post("/update_value")]
-async fn update_value(mut value: web::Payload) -> Result<HttpResponse, Error> {
+async fn update_value(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    mut value: web::Payload,
+) -> Result<HttpResponse, Error> {
     let mut bytes = web::BytesMut::new();
     while let Some(item) = value.next().await {
         bytes.extend_from_slice(&item?);
     }
-    match serde_json::from_slice::<Accept>(&bytes) {
+    match Encoding::of(&req) {
+        Ok(encoding) => Ok(receive_accepted(&node, encoding, &bytes).await),
+        Err(e) => Ok(e.into()),
+    }
+}
+
+/// Counts a value accepted by an acceptor, `bytes` is an `Accepted` in `encoding`.
+pub async fn receive_accepted(node: &PaxosNode, encoding: Encoding, bytes: &[u8]) -> HttpResponse {
+    if !node.has_role(Role::Learner) {
+        return HttpResponse::Forbidden().finish();
+    }
+    match encoding.decode::<Accepted>(bytes) {
         Ok(value) => {
-            if let Ok(mut current_value) = CURRENT_VALUE.write() {
-                *current_value = Some(value.value.to_string());
+            if !record_acceptance(node, &value) {
+                node.log("Learner: Value not chosen", &value.value).await;
+                return HttpResponse::Ok().finish();
+            }
+            if let Err(e) = learn(node, value.slot, &value.value) {
+                node.log("Learner: Value not persisted", &e.to_string())
+                    .await;
+                return HttpResponse::InternalServerError().finish();
             }
-            log("Leaner: Accepted value", value.value).await;
-            Ok(HttpResponse::Ok().finish())
+            node.log("Leaner: Accepted value", &value.value).await;
+            HttpResponse::Ok().finish()
         }
         Err(e) => {
-            log("Learner: Accept value not valid", &e.to_string()).await;
-            Ok(HttpResponse::BadRequest().finish())
+            node.log("Learner: Accept value not valid", &e.to_string())
+                .await;
+            e.into()
         }
     }
 }
 
+/// Returns the chosen and the tentatively accepted values of a slot, by default the latest one
+/// this learner heard of. With `?read=log` or `?read=lease` the default is the latest value
+/// chosen before the read, no-ops excluded.
 #[get("/value")]
-async fn get_value() -> Result<HttpResponse, Error> {
-    let current_value = CURRENT_VALUE.read().map_or(None, |v| v.clone());
-    if let Some(current_value) = current_value {
-        Ok(HttpResponse::Ok().body(current_value))
+async fn get_value(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    query: web::Query<ValueQuery>,
+) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Learner) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let ballot = match prepare_read(&node, &req, query.read).await {
+        Ok(ballot) => ballot,
+        Err(response) => return Ok(response),
+    };
+    let applied = node
+        .state_machine
+        .read()
+        .map_or(0, |state_machine| state_machine.applied());
+    let slot = if query.read == ReadMode::Local || query.slot.is_some() {
+        query.slot
     } else {
-        log("Learner: Get value not possible (value not set)", "").await;
+        let latest = node.decided_values.read().map_or(None, |decided_values| {
+            decided_values
+                .range(..applied)
+                .rev()
+                .find(|(_, value)| value.as_str() != NO_OP)
+                .map(|(slot, _)| *slot)
+        });
+        match latest {
+            Some(slot) => Some(slot),
+            None => {
+                return Ok(served_at(
+                    HttpResponse::NotFound().finish(),
+                    applied,
+                    ballot,
+                ))
+            }
+        }
+    };
+    let value_state = value_state(&node, slot);
+    if let Some(value_state) = value_state {
+        Ok(served_at(
+            HttpResponse::Ok().json(value_state),
+            applied,
+            ballot,
+        ))
+    } else {
+        node.log("Learner: Get value not possible (value not set)", "")
+            .await;
         Ok(HttpResponse::NotFound().finish())
     }
 }
 
+#[get("/log")]
+async fn get_log(
+    node: web::Data<PaxosNode>,
+    query: web::Query<LogQuery>,
+) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Learner) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let from = query.from.unwrap_or_default();
+    if let Ok(decided_values) = node.decided_values.read() {
+        Ok(HttpResponse::Ok().json(
+            decided_values
+                .range(from..)
+                .map(|(slot, value)| Chosen {
+                    slot: *slot,
+                    value: value.clone(),
+                })
+                .collect::<Vec<_>>(),
+        ))
+    } else {
+        Ok(HttpResponse::InternalServerError().finish())
+    }
+}
+
+fn value_state(node: &PaxosNode, slot: Option<u64>) -> Option<ValueState> {
+    let (Ok(decided_values), Ok(acceptances)) =
+        (node.decided_values.read(), node.acceptances.read())
+    else {
+        return None;
+    };
+    let slot = slot.or_else(|| {
+        decided_values
+            .keys()
+            .next_back()
+            .max(acceptances.keys().next_back())
+            .copied()
+    })?;
+    let mut accepted = acceptances
+        .get(&slot)
+        .map(|acceptances| {
+            acceptances
+                .iter()
+                .map(|((ballot, value), acceptors)| {
+                    let mut acceptors = acceptors.iter().copied().collect::<Vec<_>>();
+                    acceptors.sort_unstable();
+                    TentativeValue {
+                        ballot: *ballot,
+                        value: value.clone(),
+                        acceptors,
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    accepted.sort_unstable_by_key(|a| std::cmp::Reverse(a.ballot));
+    let chosen = decided_values.get(&slot).cloned();
+    (chosen.is_some() || !accepted.is_empty()).then_some(ValueState {
+        slot,
+        chosen,
+        accepted,
+    })
+}
+
+/// Counts the acceptance, returns true when enough acceptors to be a phase 2 quorum (a fast
+/// quorum in a fast window) accepted the same proposal, i.e. the value is chosen.
+fn record_acceptance(node: &PaxosNode, accepted: &Accepted) -> bool {
+    if node
+        .decided_values
+        .read()
+        .is_ok_and(|decided_values| decided_values.contains_key(&accepted.slot))
+    {
+        return false;
+    }
+    let acceptors = membership(node, accepted.slot).acceptors.len();
+    let quorum = if accepted.fast {
+        quorums(node).fast_size(acceptors)
+    } else {
+        quorums(node).phase2_size(acceptors)
+    };
+    node.acceptances.write().is_ok_and(|mut acceptances| {
+        let acceptors = acceptances
+            .entry(accepted.slot)
+            .or_default()
+            .entry((accepted.ballot, accepted.value.to_string()))
+            .or_default();
+        acceptors.insert(accepted.acceptor);
+        acceptors.len() >= quorum
+    })
+}
+
+/// Stores the value chosen for the slot and applies every command that became contiguous to
+/// the state machine.
+pub fn learn(node: &PaxosNode, slot: u64, value: &str) -> std::io::Result<()> {
+    let persisted = node.decided_values.write().map(|mut decided_values| {
+        // Slots before the log start are already applied through the snapshot
+        if decided_values.contains_key(&slot) || slot < node.log_start.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        wal::append(
+            node,
+            &Record::Learn {
+                slot,
+                value: value.to_string(),
+            },
+        )
+        .map(|_| {
+            decided_values.insert(slot, value.to_string());
+        })
+    });
+    match persisted {
+        Ok(persisted) => persisted?,
+        Err(_) => return Err(std::io::Error::other("decided values not available")),
+    }
+    if let Ok(mut acceptances) = node.acceptances.write() {
+        acceptances.remove(&slot);
+    }
+    membership::record(node, slot, value);
+    kv::apply_decided(node);
+    Ok(())
+}
+
+/// First slot of the log this node has not learned yet.
+pub fn first_undecided_slot(node: &PaxosNode) -> u64 {
+    let log_start = node.log_start.load(Ordering::Acquire);
+    node.decided_values
+        .read()
+        .map_or(log_start, |decided_values| {
+            decided_values
+                .keys()
+                .copied()
+                .zip(log_start..)
+                .find(|(slot, expected)| slot != expected)
+                .map_or(log_start + decided_values.len() as u64, |(_, expected)| {
+                    expected
+                })
+        })
+}
+
+/// Slot following the last one this node has learned.
+pub fn next_slot(node: &PaxosNode) -> u64 {
+    let log_start = node.log_start.load(Ordering::Acquire);
+    node.decided_values
+        .read()
+        .map_or(log_start, |decided_values| {
+            decided_values
+                .keys()
+                .next_back()
+                .map_or(log_start, |slot| slot + 1)
+        })
+}
+
 #[cfg(test)]
 mod tests {
-    use actix_web::{body::to_bytes, http::StatusCode, test, App};
+    use actix_web::{http::StatusCode, test, App};
     use futures_util::stream;
 
-    use crate::tests::reset_values;
+    use crate::{messages::Envelope, tests::new_node};
 
     use super::*;
 
     #[actix_web::test]
     async fn should_read_without_value() {
-        reset_values();
-        let app = test::init_service(App::new().service(get_value)).await;
+        let node = new_node(&[Role::Learner]);
+        let app = test::init_service(App::new().app_data(node.clone()).service(get_value)).await;
         let req = test::TestRequest::get().uri("/value").to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::NOT_FOUND);
     }
 
+    #[actix_web::test]
+    async fn should_not_learn_without_learner_role() {
+        let node = new_node(&[Role::Acceptor]);
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(update_value)
+                .service(get_log),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/update_value")
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accepted::new(1, 0, Ballot::new(1, 1), "value"))
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let req = test::TestRequest::get().uri("/log").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(node.decided_values.read().unwrap().is_empty());
+    }
+
     #[actix_web::test]
     async fn should_read_with_value() {
-        reset_values();
-        if let Ok(mut current_value) = CURRENT_VALUE.write() {
-            *current_value = Some("this is the current value".to_owned());
+        let node = new_node(&[Role::Learner]);
+        if let Ok(mut decided_values) = node.decided_values.write() {
+            decided_values.insert(0, "this is the current value".to_owned());
         }
-        let app = test::init_service(App::new().service(get_value)).await;
+        let app = test::init_service(App::new().app_data(node.clone()).service(get_value)).await;
         let req = test::TestRequest::get().uri("/value").to_request();
-        let resp = test::call_service(&app, req).await;
-        assert_eq!(resp.status(), StatusCode::OK);
-        let body = to_bytes(resp.into_body()).await.unwrap();
-        assert_eq!(body, "this is the current value");
+        let value_state: ValueState = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            value_state,
+            ValueState {
+                slot: 0,
+                chosen: Some("this is the current value".to_owned()),
+                accepted: vec![]
+            }
+        );
     }
 
     #[actix_web::test]
     async fn should_write_without_value() {
-        reset_values();
-        let app = test::init_service(App::new().service(update_value)).await;
+        let node = new_node(&[Role::Learner]);
+        let app = test::init_service(App::new().app_data(node.clone()).service(update_value)).await;
         let req = test::TestRequest::post()
             .uri("/update_value")
             .set_payload(
-                serde_json::to_string(&Accept::new(1, "this is another current value")).unwrap(),
+                Encoding::Json
+                    .encode(&Accepted::new(
+                        1,
+                        0,
+                        Ballot::new(0, 1),
+                        "this is another current value",
+                    ))
+                    .unwrap(),
             )
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
-        if let Ok(current_value) = CURRENT_VALUE.read() {
+        if let Ok(decided_values) = node.decided_values.read() {
             assert_eq!(
-                *current_value,
-                Some("this is another current value".to_owned())
+                decided_values.get(&0),
+                Some(&"this is another current value".to_owned())
             );
         } else {
             panic!("This should be present");
-        }
+        };
     }
 
     #[actix_web::test]
     async fn should_write_with_value() {
-        reset_values();
-        if let Ok(mut current_value) = CURRENT_VALUE.write() {
-            *current_value = Some("this is the current value".to_owned());
+        let node = new_node(&[Role::Learner]);
+        if let Ok(mut decided_values) = node.decided_values.write() {
+            decided_values.insert(0, "this is the current value".to_owned());
         }
-        let app = test::init_service(App::new().service(update_value)).await;
+        let app = test::init_service(App::new().app_data(node.clone()).service(update_value)).await;
         let req = test::TestRequest::post()
             .uri("/update_value")
             .set_payload(
-                serde_json::to_string(&Accept::new(1, "this is another current value")).unwrap(),
+                Encoding::Json
+                    .encode(&Accepted::new(
+                        1,
+                        1,
+                        Ballot::new(0, 1),
+                        "this is another current value",
+                    ))
+                    .unwrap(),
             )
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
-        if let Ok(current_value) = CURRENT_VALUE.read() {
+        if let Ok(decided_values) = node.decided_values.read() {
             assert_eq!(
-                *current_value,
-                Some("this is another current value".to_owned())
+                decided_values.get(&1),
+                Some(&"this is another current value".to_owned())
             );
         } else {
             panic!("This should be present");
+        };
+    }
+
+    #[actix_web::test]
+    async fn should_not_change_decided_value() {
+        let node = new_node(&[Role::Learner]);
+        if let Ok(mut decided_values) = node.decided_values.write() {
+            decided_values.insert(0, "this is the current value".to_owned());
         }
+        let app = test::init_service(App::new().app_data(node.clone()).service(update_value)).await;
+        let req = test::TestRequest::post()
+            .uri("/update_value")
+            .set_payload(
+                Encoding::Json
+                    .encode(&Accepted::new(
+                        1,
+                        0,
+                        Ballot::new(0, 1),
+                        "this is another current value",
+                    ))
+                    .unwrap(),
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            node.decided_values.read().unwrap().get(&0),
+            Some(&"this is the current value".to_owned())
+        );
     }
 
     #[actix_web::test]
     async fn should_write_with_value_and_multiple_calls() {
-        reset_values();
-        if let Ok(mut current_value) = CURRENT_VALUE.write() {
-            *current_value = Some("this is the current value".to_owned());
+        let node = new_node(&[Role::Learner]);
+        if let Ok(mut decided_values) = node.decided_values.write() {
+            decided_values.insert(0, "this is the current value".to_owned());
         }
-        let app = actix_test::start(|| App::new().service(update_value));
+        let data = node.clone();
+        let app =
+            actix_test::start(move || App::new().app_data(data.clone()).service(update_value));
         let client = reqwest::Client::new();
         let url = app.url("/update_value");
         let futures = stream::iter(
@@ -133,7 +470,11 @@ mod tests {
             tokio::spawn(async move {
                 client
                     .post(v.0)
-                    .body(serde_json::to_string(&Accept::new(1, &v.1)).unwrap())
+                    .body(
+                        Encoding::Json
+                            .encode(&Accepted::new(1, 0, Ballot::new(0, 1), &v.1))
+                            .unwrap(),
+                    )
                     .send()
                     .await
             })
@@ -145,10 +486,135 @@ mod tests {
                 assert_eq!(f.unwrap().unwrap().status(), StatusCode::OK);
             })
             .await;
-        if let Ok(current_value) = CURRENT_VALUE.read() {
-            assert_ne!(*current_value, None);
+        if let Ok(decided_values) = node.decided_values.read() {
+            assert!(decided_values.contains_key(&0));
         } else {
             panic!("This should be present");
+        };
+    }
+
+    #[actix_web::test]
+    async fn should_read_log_from_slot() {
+        let node = new_node(&[Role::Learner]);
+        if let Ok(mut decided_values) = node.decided_values.write() {
+            decided_values.extend([
+                (0, "first".to_owned()),
+                (1, "second".to_owned()),
+                (3, "fourth".to_owned()),
+            ]);
+        }
+        assert_eq!(first_undecided_slot(&node), 2);
+        assert_eq!(next_slot(&node), 4);
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(get_log)
+                .service(get_value),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/log?from=1").to_request();
+        let log: Vec<Chosen> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            log,
+            vec![
+                Chosen {
+                    slot: 1,
+                    value: "second".to_owned()
+                },
+                Chosen {
+                    slot: 3,
+                    value: "fourth".to_owned()
+                }
+            ]
+        );
+        let req = test::TestRequest::get().uri("/log").to_request();
+        let log: Vec<Chosen> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(log.len(), 3);
+        let req = test::TestRequest::get().uri("/value").to_request();
+        let value_state: ValueState = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(value_state.chosen, Some("fourth".to_owned()));
+        let req = test::TestRequest::get().uri("/value?slot=1").to_request();
+        let value_state: ValueState = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(value_state.chosen, Some("second".to_owned()));
+    }
+
+    #[actix_web::test]
+    async fn should_choose_value_only_with_majority_of_acceptors() {
+        let node = new_node(&[Role::Learner]);
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.extend(["a", "b", "c"].map(str::to_owned));
         }
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(update_value)
+                .service(get_value),
+        )
+        .await;
+        let acceptances = [
+            Accepted::new(1, 0, Ballot::new(1, 1), "first value"),
+            // Same acceptor again, it is not counted twice
+            Accepted::new(1, 0, Ballot::new(1, 1), "first value"),
+            // Different proposal, even with the same value, it is counted apart
+            Accepted::new(2, 0, Ballot::new(1, 2), "first value"),
+            Accepted::new(3, 0, Ballot::new(2, 1), "second value"),
+        ];
+        for accepted in &acceptances {
+            let req = test::TestRequest::post()
+                .uri("/update_value")
+                .set_json(Envelope::new(accepted))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert!(node.decided_values.read().unwrap().is_empty());
+        let req = test::TestRequest::get().uri("/value").to_request();
+        let value_state: ValueState = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            value_state,
+            ValueState {
+                slot: 0,
+                chosen: None,
+                accepted: vec![
+                    TentativeValue {
+                        ballot: Ballot::new(2, 1),
+                        value: "second value".to_owned(),
+                        acceptors: vec![3]
+                    },
+                    TentativeValue {
+                        ballot: Ballot::new(1, 2),
+                        value: "first value".to_owned(),
+                        acceptors: vec![2]
+                    },
+                    TentativeValue {
+                        ballot: Ballot::new(1, 1),
+                        value: "first value".to_owned(),
+                        acceptors: vec![1]
+                    }
+                ]
+            }
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/update_value")
+            .set_json(Envelope::new(Accepted::new(
+                2,
+                0,
+                Ballot::new(2, 1),
+                "second value",
+            )))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let req = test::TestRequest::get().uri("/value").to_request();
+        let value_state: ValueState = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            value_state,
+            ValueState {
+                slot: 0,
+                chosen: Some("second value".to_owned()),
+                accepted: vec![]
+            }
+        );
     }
 }
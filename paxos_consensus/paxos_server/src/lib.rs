@@ -0,0 +1,684 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    fs::File,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use actix_web::{rt::time::Instant, web};
+use lazy_static::lazy_static;
+use rand::{rngs::StdRng, SeedableRng};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    acceptor::{accept, any, propose},
+    admin::{get_health, get_ready, get_status},
+    batch::{Batcher, DEFAULT_BATCH_SIZE, DEFAULT_PIPELINE},
+    catch_up::{get_decided, get_snapshot, DEFAULT_CATCH_UP_MS, DEFAULT_SNAPSHOT_GAP},
+    compaction::{install_snapshot, DEFAULT_COMPACTION_MS, DEFAULT_SNAPSHOT_INTERVAL},
+    epaxos::{accept_epaxos, commit_epaxos, get_epaxos_key, pre_accept, submit_epaxos, Replica},
+    fast::{open_window, DEFAULT_FAST_WINDOW},
+    kv::{delete_key, get_key, put_key, submit_command, StateMachine},
+    leader::{get_leader, grant_lease, heartbeat, DEFAULT_LEASE_MS},
+    learner::{get_log, get_value, update_value, SlotAcceptances},
+    membership::{add_member, get_membership, remove_member, Reconfiguration, DEFAULT_ALPHA},
+    messages::{Any, Encoding, FastWindow},
+    metrics::{get_metrics, Metrics},
+    proposer::{consensus_start, DEFAULT_RETRY_BACKOFF_MS, DEFAULT_RETRY_DEADLINE_MS},
+    quorum::Quorums,
+    transport::{HttpTransport, Sent, Transport, DEFAULT_REQUEST_TIMEOUT_MS},
+};
+
+mod acceptor;
+pub mod admin;
+mod batch;
+mod catch_up;
+pub mod client;
+mod compaction;
+mod epaxos;
+mod fast;
+pub mod kv;
+mod leader;
+mod learner;
+mod membership;
+mod messages;
+mod metrics;
+mod proposer;
+pub mod quorum;
+mod read;
+pub mod session;
+pub mod simulator;
+mod transport;
+mod wal;
+
+lazy_static! {
+    static ref CLIENT: Client = reqwest::Client::new();
+}
+
+/// State of a node, every handler gets it from the app data so several nodes can run in the
+/// same process.
+pub struct PaxosNode {
+    node_id: u64,
+    // Where the events are sent, nothing is logged when empty (simulated nodes)
+    log_server: String,
+    roles: HashSet<Role>,
+    acceptor_nodes: RwLock<Vec<String>>,
+    learner_nodes: RwLock<Vec<String>>,
+    // Accepting phase, values decided for every slot of the replicated log
+    decided_values: RwLock<BTreeMap<u64, String>>,
+    // Slots before it are only in the installed snapshot, their values are not kept
+    log_start: AtomicU64,
+    // How often the learner asks its peers for the values it missed, and how many missing
+    // slots make it install a snapshot instead
+    catch_up_ms: AtomicU64,
+    snapshot_gap: AtomicU64,
+    // How many slots are applied between two snapshots, 0 never takes one, and how often the
+    // learner checks whether one is due
+    snapshot_interval: AtomicU64,
+    compaction_ms: AtomicU64,
+    // Accepting phase, the last ballot and value accepted by this acceptor per slot
+    accepted: RwLock<BTreeMap<u64, (Ballot, String)>>,
+    // Acceptances of the slots not chosen yet
+    acceptances: RwLock<BTreeMap<u64, SlotAcceptances>>,
+    // Proposal phase, highest round used or seen by this node and highest ballot promised
+    round: AtomicU64,
+    promised_ballot: RwLock<Ballot>,
+    // Draws the jitter of the retries, seeded by the simulator so a run can be replayed
+    rng: Mutex<StdRng>,
+    // Ballot that completed phase 1 for every slot, while set this node is the leader
+    leader_proposal: RwLock<Option<Ballot>>,
+    // Acceptors that promised the leader ballot, every group of acceptors in charge of a slot
+    // has to promise before the slot is proposed
+    prepared_acceptors: RwLock<Option<(Ballot, Vec<Vec<String>>)>>,
+    // Next free slot of the log, valid only while this node is the leader
+    next_slot: AtomicU64,
+    // Most values proposed in one slot and most slots proposed at the same time, the values
+    // submitted while every slot is in flight wait for the next batch
+    batch_size: AtomicU64,
+    pipeline: AtomicU64,
+    batcher: Mutex<Batcher>,
+    // Retry phase, how long a value keeps being proposed and the first backoff between rounds
+    retry_deadline_ms: AtomicU64,
+    retry_backoff_ms: AtomicU64,
+    // How long a request to a peer can take before it is given up
+    request_timeout_ms: AtomicU64,
+    // Proposers taking part in the election, this node included
+    proposer_nodes: RwLock<Vec<String>>,
+    // Address the other proposers use to reach this node
+    node_url: RwLock<String>,
+    // Leader followed by this node and when its lease expires
+    lease: RwLock<Option<(Leader, Instant)>>,
+    lease_ms: AtomicU64,
+    // Proposer this acceptor granted a lease to and when it expires, no other proposer is
+    // promised before
+    lease_grant: RwLock<Option<(Leader, Instant)>>,
+    // Until when this acceptor, restarted from its log, grants no lease and promises nothing
+    lease_fence: RwLock<Option<Instant>>,
+    // Reconfigurations decided in each slot, the one decided in slot `s` applies from `s + alpha`
+    reconfigurations: RwLock<BTreeMap<u64, Reconfiguration>>,
+    // How many slots can be proposed before the previous ones are decided
+    alpha: AtomicU64,
+    // Acceptors needed by each phase
+    quorums: RwLock<Quorums>,
+    // Most slots of a fast window, 0 disables Fast Paxos, the window opened by this leader and
+    // the one granted to this acceptor
+    fast_window_size: AtomicU64,
+    fast_window: RwLock<Option<FastWindow>>,
+    fast_grant: RwLock<Option<Any>>,
+    // Instances of the Egalitarian Paxos mode, with the store built executing them
+    epaxos: RwLock<Replica>,
+    // Key-value store built applying the decided commands in slot order
+    state_machine: RwLock<StateMachine>,
+    // Append-only log of the node and its path, when not opened nothing is persisted
+    wal: Mutex<Option<(File, PathBuf)>>,
+    // Counters and latencies exposed by `GET /metrics`
+    metrics: Metrics,
+    // Set once a shutdown started, the node is no longer ready
+    stopping: AtomicBool,
+    // Encoding of the messages sent to the peers, they answer in the same one
+    encoding: Encoding,
+    transport: Box<dyn Transport>,
+}
+
+impl PaxosNode {
+    fn new(
+        node_id: u64,
+        log_server: String,
+        roles: HashSet<Role>,
+        transport: Box<dyn Transport>,
+    ) -> Self {
+        Self {
+            node_id,
+            log_server,
+            roles,
+            acceptor_nodes: RwLock::new(Vec::new()),
+            learner_nodes: RwLock::new(Vec::new()),
+            decided_values: RwLock::new(BTreeMap::new()),
+            log_start: AtomicU64::new(0),
+            catch_up_ms: AtomicU64::new(DEFAULT_CATCH_UP_MS),
+            snapshot_gap: AtomicU64::new(DEFAULT_SNAPSHOT_GAP),
+            snapshot_interval: AtomicU64::new(DEFAULT_SNAPSHOT_INTERVAL),
+            compaction_ms: AtomicU64::new(DEFAULT_COMPACTION_MS),
+            accepted: RwLock::new(BTreeMap::new()),
+            acceptances: RwLock::new(BTreeMap::new()),
+            round: AtomicU64::new(0),
+            promised_ballot: RwLock::new(Ballot::default()),
+            rng: Mutex::new(StdRng::from_entropy()),
+            leader_proposal: RwLock::new(None),
+            prepared_acceptors: RwLock::new(None),
+            next_slot: AtomicU64::new(0),
+            batch_size: AtomicU64::new(DEFAULT_BATCH_SIZE),
+            pipeline: AtomicU64::new(DEFAULT_PIPELINE),
+            batcher: Mutex::new(Batcher::default()),
+            retry_deadline_ms: AtomicU64::new(DEFAULT_RETRY_DEADLINE_MS),
+            retry_backoff_ms: AtomicU64::new(DEFAULT_RETRY_BACKOFF_MS),
+            request_timeout_ms: AtomicU64::new(DEFAULT_REQUEST_TIMEOUT_MS),
+            proposer_nodes: RwLock::new(Vec::new()),
+            node_url: RwLock::new(String::new()),
+            lease: RwLock::new(None),
+            lease_ms: AtomicU64::new(DEFAULT_LEASE_MS),
+            lease_grant: RwLock::new(None),
+            lease_fence: RwLock::new(None),
+            reconfigurations: RwLock::new(BTreeMap::new()),
+            alpha: AtomicU64::new(DEFAULT_ALPHA),
+            quorums: RwLock::new(Quorums::default()),
+            fast_window_size: AtomicU64::new(DEFAULT_FAST_WINDOW),
+            fast_window: RwLock::new(None),
+            fast_grant: RwLock::new(None),
+            epaxos: RwLock::new(Replica::default()),
+            state_machine: RwLock::new(StateMachine::default()),
+            wal: Mutex::new(None),
+            metrics: Metrics::default(),
+            stopping: AtomicBool::new(false),
+            encoding: Encoding::Json,
+            transport,
+        }
+    }
+
+    /// Configures the node from the environment, the write-ahead log (if any) is replayed.
+    pub fn from_env() -> std::io::Result<Self> {
+        let node_id_value = std::env::var("NODE_ID").expect("Node id not set");
+        let node_id = node_id_value
+            .parse::<u64>()
+            .expect("Node id should be a number");
+        let log_server = std::env::var("LOG_SERVER").expect("Log server not set");
+        println!("Log server used: {}", log_server);
+        let node_roles = parse_roles(&std::env::var("PAXOS_ROLE").expect("Paxos roles not set"))?;
+        println!("Paxos roles: {:?}", node_roles);
+        let mut node = PaxosNode::new(node_id, log_server, node_roles, Box::new(HttpTransport));
+        if let Ok(encoding) = std::env::var("PAXOS_ENCODING") {
+            node.encoding = match encoding.as_str() {
+                "json" => Encoding::Json,
+                "bincode" => Encoding::Bincode,
+                _ => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Encoding not valid: {}", encoding),
+                    ))
+                }
+            };
+            println!("Paxos encoding: {:?}", node.encoding);
+        }
+        let paxos_acceptor_nodes_values =
+            std::env::var("PAXOS_ACCEPTOR_NODES").expect("Paxos acceptor nodes are not set");
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.extend(
+                paxos_acceptor_nodes_values
+                    .split(',')
+                    .map(|v| v.to_string()),
+            );
+            println!("Paxos nodes: {:?}", paxos_acceptor_nodes);
+        }
+
+        let paxos_learner_nodes_values =
+            std::env::var("PAXOS_LEARNER_NODES").expect("Paxos learner nodes are not set");
+        if let Ok(mut paxos_learner_nodes) = node.learner_nodes.write() {
+            paxos_learner_nodes
+                .extend(paxos_learner_nodes_values.split(',').map(|v| v.to_string()));
+            println!("Paxos nodes: {:?}", paxos_learner_nodes);
+        }
+
+        if let Ok(retry_deadline) = std::env::var("PAXOS_RETRY_DEADLINE_MS") {
+            node.retry_deadline_ms.store(
+                retry_deadline
+                    .parse::<u64>()
+                    .expect("Retry deadline should be a number"),
+                Ordering::Release,
+            );
+        }
+        if let Ok(retry_backoff) = std::env::var("PAXOS_RETRY_BACKOFF_MS") {
+            node.retry_backoff_ms.store(
+                retry_backoff
+                    .parse::<u64>()
+                    .expect("Retry backoff should be a number"),
+                Ordering::Release,
+            );
+        }
+
+        if let Ok(request_timeout) = std::env::var("PAXOS_REQUEST_TIMEOUT_MS") {
+            node.request_timeout_ms.store(
+                request_timeout
+                    .parse::<u64>()
+                    .expect("Request timeout should be a number"),
+                Ordering::Release,
+            );
+        }
+
+        if let Ok(batch_size) = std::env::var("PAXOS_BATCH_SIZE") {
+            node.batch_size.store(
+                batch_size
+                    .parse::<u64>()
+                    .expect("Batch size should be a number"),
+                Ordering::Release,
+            );
+        }
+        if let Ok(pipeline) = std::env::var("PAXOS_PIPELINE") {
+            node.pipeline.store(
+                pipeline
+                    .parse::<u64>()
+                    .expect("Pipeline should be a number"),
+                Ordering::Release,
+            );
+        }
+
+        if let Ok(paxos_proposer_nodes_values) = std::env::var("PAXOS_PROPOSER_NODES") {
+            let node_url_value = std::env::var("PAXOS_NODE_URL").expect("Paxos node url not set");
+            if let Ok(mut node_url) = node.node_url.write() {
+                *node_url = node_url_value;
+            }
+            if let Ok(mut paxos_proposer_nodes) = node.proposer_nodes.write() {
+                paxos_proposer_nodes.extend(
+                    paxos_proposer_nodes_values
+                        .split(',')
+                        .map(|v| v.to_string()),
+                );
+                println!("Paxos proposer nodes: {:?}", paxos_proposer_nodes);
+            }
+        }
+        if let Ok(lease) = std::env::var("PAXOS_LEASE_MS") {
+            node.lease_ms.store(
+                lease.parse::<u64>().expect("Lease should be a number"),
+                Ordering::Release,
+            );
+        }
+
+        if let Ok(alpha) = std::env::var("PAXOS_ALPHA") {
+            node.alpha.store(
+                alpha.parse::<u64>().expect("Alpha should be a number"),
+                Ordering::Release,
+            );
+        }
+
+        let quorums = Quorums::from_env()?;
+        let acceptors = node.acceptor_nodes.read().map_or(0, |n| n.len());
+        quorums
+            .validate(acceptors)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?;
+        println!("Paxos quorums: {}", quorums);
+        if let Ok(mut node_quorums) = node.quorums.write() {
+            *node_quorums = quorums;
+        }
+
+        if let Ok(fast_window) = std::env::var("PAXOS_FAST_WINDOW") {
+            node.fast_window_size.store(
+                fast_window
+                    .parse::<u64>()
+                    .expect("Fast window should be a number"),
+                Ordering::Release,
+            );
+        }
+
+        if let Ok(catch_up) = std::env::var("PAXOS_CATCH_UP_MS") {
+            node.catch_up_ms.store(
+                catch_up
+                    .parse::<u64>()
+                    .expect("Catch up interval should be a number"),
+                Ordering::Release,
+            );
+        }
+        if let Ok(snapshot_gap) = std::env::var("PAXOS_SNAPSHOT_GAP") {
+            node.snapshot_gap.store(
+                snapshot_gap
+                    .parse::<u64>()
+                    .expect("Snapshot gap should be a number"),
+                Ordering::Release,
+            );
+        }
+        if let Ok(snapshot_interval) = std::env::var("PAXOS_SNAPSHOT_INTERVAL") {
+            node.snapshot_interval.store(
+                snapshot_interval
+                    .parse::<u64>()
+                    .expect("Snapshot interval should be a number"),
+                Ordering::Release,
+            );
+        }
+        if let Ok(compaction) = std::env::var("PAXOS_COMPACTION_MS") {
+            node.compaction_ms.store(
+                compaction
+                    .parse::<u64>()
+                    .expect("Compaction interval should be a number"),
+                Ordering::Release,
+            );
+        }
+
+        if let Ok(wal_path) = std::env::var("PAXOS_WAL_PATH") {
+            let replayed = wal::open(&node, &wal_path)?;
+            println!(
+                "Write-ahead log used: {}, records replayed: {}",
+                wal_path, replayed
+            );
+        }
+        Ok(node)
+    }
+
+    /// Whether the node was configured with the role, every handler checks the role it needs.
+    fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout_ms.load(Ordering::Acquire))
+    }
+
+    /// Sends the message to an endpoint of a peer, fails with `TimedOut` when the peer does not
+    /// answer within the request timeout.
+    fn send<T: Serialize>(&self, url: &str, path: &str, message: &T) -> Sent<'_> {
+        match self.encoding.encode(message) {
+            Ok(body) => self.timed(self.transport.send(url, path, self.encoding, body)),
+            Err(e) => Box::pin(async move { Err(e) }),
+        }
+    }
+
+    /// Reads an endpoint of a peer, the answer is in the encoding of the node.
+    fn fetch(&self, url: &str, path: &str) -> Sent<'_> {
+        self.timed(self.transport.get(url, path, self.encoding))
+    }
+
+    fn timed<'a>(&self, sent: Sent<'a>) -> Sent<'a> {
+        let timeout = self.request_timeout();
+        Box::pin(async move {
+            actix_web::rt::time::timeout(timeout, sent)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(std::io::Error::new(
+                        ErrorKind::TimedOut,
+                        "request timed out",
+                    ))
+                })
+        })
+    }
+
+    async fn log(&self, message: &str, value: &str) {
+        if !self.log_server.is_empty() {
+            post_log(&self.log_server, self.request_timeout(), message, value).await
+        }
+    }
+}
+
+/// Sends the message, with the host name of the node, to the log server collecting the events
+/// of the whole cluster.
+pub async fn post_log(log_server: &str, timeout: Duration, message: &str, value: &str) {
+    println!("message: {}, value: {}", message, value);
+    let body = if value.is_empty() {
+        format!(
+            "{}, for node: {}",
+            message,
+            gethostname::gethostname().to_str().unwrap(),
+        )
+    } else {
+        format!(
+            "{}, for node: {}, for value: {}",
+            message,
+            gethostname::gethostname().to_str().unwrap(),
+            value
+        )
+    };
+    match CLIENT
+        .post(log_server)
+        .timeout(timeout)
+        .body(body)
+        .send()
+        .await
+    {
+        Ok(response) => {
+            if response.status() != reqwest::StatusCode::OK {
+                println!("Error sending log message: {}", response.status());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+}
+
+/// Proposal number, ordered by round and then by the id of the proposer, so two proposers never
+/// use the same one. The default ballot is lower than any ballot a proposer can use.
+#[derive(
+    Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug,
+)]
+pub struct Ballot {
+    round: u64,
+    node_id: u64,
+}
+
+impl Ballot {
+    fn new(round: u64, node_id: u64) -> Self {
+        Self { round, node_id }
+    }
+}
+
+impl fmt::Display for Ballot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.round, self.node_id)
+    }
+}
+
+/// Value used to fill log slots that were left empty by a previous leader.
+pub const NO_OP: &str = "";
+
+/// Proposer holding the leadership lease, sent as heartbeat by the leader.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Leader {
+    node_id: u64,
+    url: String,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Role {
+    Proposer,
+    Acceptor,
+    Learner,
+}
+
+impl TryFrom<String> for Role {
+    type Error = std::io::Error;
+
+    fn try_from(role: String) -> Result<Self, Self::Error> {
+        match role.as_str() {
+            "proposer" => Ok(Self::Proposer),
+            "acceptor" => Ok(Self::Acceptor),
+            "learner" => Ok(Self::Learner),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Role not valid: {}", role),
+            )),
+        }
+    }
+}
+
+/// Parses a comma separated list of roles, e.g. `proposer,learner`, every node can have any
+/// combination of them.
+fn parse_roles(roles: &str) -> std::io::Result<HashSet<Role>> {
+    roles
+        .split(',')
+        .map(|role| Role::try_from(role.trim().to_owned()))
+        .collect()
+}
+
+/// Registers every endpoint of the node, the node itself has to be in the app data.
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(consensus_start)
+        .service(propose)
+        .service(accept)
+        .service(any)
+        .service(open_window)
+        .service(update_value)
+        .service(get_value)
+        .service(get_log)
+        .service(get_decided)
+        .service(get_snapshot)
+        .service(install_snapshot)
+        .service(get_key)
+        .service(put_key)
+        .service(delete_key)
+        .service(submit_command)
+        .service(submit_epaxos)
+        .service(get_epaxos_key)
+        .service(pre_accept)
+        .service(accept_epaxos)
+        .service(commit_epaxos)
+        .service(heartbeat)
+        .service(grant_lease)
+        .service(get_leader)
+        .service(add_member)
+        .service(remove_member)
+        .service(get_membership)
+        .service(get_metrics)
+        .service(get_health)
+        .service(get_ready)
+        .service(get_status);
+}
+
+/// Starts what the node runs in background, the proposers keep electing a leader and the
+/// learners catch up with their peers and compact their log.
+pub fn spawn_tasks(node: &web::Data<PaxosNode>) {
+    if node.has_role(Role::Proposer) {
+        actix_web::rt::spawn(leader::run(node.clone()));
+    }
+    if node.has_role(Role::Learner) {
+        actix_web::rt::spawn(catch_up::run(node.clone()));
+        actix_web::rt::spawn(compaction::run(node.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+
+    use crate::messages::Chosen;
+
+    use super::*;
+
+    pub fn new_node(roles: &[Role]) -> web::Data<PaxosNode> {
+        new_node_with_id(0, roles)
+    }
+
+    pub fn new_node_with_id(node_id: u64, roles: &[Role]) -> web::Data<PaxosNode> {
+        web::Data::new(PaxosNode::new(
+            node_id,
+            "invalid-server".to_owned(),
+            roles.iter().copied().collect(),
+            Box::new(HttpTransport),
+        ))
+    }
+
+    /// Serves every endpoint of the node on a random port.
+    pub fn start_node(node: &web::Data<PaxosNode>) -> actix_test::TestServer {
+        let data = node.clone();
+        actix_test::start(move || App::new().app_data(data.clone()).configure(services))
+    }
+
+    #[actix_web::test]
+    async fn should_replicate_values_in_cluster() {
+        let nodes = (1..=3)
+            .map(|node_id| {
+                new_node_with_id(node_id, &[Role::Proposer, Role::Acceptor, Role::Learner])
+            })
+            .collect::<Vec<_>>();
+        let servers = nodes.iter().map(start_node).collect::<Vec<_>>();
+        let urls = servers
+            .iter()
+            .map(|server| format!("http://{}", server.addr()))
+            .collect::<Vec<_>>();
+        for (node, url) in nodes.iter().zip(&urls) {
+            node.acceptor_nodes
+                .write()
+                .unwrap()
+                .extend_from_slice(&urls);
+            node.learner_nodes.write().unwrap().extend_from_slice(&urls);
+            node.proposer_nodes
+                .write()
+                .unwrap()
+                .extend_from_slice(&urls);
+            *node.node_url.write().unwrap() = url.clone();
+        }
+        // The node with the highest id is elected, the others forward to it
+        leader::tick(&nodes[2]).await;
+        let client = reqwest::Client::new();
+        let leader: Leader = client
+            .get(servers[0].url("/leader"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(leader.node_id, 3);
+
+        let resp = client
+            .post(servers[0].url("/consensus"))
+            .body("value")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            resp.text().await.unwrap(),
+            "Value value accepted in slot 0!"
+        );
+        let resp = client
+            .put(servers[1].url("/kv/key"))
+            .body("kv value")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        // Every learner heard from a majority of the acceptors before the leader answered
+        for server in &servers {
+            let log: Vec<Chosen> = client
+                .get(server.url("/log"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(log.len(), 2);
+            assert_eq!(log[0].value, "value");
+            let resp = client.get(server.url("/kv/key")).send().await.unwrap();
+            assert_eq!(resp.text().await.unwrap(), "kv value");
+        }
+    }
+
+    #[test]
+    fn should_parse_any_set_of_roles() {
+        assert_eq!(
+            parse_roles("proposer,learner").unwrap(),
+            HashSet::from([Role::Proposer, Role::Learner])
+        );
+        assert_eq!(
+            parse_roles("acceptor").unwrap(),
+            HashSet::from([Role::Acceptor])
+        );
+        assert!(parse_roles("proposer,storage").is_err());
+    }
+}
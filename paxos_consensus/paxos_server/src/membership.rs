@@ -0,0 +1,480 @@
+use std::sync::atomic::Ordering;
+
+use actix_web::{get, post, web, Error, HttpRequest, HttpResponse};
+use futures_util::StreamExt as _;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    leader::{forward, leadership, Leadership},
+    learner::first_undecided_slot,
+    messages::ErrorBody,
+    proposer::propose_value,
+    quorum::quorums,
+    PaxosNode, Role,
+};
+
+pub const DEFAULT_ALPHA: u64 = 3;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Member {
+    Acceptor,
+    Learner,
+}
+
+/// Body of `/membership/add` and `/membership/remove`.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct MemberChange {
+    role: Member,
+    node: String,
+}
+
+/// Command changing the nodes of the cluster, decided as a system entry.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub enum Reconfiguration {
+    Add(MemberChange),
+    Remove(MemberChange),
+}
+
+/// Value of a log slot written only by the nodes, stored as JSON (`{"System":...}`). A client
+/// value that reads as one is decided inside a batch or refused, so it is never applied.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct System {
+    #[serde(rename = "System")]
+    reconfiguration: Reconfiguration,
+}
+
+impl Reconfiguration {
+    fn encode(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&System {
+            reconfiguration: self.clone(),
+        })
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        serde_json::from_str::<System>(value)
+            .ok()
+            .map(|system| system.reconfiguration)
+    }
+}
+
+/// Whether the value would be read as a system entry if it was decided as it is.
+pub fn is_system(value: &str) -> bool {
+    Reconfiguration::decode(value).is_some()
+}
+
+/// Acceptors and learners in charge of a slot.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Membership {
+    pub acceptors: Vec<String>,
+    pub learners: Vec<String>,
+}
+
+/// Body of a successful reconfiguration, the new membership is used from `effective_from`.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Reconfigured {
+    slot: u64,
+    effective_from: u64,
+}
+
+impl Membership {
+    fn apply(&mut self, reconfiguration: &Reconfiguration) {
+        match reconfiguration {
+            Reconfiguration::Add(MemberChange { role, node }) => {
+                let nodes = self.nodes(*role);
+                if !nodes.contains(node) {
+                    nodes.push(node.clone());
+                }
+            }
+            Reconfiguration::Remove(MemberChange { role, node }) => {
+                self.nodes(*role).retain(|n| n != node);
+            }
+        }
+    }
+
+    fn nodes(&mut self, role: Member) -> &mut Vec<String> {
+        match role {
+            Member::Acceptor => &mut self.acceptors,
+            Member::Learner => &mut self.learners,
+        }
+    }
+}
+
+pub fn alpha(node: &PaxosNode) -> u64 {
+    node.alpha.load(Ordering::Acquire).max(1)
+}
+
+/// Membership in charge of the slot, the nodes configured at startup changed by the
+/// reconfigurations decided at least `alpha` slots before.
+pub fn membership(node: &PaxosNode, slot: u64) -> Membership {
+    membership_until(node, (slot + 1).saturating_sub(alpha(node)))
+}
+
+/// Membership once every reconfiguration known by this node is applied.
+pub fn latest_membership(node: &PaxosNode) -> Membership {
+    membership_until(node, u64::MAX)
+}
+
+fn membership_until(node: &PaxosNode, slot: u64) -> Membership {
+    let mut membership = Membership {
+        acceptors: node.acceptor_nodes.read().map_or(vec![], |n| n.clone()),
+        learners: node.learner_nodes.read().map_or(vec![], |n| n.clone()),
+    };
+    if let Ok(reconfigurations) = node.reconfigurations.read() {
+        for reconfiguration in reconfigurations.range(..slot).map(|(_, r)| r) {
+            membership.apply(reconfiguration);
+        }
+    }
+    membership
+}
+
+/// Whether every reconfiguration that can change the membership of the slot is decided.
+pub fn is_known(node: &PaxosNode, slot: u64) -> bool {
+    slot < first_undecided_slot(node) + alpha(node)
+}
+
+/// Keeps track of the decided value if it is a reconfiguration.
+pub fn record(node: &PaxosNode, slot: u64, value: &str) {
+    if let Some(reconfiguration) = Reconfiguration::decode(value) {
+        if let Ok(mut reconfigurations) = node.reconfigurations.write() {
+            reconfigurations.insert(slot, reconfiguration);
+        }
+    }
+}
+
+async fn reconfigure(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    mut value: web::Payload,
+    path: &str,
+    reconfiguration: fn(MemberChange) -> Reconfiguration,
+) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Proposer) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let mut bytes = web::BytesMut::new();
+    while let Some(item) = value.next().await {
+        bytes.extend_from_slice(&item?);
+    }
+    let Ok(change) = serde_json::from_slice::<MemberChange>(&bytes) else {
+        node.log("Membership: Change not valid", "").await;
+        return Ok(HttpResponse::BadRequest().finish());
+    };
+    match leadership(&node, &req) {
+        Leadership::Local => {}
+        Leadership::Remote(leader) => {
+            return Ok(forward(&node, &req, &leader, Method::POST, path, bytes.to_vec()).await)
+        }
+        Leadership::Unknown => return Ok(HttpResponse::ServiceUnavailable().finish()),
+    }
+    let reconfiguration = reconfiguration(change);
+    let mut membership = latest_membership(&node);
+    membership.apply(&reconfiguration);
+    if membership.acceptors.is_empty() {
+        node.log("Membership: At least one acceptor is needed", "")
+            .await;
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+    if let Err(e) = quorums(&node).validate(membership.acceptors.len()) {
+        node.log("Membership: Quorums not valid", &e).await;
+        return Ok(HttpResponse::BadRequest().json(ErrorBody { error: e }));
+    }
+    let value = reconfiguration.encode()?;
+    node.log("Membership: Reconfiguration started", &value)
+        .await;
+    match propose_value(&node, &value).await {
+        Ok(slot) => Ok(HttpResponse::Ok().json(Reconfigured {
+            slot,
+            effective_from: slot + alpha(&node),
+        })),
+        Err(e) => {
+            node.log(
+                "Membership: Reconfiguration not decided",
+                &format!("{:?}", e),
+            )
+            .await;
+            Ok(e.into())
+        }
+    }
+}
+
+#[post("/membership/add")]
+async fn add_member(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    value: web::Payload,
+) -> Result<HttpResponse, Error> {
+    reconfigure(node, req, value, "/membership/add", Reconfiguration::Add).await
+}
+
+#[post("/membership/remove")]
+async fn remove_member(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    value: web::Payload,
+) -> Result<HttpResponse, Error> {
+    reconfigure(
+        node,
+        req,
+        value,
+        "/membership/remove",
+        Reconfiguration::Remove,
+    )
+    .await
+}
+
+#[get("/membership")]
+async fn get_membership(node: web::Data<PaxosNode>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(latest_membership(&node)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+    use futures::future::join_all;
+
+    use crate::{
+        acceptor::{accept, propose},
+        learner::learn,
+        proposer::consensus_start,
+        quorum::Quorums,
+        tests::new_node,
+    };
+
+    use super::*;
+
+    fn start_acceptor() -> (actix_test::TestServer, web::Data<PaxosNode>) {
+        let node = new_node(&[Role::Acceptor]);
+        let data = node.clone();
+        let server = actix_test::start(move || {
+            App::new()
+                .app_data(data.clone())
+                .service(propose)
+                .service(accept)
+        });
+        (server, node)
+    }
+
+    fn accepted_slots(node: &PaxosNode) -> Vec<u64> {
+        node.accepted.read().unwrap().keys().copied().collect()
+    }
+
+    fn change(role: Member, node: &str) -> MemberChange {
+        MemberChange {
+            role,
+            node: node.to_owned(),
+        }
+    }
+
+    fn proposals() -> Vec<test::TestRequest> {
+        (0..4)
+            .map(|i| {
+                test::TestRequest::post()
+                    .uri("/consensus")
+                    .set_payload(format!("value {i}"))
+            })
+            .collect()
+    }
+
+    fn assert_decided(node: &PaxosNode, amount: usize) {
+        let decided_values = node.decided_values.read().unwrap();
+        for i in 0..amount {
+            let value = format!("value {i}");
+            assert!(decided_values.values().any(|v| *v == value));
+        }
+    }
+
+    #[actix_web::test]
+    async fn should_apply_reconfiguration_after_alpha_slots() {
+        let node = new_node(&[Role::Learner]);
+        node.alpha.store(2, Ordering::Release);
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.push("http://acceptor-1".to_owned());
+        }
+        let reconfiguration = Reconfiguration::Add(change(Member::Acceptor, "http://acceptor-2"));
+        learn(&node, 0, &reconfiguration.encode().unwrap()).unwrap();
+        assert!(is_known(&node, 2));
+        assert!(!is_known(&node, 3));
+        assert_eq!(membership(&node, 1).acceptors, vec!["http://acceptor-1"]);
+        assert_eq!(
+            membership(&node, 2).acceptors,
+            vec!["http://acceptor-1", "http://acceptor-2"]
+        );
+        assert_eq!(
+            quorums(&node).phase2_size(membership(&node, 2).acceptors.len()),
+            2
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_add_acceptor_with_proposals_in_flight() {
+        let node = new_node(&[Role::Proposer, Role::Learner]);
+        node.alpha.store(2, Ordering::Release);
+        let (acceptor_1, acceptor_node_1) = start_acceptor();
+        let (acceptor_2, _) = start_acceptor();
+        let (acceptor_3, acceptor_node_3) = start_acceptor();
+        let urls = [&acceptor_1, &acceptor_2, &acceptor_3].map(|a| format!("http://{}", a.addr()));
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.extend_from_slice(&urls[..2]);
+        }
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(consensus_start)
+                .service(add_member)
+                .service(get_membership),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/membership/add")
+            .set_json(change(Member::Acceptor, &urls[2]))
+            .to_request();
+        let reconfigured: Reconfigured = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            reconfigured,
+            Reconfigured {
+                slot: 0,
+                effective_from: 2
+            }
+        );
+        let responses = join_all(
+            proposals()
+                .into_iter()
+                .map(|req| test::call_service(&app, req.to_request())),
+        )
+        .await;
+        for resp in responses {
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert_decided(&node, 4);
+
+        // The new acceptor only takes part in the slots after the alpha window
+        assert!(accepted_slots(&acceptor_node_1).starts_with(&[0, 1]));
+        let new_acceptor_slots = accepted_slots(&acceptor_node_3);
+        assert!(!new_acceptor_slots.is_empty());
+        assert!(new_acceptor_slots.iter().all(|slot| *slot >= 2));
+
+        let req = test::TestRequest::get().uri("/membership").to_request();
+        let current: Membership = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(current.acceptors, urls);
+    }
+
+    #[actix_web::test]
+    async fn should_remove_acceptor_with_proposals_in_flight() {
+        let node = new_node(&[Role::Proposer, Role::Learner]);
+        node.alpha.store(2, Ordering::Release);
+        let (acceptor_1, _) = start_acceptor();
+        let (acceptor_2, _) = start_acceptor();
+        let (acceptor_3, acceptor_node_3) = start_acceptor();
+        let urls = [&acceptor_1, &acceptor_2, &acceptor_3].map(|a| format!("http://{}", a.addr()));
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.extend_from_slice(&urls);
+        }
+        let app = test::init_service(
+            App::new()
+                .app_data(node.clone())
+                .service(consensus_start)
+                .service(remove_member)
+                .service(get_membership),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/membership/remove")
+            .set_json(change(Member::Acceptor, &urls[2]))
+            .to_request();
+        let reconfigured: Reconfigured = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(reconfigured.effective_from, 2);
+        let responses = join_all(
+            proposals()
+                .into_iter()
+                .map(|req| test::call_service(&app, req.to_request())),
+        )
+        .await;
+        for resp in responses {
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert_decided(&node, 4);
+
+        // The removed acceptor is not asked anything after the alpha window
+        assert!(accepted_slots(&acceptor_node_3)
+            .iter()
+            .all(|slot| *slot < 2));
+
+        let req = test::TestRequest::get().uri("/membership").to_request();
+        let current: Membership = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(current.acceptors, urls[..2]);
+    }
+
+    #[actix_web::test]
+    async fn should_not_reconfigure_with_client_value() {
+        let node = new_node(&[Role::Proposer, Role::Learner]);
+        let (acceptor, _) = start_acceptor();
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.push(format!("http://{}", acceptor.addr()));
+        }
+        let app =
+            test::init_service(App::new().app_data(node.clone()).service(consensus_start)).await;
+        let reconfiguration = Reconfiguration::Remove(change(
+            Member::Acceptor,
+            &format!("http://{}", acceptor.addr()),
+        ));
+        let value = reconfiguration.encode().unwrap();
+        for value in [value, serde_json::to_string(&reconfiguration).unwrap()] {
+            let req = test::TestRequest::post()
+                .uri("/consensus")
+                .set_payload(value)
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        }
+        assert!(node.reconfigurations.read().unwrap().is_empty());
+        assert_eq!(latest_membership(&node).acceptors.len(), 1);
+        // The value that reads as a system entry was decided inside a batch
+        let decided_values = node.decided_values.read().unwrap();
+        assert!(!is_system(&decided_values[&0]));
+        assert_eq!(
+            crate::batch::values(&decided_values[&0]),
+            vec![reconfiguration.encode().unwrap()]
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_keep_at_least_one_acceptor() {
+        let node = new_node(&[Role::Proposer]);
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.push("http://acceptor-1".to_owned());
+        }
+        let app =
+            test::init_service(App::new().app_data(node.clone()).service(remove_member)).await;
+        let req = test::TestRequest::post()
+            .uri("/membership/remove")
+            .set_json(change(Member::Acceptor, "http://acceptor-1"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn should_refuse_membership_breaking_quorum_intersection() {
+        let node = new_node(&[Role::Proposer]);
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.extend((1..=3).map(|i| format!("http://acceptor-{}", i)));
+        }
+        *node.quorums.write().unwrap() = Quorums::Sizes {
+            phase1: 2,
+            phase2: 2,
+        };
+        let app = test::init_service(App::new().app_data(node.clone()).service(add_member)).await;
+        // With 4 acceptors two quorums of 2 may not intersect
+        let req = test::TestRequest::post()
+            .uri("/membership/add")
+            .set_json(change(Member::Acceptor, "http://acceptor-4"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(node.decided_values.read().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
+
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{kv::Command, membership::Reconfiguration, session::Session, Ballot, Leader};
+
+/// Version of the messages exchanged between nodes, a node refuses messages of other versions.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const JSON: &str = "application/json";
+const BINCODE: &str = "application/x-bincode";
+
+/// Body of every message between nodes, the version comes first so it can be checked before
+/// reading the rest.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Envelope<T> {
+    pub version: u32,
+    pub message: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(message: T) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            message,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+/// How messages are written on the wire, chosen by the content type of the request and used
+/// for its response as well.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Json,
+    Bincode,
+}
+
+impl Encoding {
+    /// Encoding of a body with the given content type, JSON when it is not set.
+    pub fn from_content_type(content_type: Option<&str>) -> Result<Self, MessageError> {
+        let Some(content_type) = content_type else {
+            return Ok(Self::Json);
+        };
+        match content_type.split(';').next().map(str::trim) {
+            Some(JSON) => Ok(Self::Json),
+            Some(BINCODE) => Ok(Self::Bincode),
+            _ => Err(MessageError::UnsupportedContentType(
+                content_type.to_owned(),
+            )),
+        }
+    }
+
+    pub fn of(req: &HttpRequest) -> Result<Self, MessageError> {
+        Self::from_content_type(
+            req.headers()
+                .get(actix_web::http::header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok()),
+        )
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => JSON,
+            Self::Bincode => BINCODE,
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, message: &T) -> std::io::Result<Vec<u8>> {
+        let envelope = Envelope::new(message);
+        match self {
+            Self::Json => Ok(serde_json::to_vec(&envelope)?),
+            Self::Bincode => bincode::serialize(&envelope).map_err(std::io::Error::other),
+        }
+    }
+
+    pub fn decode<'a, T: Deserialize<'a>>(self, bytes: &'a [u8]) -> Result<T, MessageError> {
+        let version = match self {
+            Self::Json => serde_json::from_slice::<Version>(bytes).map_err(MessageError::from),
+            Self::Bincode => bincode::deserialize::<Version>(bytes).map_err(MessageError::from),
+        }?;
+        if version.version != PROTOCOL_VERSION {
+            return Err(MessageError::UnsupportedVersion(version.version));
+        }
+        let envelope = match self {
+            Self::Json => serde_json::from_slice::<Envelope<T>>(bytes).map_err(MessageError::from),
+            Self::Bincode => bincode::deserialize::<Envelope<T>>(bytes).map_err(MessageError::from),
+        }?;
+        Ok(envelope.message)
+    }
+
+    /// Response carrying the message, in this encoding.
+    pub fn respond<T: Serialize>(self, status: StatusCode, message: &T) -> HttpResponse {
+        match self.encode(message) {
+            Ok(body) => HttpResponse::build(status)
+                .content_type(self.content_type())
+                .body(body),
+            Err(e) => HttpResponse::InternalServerError().json(ErrorBody {
+                error: e.to_string(),
+            }),
+        }
+    }
+}
+
+/// Reasons a message could not be read.
+#[derive(Debug, PartialEq)]
+pub enum MessageError {
+    UnsupportedContentType(String),
+    UnsupportedVersion(u32),
+    Malformed(String),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedContentType(content_type) => {
+                write!(f, "Content type not supported: {}", content_type)
+            }
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Protocol version not supported: {}, expected {}",
+                version, PROTOCOL_VERSION
+            ),
+            Self::Malformed(e) => write!(f, "Message not valid: {}", e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for MessageError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Malformed(e.to_string())
+    }
+}
+
+impl From<bincode::Error> for MessageError {
+    fn from(e: bincode::Error) -> Self {
+        Self::Malformed(e.to_string())
+    }
+}
+
+impl From<MessageError> for HttpResponse {
+    fn from(error: MessageError) -> Self {
+        let status = match error {
+            MessageError::UnsupportedContentType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            MessageError::UnsupportedVersion(_) | MessageError::Malformed(_) => {
+                StatusCode::BAD_REQUEST
+            }
+        };
+        HttpResponse::build(status).json(ErrorBody {
+            error: error.to_string(),
+        })
+    }
+}
+
+/// Body of the responses to requests that could not be handled, always JSON.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+/// Body of `/propose`, the promise covers every slot starting from `slot`.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Prepare {
+    pub ballot: Ballot,
+    pub slot: u64,
+}
+
+impl Prepare {
+    pub fn new(ballot: Ballot, slot: u64) -> Self {
+        Self { ballot, slot }
+    }
+}
+
+/// Body of a successful `/propose` response, it carries the proposals already accepted by the
+/// acceptor for the slots covered by the promise so the proposer can adopt their values.
+#[derive(Deserialize, Serialize, Default, Debug, PartialEq)]
+pub struct Promise {
+    pub accepted: Vec<AcceptedEntry>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct AcceptedEntry {
+    pub slot: u64,
+    pub ballot: Ballot,
+    pub value: String,
+}
+
+/// Body of a `406 Not Acceptable` from an acceptor, the proposer can retry with a ballot higher
+/// than `promised`.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Nack {
+    pub promised: Ballot,
+}
+
+/// Body of a `/lease` response, the proposer holding the lease granted by the acceptor and the
+/// highest ballot the acceptor promised: the leader runs phase 1 again when it is higher than
+/// its own ballot.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Lease {
+    pub holder: Leader,
+    pub promised: Ballot,
+}
+
+/// Body of `/accept`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Accept<'a> {
+    pub slot: u64,
+    pub ballot: Ballot,
+    // Borrowed unless the JSON string contains escapes
+    #[serde(borrow)]
+    pub value: Cow<'a, str>,
+    // Sent by a client in a fast window rather than by the leader
+    #[serde(default)]
+    pub fast: bool,
+}
+
+impl<'a> Accept<'a> {
+    pub fn new(slot: u64, ballot: Ballot, value: &'a str) -> Self {
+        Self {
+            slot,
+            ballot,
+            value: Cow::Borrowed(value),
+            fast: false,
+        }
+    }
+}
+
+/// Body of `/any`, the leader lets the acceptors accept any value sent by the clients for the
+/// slots from `from` to `to` (excluded) in its ballot.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Any {
+    pub ballot: Ballot,
+    pub from: u64,
+    pub to: u64,
+}
+
+/// Body of a successful `POST /fast`: the clients send their values straight to the acceptors
+/// in the slots of the window, a value is chosen once `fast_quorum` acceptors accepted it.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FastWindow {
+    pub ballot: Ballot,
+    pub from: u64,
+    pub to: u64,
+    pub acceptors: Vec<String>,
+    pub fast_quorum: usize,
+}
+
+/// Sent by an acceptor to every learner for each value it accepts, and as body of a successful
+/// `/accept` response.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Accepted<'a> {
+    pub acceptor: u64,
+    pub slot: u64,
+    pub ballot: Ballot,
+    #[serde(borrow)]
+    pub value: Cow<'a, str>,
+    // Accepted in a fast window, chosen only by a fast quorum
+    #[serde(default)]
+    pub fast: bool,
+}
+
+impl<'a> Accepted<'a> {
+    pub fn new(acceptor: u64, slot: u64, ballot: Ballot, value: &'a str) -> Self {
+        Self {
+            acceptor,
+            slot,
+            ballot,
+            value: Cow::Borrowed(value),
+            fast: false,
+        }
+    }
+}
+
+/// Value chosen for a slot of the replicated log, returned by the learners.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Chosen {
+    pub slot: u64,
+    pub value: String,
+}
+
+/// Body of a `/decided` response, the decided values from the requested slot. `log_start` is
+/// the first slot the peer still has the value of, the slots before it are only in its snapshot.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Decided {
+    pub entries: Vec<Chosen>,
+    pub log_start: u64,
+    pub first_undecided: u64,
+}
+
+/// State of a node once every slot before `slot` is applied, sent instead of the values of
+/// those slots.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub slot: u64,
+    pub data: HashMap<String, String>,
+    pub sessions: HashMap<String, Session>,
+    pub reconfigurations: BTreeMap<u64, Reconfiguration>,
+}
+
+/// Instance of the Egalitarian Paxos log, every replica leads the instances with its node id.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct InstanceId {
+    pub replica: u64,
+    pub instance: u64,
+}
+
+/// Body of `/epaxos/pre_accept`, `/epaxos/accept` and `/epaxos/commit`: the command of an
+/// instance with the attributes ordering it against the interfering ones.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Instance {
+    pub id: InstanceId,
+    pub command: Command,
+    pub seq: u64,
+    pub deps: BTreeSet<InstanceId>,
+}
+
+/// Body of a successful answer to the EPaxos messages, the attributes of the instance on the
+/// replica: the instances it depends on and a sequence number higher than theirs.
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq, Eq)]
+pub struct Attributes {
+    pub seq: u64,
+    pub deps: BTreeSet<InstanceId>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_and_decode_messages() {
+        let accept = Accept::new(3, Ballot::new(2, 1), "value \"quoted\"");
+        for encoding in [Encoding::Json, Encoding::Bincode] {
+            let bytes = encoding.encode(&accept).unwrap();
+            assert_eq!(encoding.decode::<Accept>(&bytes), Ok(accept.clone()));
+        }
+        assert!(
+            Encoding::Bincode.encode(&accept).unwrap().len()
+                < Encoding::Json.encode(&accept).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn should_select_encoding_by_content_type() {
+        assert_eq!(Encoding::from_content_type(None), Ok(Encoding::Json));
+        assert_eq!(
+            Encoding::from_content_type(Some("application/json; charset=utf-8")),
+            Ok(Encoding::Json)
+        );
+        assert_eq!(
+            Encoding::from_content_type(Some("application/x-bincode")),
+            Ok(Encoding::Bincode)
+        );
+        assert_eq!(
+            Encoding::from_content_type(Some("text/plain")),
+            Err(MessageError::UnsupportedContentType(
+                "text/plain".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn should_refuse_other_versions_and_malformed_messages() {
+        let bytes = serde_json::to_vec(&Envelope {
+            version: PROTOCOL_VERSION + 1,
+            message: Prepare::new(Ballot::new(1, 1), 0),
+        })
+        .unwrap();
+        assert_eq!(
+            Encoding::Json.decode::<Prepare>(&bytes),
+            Err(MessageError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+        );
+        assert!(matches!(
+            Encoding::Json.decode::<Prepare>(b"12"),
+            Err(MessageError::Malformed(_))
+        ));
+        assert!(matches!(
+            Encoding::Bincode.decode::<Prepare>(&[1, 0, 0, 0]),
+            Err(MessageError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use actix_web::{get, web, Error, HttpResponse};
+
+use crate::PaxosNode;
+
+/// Upper bounds, in seconds, of the buckets of the round latency histogram.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Durations counted in cumulative buckets, the sum is kept in microseconds.
+pub struct Histogram {
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: LATENCY_BUCKETS.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// What a node counts since it started, exposed to Prometheus by `GET /metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    // Acceptor side, answers to the phase 1 and phase 2 requests
+    pub promises_granted: Counter,
+    pub promises_rejected: Counter,
+    pub accepts_accepted: Counter,
+    pub accepts_rejected: Counter,
+    // Proposer side, every attempt to decide a value with a ballot is a round
+    pub rounds_started: Counter,
+    pub rounds_succeeded: Counter,
+    pub rounds_failed: Counter,
+    pub round_latency: Histogram,
+    // Rounds started again with a higher ballot after a rejected one
+    pub retries: Counter,
+}
+
+fn counter(out: &mut String, name: &str, help: &str, samples: &[(&str, u64)]) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{labels} {value}");
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{le=\"{bound}\"}} {}",
+            bucket.load(Ordering::Relaxed)
+        );
+    }
+    let count = histogram.count.load(Ordering::Relaxed);
+    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+    let _ = writeln!(
+        out,
+        "{name}_sum {}",
+        histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    );
+    let _ = writeln!(out, "{name}_count {count}");
+}
+
+/// The metrics of the node in the Prometheus text format.
+pub fn render(node: &PaxosNode) -> String {
+    let metrics = &node.metrics;
+    let mut out = String::new();
+    counter(
+        &mut out,
+        "paxos_promises_total",
+        "Phase 1 requests answered by this acceptor.",
+        &[
+            ("{result=\"granted\"}", metrics.promises_granted.get()),
+            ("{result=\"rejected\"}", metrics.promises_rejected.get()),
+        ],
+    );
+    counter(
+        &mut out,
+        "paxos_accepts_total",
+        "Phase 2 requests answered by this acceptor.",
+        &[
+            ("{result=\"accepted\"}", metrics.accepts_accepted.get()),
+            ("{result=\"rejected\"}", metrics.accepts_rejected.get()),
+        ],
+    );
+    counter(
+        &mut out,
+        "paxos_rounds_started_total",
+        "Rounds started by this proposer.",
+        &[("", metrics.rounds_started.get())],
+    );
+    counter(
+        &mut out,
+        "paxos_rounds_succeeded_total",
+        "Rounds of this proposer that decided their value.",
+        &[("", metrics.rounds_succeeded.get())],
+    );
+    counter(
+        &mut out,
+        "paxos_rounds_failed_total",
+        "Rounds of this proposer that did not decide their value.",
+        &[("", metrics.rounds_failed.get())],
+    );
+    counter(
+        &mut out,
+        "paxos_retries_total",
+        "Rounds started again with a higher ballot after a rejected one.",
+        &[("", metrics.retries.get())],
+    );
+    histogram(
+        &mut out,
+        "paxos_round_duration_seconds",
+        "Time taken by the rounds of this proposer, failed ones included.",
+        &metrics.round_latency,
+    );
+    let promised = node
+        .promised_ballot
+        .read()
+        .map_or(Default::default(), |b| *b);
+    gauge(
+        &mut out,
+        "paxos_promised_ballot_round",
+        "Round of the highest ballot promised by this acceptor.",
+        promised.round,
+    );
+    gauge(
+        &mut out,
+        "paxos_promised_ballot_node_id",
+        "Node id of the highest ballot promised by this acceptor.",
+        promised.node_id,
+    );
+    out
+}
+
+#[get("/metrics")]
+async fn get_metrics(node: web::Data<PaxosNode>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(render(&node)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        messages::{Envelope, Prepare},
+        proposer::propose_value,
+        tests::{new_node_with_id, start_node},
+        Ballot, Role,
+    };
+
+    #[actix_web::test]
+    async fn should_count_rejected_round_and_retry() {
+        let node = new_node_with_id(1, &[Role::Proposer, Role::Acceptor, Role::Learner]);
+        let server = start_node(&node);
+        let url = format!("http://{}", server.addr());
+        node.acceptor_nodes.write().unwrap().push(url.clone());
+        node.learner_nodes.write().unwrap().push(url);
+        let client = reqwest::Client::new();
+        // Another proposer is promised first, the first round of this node is rejected
+        let resp = client
+            .post(server.url("/propose"))
+            .json(&Envelope::new(Prepare::new(Ballot::new(3, 2), 0)))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(propose_value(&node, "value").await.unwrap(), 0);
+
+        let resp = client.get(server.url("/metrics")).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let body = resp.text().await.unwrap();
+        for line in [
+            "paxos_promises_total{result=\"granted\"} 2",
+            "paxos_promises_total{result=\"rejected\"} 1",
+            "paxos_accepts_total{result=\"accepted\"} 1",
+            "paxos_accepts_total{result=\"rejected\"} 0",
+            "paxos_rounds_started_total 2",
+            "paxos_rounds_succeeded_total 1",
+            "paxos_rounds_failed_total 1",
+            "paxos_retries_total 1",
+            "paxos_round_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "paxos_round_duration_seconds_count 2",
+            "paxos_promised_ballot_round 4",
+            "paxos_promised_ballot_node_id 1",
+        ] {
+            assert!(body.lines().any(|l| l == line), "{line} not in {body}");
+        }
+    }
+}
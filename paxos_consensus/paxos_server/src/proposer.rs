@@ -1,139 +1,499 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::atomic::Ordering,
+    time::Duration,
+};
 
-use actix_web::{post, web, Error, HttpResponse};
-use futures::future::join_all;
+use actix_web::{post, rt::time::Instant, web, Error, HttpRequest, HttpResponse};
+use futures::{future::join_all, stream::FuturesUnordered};
 use futures_util::StreamExt as _;
-use lazy_static::lazy_static;
-use reqwest::StatusCode;
+use rand::Rng;
+use reqwest::{Method, StatusCode};
 
-use crate::{log, Accept, Role, CLIENT, NODE_ID, NODE_ROLE, PAXOS_ACCEPTOR_NODES};
+use crate::{
+    batch::submit,
+    catch_up::catch_up,
+    fast::{self, Vote},
+    leader::{forward, leadership, Leadership},
+    learner::{first_undecided_slot, learn, next_slot},
+    membership::{self, membership},
+    messages::{Accept, ErrorBody, Nack, Prepare, Promise},
+    quorum::quorums,
+    session::{self, ClientRequest},
+    transport::Reply,
+    wal::{self, Record},
+    Ballot, PaxosNode, Role, NO_OP,
+};
 
-lazy_static! {
-    // Proposal phase
-    pub static ref PROPOSAL_ID: AtomicU64 = AtomicU64::new(0);
-    pub static ref PROPOSAL_NUMBER_TO_IGNORE: AtomicU64 = AtomicU64::new(0);
-}
+pub const DEFAULT_RETRY_DEADLINE_MS: u64 = 5000;
+pub const DEFAULT_RETRY_BACKOFF_MS: u64 = 20;
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const MEMBERSHIP_POLL: Duration = Duration::from_millis(1);
 
 #[post("/consensus")]
-async fn consensus_start(mut value: web::Payload) -> Result<HttpResponse, Error> {
-    let role = *NODE_ROLE.read().unwrap();
-    if role != Role::Proposer {
+async fn consensus_start(
+    node: web::Data<PaxosNode>,
+    req: HttpRequest,
+    mut value: web::Payload,
+) -> Result<HttpResponse, Error> {
+    if !node.has_role(Role::Proposer) {
         return Ok(HttpResponse::Forbidden().finish());
     }
     let mut bytes = web::BytesMut::new();
     while let Some(item) = value.next().await {
         bytes.extend_from_slice(&item?);
     }
-    let value = bytes.escape_ascii().to_string();
-    log("Proposer: Consensus started", &value).await;
-    let proposal_number = get_next_id();
-    let mut acceptors_amount = 0;
-    let futures = PAXOS_ACCEPTOR_NODES.read().map(|paxos_acceptor_nodes| {
-        acceptors_amount = paxos_acceptor_nodes.len();
-        paxos_acceptor_nodes
-            .iter()
-            .map(|n| {
-                CLIENT
-                    .post(format!("{}/propose", n))
-                    .body(proposal_number.to_string())
-                    .send()
-            })
-            .collect::<Vec<_>>()
-    });
-    let futures = if let Ok(futures) = futures {
-        join_all(futures).await
+    // Only the leader runs phase 1, otherwise the proposers keep preempting each other
+    match leadership(&node, &req) {
+        Leadership::Local => {}
+        Leadership::Remote(leader) => {
+            return Ok(forward(
+                &node,
+                &req,
+                &leader,
+                Method::POST,
+                "/consensus",
+                bytes.to_vec(),
+            )
+            .await)
+        }
+        Leadership::Unknown => return Ok(HttpResponse::ServiceUnavailable().finish()),
+    }
+    let value = match String::from_utf8(bytes.to_vec()) {
+        Ok(value) => value,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(ErrorBody {
+                error: format!("Value not valid UTF-8: {}", e),
+            }))
+        }
+    };
+    let request = match ClientRequest::of(&req, value.clone()) {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+    // A client falling back after a collision finds its request applied if the recovery chose it
+    fast::close(&node).await;
+    let proposed = match &request {
+        Some(request) => match session::applied(&node, request) {
+            Ok(Some(session)) => {
+                return Ok(accepted_in(&value, session.slot));
+            }
+            Ok(None) => request.encode()?,
+            Err(response) => return Ok(response),
+        },
+        None => value.clone(),
+    };
+    node.log("Proposer: Consensus started", &proposed).await;
+    match submit(&node, &proposed).await {
+        Ok((slot, _)) => {
+            // A retry decided again keeps the slot of the first attempt
+            let slot = request
+                .and_then(|request| session::applied(&node, &request).ok().flatten())
+                .map_or(slot, |session| session.slot);
+            Ok(accepted_in(&value, slot))
+        }
+        Err(e) => Ok(e.into()),
+    }
+}
+
+fn accepted_in(value: &str, slot: u64) -> HttpResponse {
+    HttpResponse::Ok().body(format!("Value {value} accepted in slot {slot}!"))
+}
+
+/// Reasons a value could not be decided by this proposer.
+#[derive(Debug)]
+pub enum ProposeError {
+    /// A majority was not reached, with the highest ballot promised by the acceptors
+    /// that refused (if any)
+    NotAccepted(Option<Ballot>),
+    Storage(std::io::Error),
+}
+
+impl From<ProposeError> for HttpResponse {
+    fn from(error: ProposeError) -> Self {
+        match error {
+            ProposeError::NotAccepted(_) => HttpResponse::NotAcceptable().json(ErrorBody {
+                error: "Value not accepted by a majority of the acceptors".to_owned(),
+            }),
+            ProposeError::Storage(e) => HttpResponse::InternalServerError().json(ErrorBody {
+                error: format!("Value not persisted: {}", e),
+            }),
+        }
+    }
+}
+
+/// Appends the value to the next free slot of the log, returns the slot once the value is
+/// chosen. Rejected rounds are retried with a higher ballot, after a randomized
+/// exponential backoff, until the retry deadline expires.
+pub async fn propose_value(node: &PaxosNode, value: &str) -> Result<u64, ProposeError> {
+    // The slots of an open fast window have to be completed before the following ones apply
+    fast::close(node).await;
+    let deadline =
+        Instant::now() + Duration::from_millis(node.retry_deadline_ms.load(Ordering::Acquire));
+    let mut backoff = Duration::from_millis(node.retry_backoff_ms.load(Ordering::Acquire));
+    loop {
+        match try_propose_value(node, value).await {
+            Err(ProposeError::NotAccepted(promised)) if Instant::now() + backoff < deadline => {
+                node.metrics.retries.inc();
+                if let Some(promised) = promised {
+                    restore_ballot(node, promised);
+                }
+                let delay = node
+                    .rng
+                    .lock()
+                    .map_or(backoff, |mut rng| rng.gen_range(backoff / 2..=backoff));
+                node.log("Proposer: Retrying after", &format!("{:?}", delay))
+                    .await;
+                actix_web::rt::time::sleep(delay).await;
+                backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// One round, phase 1 is only run when this node is not the leader yet.
+async fn try_propose_value(node: &PaxosNode, value: &str) -> Result<u64, ProposeError> {
+    node.metrics.rounds_started.inc();
+    let started = Instant::now();
+    let result = propose_in_next_slot(node, value).await;
+    node.metrics.round_latency.observe(started.elapsed());
+    if result.is_ok() {
+        node.metrics.rounds_succeeded.inc();
     } else {
-        vec![]
+        node.metrics.rounds_failed.inc();
+    }
+    result
+}
+
+async fn propose_in_next_slot(node: &PaxosNode, value: &str) -> Result<u64, ProposeError> {
+    let ballot = lead(node).await?;
+    let slot = node.next_slot.fetch_add(1, Ordering::AcqRel);
+    accept_in_slot(node, slot, ballot, value)
+        .await
+        .map(|_| slot)
+        .inspect_err(|e| {
+            if let ProposeError::NotAccepted(_) = e {
+                // Another proposer took over or the acceptors changed, the next request has to
+                // run phase 1 again
+                step_down(node, ballot);
+            }
+        })
+}
+
+/// Phase 2 for a slot taken while being the leader, the acceptors of the slot are known only
+/// once the slots `alpha` before it are decided.
+async fn accept_in_slot(
+    node: &PaxosNode,
+    slot: u64,
+    ballot: Ballot,
+    value: &str,
+) -> Result<(), ProposeError> {
+    let deadline =
+        Instant::now() + Duration::from_millis(node.retry_deadline_ms.load(Ordering::Acquire));
+    while !membership::is_known(node, slot) {
+        if Instant::now() >= deadline {
+            return Err(ProposeError::NotAccepted(None));
+        }
+        actix_web::rt::time::sleep(MEMBERSHIP_POLL).await;
+    }
+    let acceptors = membership(node, slot).acceptors;
+    if !is_prepared(node, ballot, &acceptors) {
+        node.log("Proposer: Acceptors changed", &slot.to_string())
+            .await;
+        return Err(ProposeError::NotAccepted(None));
+    }
+    accept_value(node, slot, ballot, value).await
+}
+
+/// Whether the acceptors promised the ballot of this leader.
+pub fn is_prepared(node: &PaxosNode, ballot: Ballot, acceptors: &[String]) -> bool {
+    node.prepared_acceptors.read().is_ok_and(|prepared| {
+        prepared.as_ref().is_some_and(|(b, groups)| {
+            *b == ballot && groups.iter().any(|group| group == acceptors)
+        })
+    })
+}
+
+/// Ballot this node leads with, phase 1 is run first when it is not the leader.
+pub async fn lead(node: &PaxosNode) -> Result<Ballot, ProposeError> {
+    let leader_proposal = node.leader_proposal.read().map_or(None, |l| *l);
+    match leader_proposal {
+        Some(ballot) => Ok(ballot),
+        None => become_leader(node).await,
+    }
+}
+
+/// Runs phase 1 for every slot not yet learned by this node and completes the slots that
+/// other proposers left accepted but maybe not chosen, returns the ballot to use for the
+/// following slots. Slots are completed `alpha` at a time as the acceptors of a slot depend on
+/// the reconfigurations decided before it.
+async fn become_leader(node: &PaxosNode) -> Result<Ballot, ProposeError> {
+    let ballot = next_ballot(node);
+    // A ballot must never be reused after a restart
+    if let Err(e) = wal::append(node, &Record::Proposal(ballot)) {
+        node.log("Proposer: Proposal not persisted", &e.to_string())
+            .await;
+        return Err(ProposeError::Storage(e));
+    }
+    let from_slot = first_undecided_slot(node);
+    let mut prepared: Vec<Vec<String>> = Vec::new();
+    let mut accepted: BTreeMap<u64, (Ballot, String)> = BTreeMap::new();
+    let mut window_start = from_slot;
+    loop {
+        let window_end = window_start + membership::alpha(node);
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        for slot in window_start..window_end {
+            let acceptors = membership(node, slot).acceptors;
+            if !prepared.contains(&acceptors) && !groups.contains(&acceptors) {
+                groups.push(acceptors);
+            }
+        }
+        if !groups.is_empty() {
+            for (slot, (b, value)) in prepare(node, ballot, from_slot, &groups).await? {
+                if accepted.get(&slot).map(|(p, _)| *p) < Some(b) {
+                    accepted.insert(slot, (b, value));
+                }
+            }
+            prepared.extend(groups);
+        }
+        let last_slot = accepted
+            .keys()
+            .next_back()
+            .map_or(from_slot, |slot| slot + 1)
+            .max(next_slot(node))
+            .max(node.next_slot.load(Ordering::Acquire));
+        if window_start >= last_slot {
+            break;
+        }
+        let futures = (window_start..window_end.min(last_slot))
+            .filter(|slot| {
+                !node
+                    .decided_values
+                    .read()
+                    .is_ok_and(|decided_values| decided_values.contains_key(slot))
+            })
+            .map(|slot| {
+                let value = accepted.get(&slot).map_or(NO_OP, |(_, value)| value);
+                accept_value(node, slot, ballot, value)
+            })
+            .collect::<Vec<_>>();
+        join_all(futures)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        window_start = window_end.min(last_slot);
+    }
+    if let Ok(mut prepared_acceptors) = node.prepared_acceptors.write() {
+        *prepared_acceptors = Some((ballot, prepared));
+    }
+    if let Ok(mut leader_proposal) = node.leader_proposal.write() {
+        *leader_proposal = Some(ballot);
+    }
+    node.next_slot.fetch_max(window_start, Ordering::AcqRel);
+    node.log("Proposer: Leader", &ballot.to_string()).await;
+    Ok(ballot)
+}
+
+/// Phase 1, returns for each slot the value to propose among the ones accepted with the highest
+/// ballot (see `fast::choose`) if a phase 1 quorum of every group of acceptors promised. It
+/// returns as soon as the quorums promised, the requests still running are dropped. When
+/// acceptors truncated `slot` this node catches up first, the next round starts after it.
+async fn prepare(
+    node: &PaxosNode,
+    ballot: Ballot,
+    slot: u64,
+    groups: &[Vec<String>],
+) -> Result<BTreeMap<u64, (Ballot, String)>, ProposeError> {
+    let mut acceptors: Vec<&String> = groups.iter().flatten().collect();
+    acceptors.sort_unstable();
+    acceptors.dedup();
+    let mut replies = acceptors
+        .into_iter()
+        .map(|n| {
+            let reply = node.send(n, "/propose", &Prepare::new(ballot, slot));
+            async move { (n, reply.await) }
+        })
+        .collect::<FuturesUnordered<_>>();
+    let mut promised = HashSet::new();
+    let mut highest_promised = None;
+    let mut compacted = false;
+    let mut votes: BTreeMap<u64, Vec<Vote>> = BTreeMap::new();
+    let quorums = quorums(node);
+    let majorities = |promised: &HashSet<&String>| {
+        groups
+            .iter()
+            .all(|group| quorums.is_phase1(group, promised))
     };
-    let mut promised_amount = 0;
-    for response in futures {
+    while let Some((acceptor, response)) = replies.next().await {
         match response {
             Ok(v) => {
-                if v.status() == StatusCode::OK {
-                    log("Proposer: Propose sent", &proposal_number.to_string()).await;
-                    promised_amount += 1;
+                if v.status == StatusCode::OK {
+                    node.log("Proposer: Propose sent", &ballot.to_string())
+                        .await;
+                    match v.decode::<Promise>() {
+                        Ok(promise) => {
+                            promised.insert(acceptor);
+                            for accepted in promise.accepted {
+                                votes.entry(accepted.slot).or_default().push((
+                                    acceptor,
+                                    accepted.ballot,
+                                    accepted.value,
+                                ));
+                            }
+                        }
+                        Err(e) => {
+                            node.log("Proposer: Promise not valid", &e.to_string())
+                                .await
+                        }
+                    }
                 } else {
-                    log(
-                        "Proposer: Propose sent with errors",
-                        &v.status().to_string(),
-                    )
-                    .await;
+                    node.log("Proposer: Propose sent with errors", &v.status.to_string())
+                        .await;
+                    compacted |= v.status == StatusCode::GONE;
+                    highest_promised = highest_promised.max(nack_promised(&v));
                 }
             }
-            Err(e) => log("Proposer: Error", &e.to_string()).await,
-        }
-    }
-
-    if promised_amount > acceptors_amount / 2 {
-        let mut acceptors_accepted_amount = 0;
-        let futures = PAXOS_ACCEPTOR_NODES.read().map(|paxos_acceptor_nodes| {
-            acceptors_accepted_amount = paxos_acceptor_nodes.len();
-            paxos_acceptor_nodes
-                .iter()
-                .map(|n| {
-                    CLIENT
-                        .post(format!("{}/accept", n))
-                        .json(&Accept::new(proposal_number, &value))
-                        .send()
-                })
-                .collect::<Vec<_>>()
-        });
-        let futures = if let Ok(futures) = futures {
-            join_all(futures).await
-        } else {
-            vec![]
-        };
-        let mut accepted_amount = 0;
-        for response in futures {
-            match response {
-                Ok(v) => {
-                    if v.status() == StatusCode::ACCEPTED {
-                        log("Proposer: Accept sent", &proposal_number.to_string()).await;
-                        accepted_amount += 1;
-                    } else {
-                        log("Proposer: Accept sent with errors", &v.status().to_string()).await;
-                    }
+            Err(e) => node.log("Proposer: Error", &e.to_string()).await,
+        }
+        if majorities(&promised) {
+            break;
+        }
+    }
+    if majorities(&promised) {
+        Ok(votes
+            .into_iter()
+            .filter_map(|(slot, votes)| {
+                let acceptors = membership(node, slot).acceptors;
+                fast::choose(&quorums, &acceptors, &promised, votes).map(|value| (slot, value))
+            })
+            .collect())
+    } else {
+        if compacted {
+            // The decided values of the truncated slots are only in the snapshots of the learners
+            node.log("Proposer: Slots compacted, catching up", &slot.to_string())
+                .await;
+            catch_up(node).await;
+        }
+        Err(ProposeError::NotAccepted(highest_promised))
+    }
+}
+
+/// Phase 2, succeeds as soon as a phase 2 quorum of the acceptors of the slot accepted the
+/// value, the value is chosen and learned by this node as well.
+async fn accept_value(
+    node: &PaxosNode,
+    slot: u64,
+    ballot: Ballot,
+    value: &str,
+) -> Result<(), ProposeError> {
+    let acceptors = membership(node, slot).acceptors;
+    let quorums = quorums(node);
+    let mut replies = acceptors
+        .iter()
+        .map(|n| {
+            let reply = node.send(n, "/accept", &Accept::new(slot, ballot, value));
+            async move { (n, reply.await) }
+        })
+        .collect::<FuturesUnordered<_>>();
+    let mut accepted = HashSet::new();
+    let mut highest_promised = None;
+    while let Some((acceptor, response)) = replies.next().await {
+        match response {
+            Ok(v) => {
+                if v.status == StatusCode::ACCEPTED {
+                    node.log("Proposer: Accept sent", &ballot.to_string()).await;
+                    accepted.insert(acceptor);
+                } else {
+                    node.log("Proposer: Accept sent with errors", &v.status.to_string())
+                        .await;
+                    highest_promised = highest_promised.max(nack_promised(&v));
                 }
-                Err(e) => log("Proposer: Error", &e.to_string()).await,
             }
+            Err(e) => node.log("Proposer: Error", &e.to_string()).await,
+        }
+        if quorums.is_phase2(&acceptors, &accepted) {
+            break;
         }
-        if accepted_amount > acceptors_amount / 2 {
-            return Ok(HttpResponse::Ok().body(format!("Value {value} accepted!")));
+    }
+    // The acceptors that did not answer yet are not waited for
+    drop(replies);
+    if quorums.is_phase2(&acceptors, &accepted) {
+        if let Err(e) = learn(node, slot, value) {
+            node.log("Proposer: Value not persisted", &e.to_string())
+                .await;
+            return Err(ProposeError::Storage(e));
+        }
+        Ok(())
+    } else {
+        Err(ProposeError::NotAccepted(highest_promised))
+    }
+}
+
+/// Promised ballot carried by a NACK, if the reply is one.
+pub fn nack_promised(reply: &Reply) -> Option<Ballot> {
+    if reply.status != StatusCode::NOT_ACCEPTABLE {
+        return None;
+    }
+    reply.decode::<Nack>().ok().map(|nack| nack.promised)
+}
+
+pub fn step_down(node: &PaxosNode, ballot: Ballot) {
+    if let Ok(mut leader_proposal) = node.leader_proposal.write() {
+        if *leader_proposal == Some(ballot) {
+            *leader_proposal = None;
         }
     }
-    Ok(HttpResponse::NotAcceptable().finish())
 }
 
-fn get_next_id() -> u64 {
-    PROPOSAL_ID.fetch_add(1, Ordering::Release) * 10 + NODE_ID.load(Ordering::Acquire)
+fn next_ballot(node: &PaxosNode) -> Ballot {
+    Ballot::new(node.round.fetch_add(1, Ordering::AcqRel) + 1, node.node_id)
+}
+
+/// Moves the round past a ballot already used by this node or refused by an acceptor.
+pub fn restore_ballot(node: &PaxosNode, ballot: Ballot) {
+    node.round.fetch_max(ballot.round, Ordering::AcqRel);
+}
+
+/// Promises the ballot unless a higher one was already promised, returns the ballot promised
+/// before.
+pub fn promise(node: &PaxosNode, ballot: Ballot) -> Ballot {
+    node.promised_ballot
+        .write()
+        .map_or(Ballot::default(), |mut promised| {
+            let previous = *promised;
+            *promised = previous.max(ballot);
+            previous
+        })
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::atomic::Ordering;
 
-    use actix_web::{http::StatusCode, test, App};
+    use actix_web::{body::to_bytes, http::StatusCode, test, web, App, HttpResponse};
+    use mockito::Matcher;
 
     use crate::{
         acceptor::{accept, propose},
-        proposer::{consensus_start, get_next_id, PROPOSAL_ID},
-        tests::reset_values,
-        Role, CURRENT_VALUE, NODE_ID, NODE_ROLE, PAXOS_ACCEPTOR_NODES,
+        learner::{get_log, update_value},
+        messages::{Accept, AcceptedEntry, Chosen, Encoding, Envelope, Nack, Prepare, Promise},
+        proposer::{consensus_start, next_ballot, propose_value, restore_ballot, ProposeError},
+        tests::{new_node, new_node_with_id, start_node},
+        Ballot, Role, NO_OP,
     };
 
     #[actix_web::test]
     async fn should_start_consensus_process() {
-        reset_values();
-        if let Ok(mut node_role) = NODE_ROLE.write() {
-            *node_role = Role::Proposer;
-        }
+        let node = new_node(&[Role::Proposer]);
         let mut server = mockito::Server::new();
-        if let Ok(mut paxos_acceptor_nodes) = PAXOS_ACCEPTOR_NODES.write() {
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
             paxos_acceptor_nodes.push(server.url());
         }
         let mock_propose = server
             .mock("POST", "/propose")
             .with_status(StatusCode::OK.as_u16() as usize)
+            .with_body(Encoding::Json.encode(&Promise::default()).unwrap())
             .create();
         let mock_accept = server
             .mock("POST", "/accept")
@@ -141,6 +501,7 @@ mod tests {
             .create();
         let app = test::init_service(
             App::new()
+                .app_data(node.clone())
                 .service(consensus_start)
                 .service(propose)
                 .service(accept),
@@ -154,19 +515,418 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::OK);
         mock_propose.assert();
         mock_accept.assert();
-        assert_eq!(*CURRENT_VALUE.read().unwrap(), None);
+        assert_eq!(
+            node.decided_values.read().unwrap().get(&0),
+            Some(&"this is a value".to_owned())
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_skip_phase_one_while_leader() {
+        let node = new_node(&[Role::Proposer]);
+        let mut server = mockito::Server::new();
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.push(server.url());
+        }
+        let mock_propose = server
+            .mock("POST", "/propose")
+            .with_status(StatusCode::OK.as_u16() as usize)
+            .with_body(Encoding::Json.encode(&Promise::default()).unwrap())
+            .expect(1)
+            .create();
+        let mock_accept = server
+            .mock("POST", "/accept")
+            .with_status(StatusCode::ACCEPTED.as_u16() as usize)
+            .expect(3)
+            .create();
+        let app =
+            test::init_service(App::new().app_data(node.clone()).service(consensus_start)).await;
+        for slot in 0..3 {
+            let req = test::TestRequest::post()
+                .uri("/consensus")
+                .set_payload(format!("value {slot}"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = to_bytes(resp.into_body()).await.unwrap();
+            assert_eq!(body, format!("Value value {slot} accepted in slot {slot}!"));
+        }
+        mock_propose.assert();
+        mock_accept.assert();
+        assert_eq!(node.next_slot.load(Ordering::Acquire), 3);
+    }
+
+    #[actix_web::test]
+    async fn should_run_phase_one_again_after_rejected_accept() {
+        let node = new_node(&[Role::Proposer]);
+        node.retry_deadline_ms.store(0, Ordering::Release);
+        let mut server = mockito::Server::new();
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.push(server.url());
+        }
+        let mock_propose = server
+            .mock("POST", "/propose")
+            .with_status(StatusCode::OK.as_u16() as usize)
+            .with_body(Encoding::Json.encode(&Promise::default()).unwrap())
+            .create();
+        let mock_accept = server
+            .mock("POST", "/accept")
+            .with_status(StatusCode::NOT_ACCEPTABLE.as_u16() as usize)
+            .create();
+        let app =
+            test::init_service(App::new().app_data(node.clone()).service(consensus_start)).await;
+        let req = test::TestRequest::post()
+            .uri("/consensus")
+            .set_payload("this is a value")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+        mock_propose.assert();
+        mock_accept.assert();
+        assert_eq!(*node.leader_proposal.read().unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn should_retry_with_ballot_higher_than_nack() {
+        let node = new_node_with_id(1, &[Role::Proposer]);
+        let mut server = mockito::Server::new();
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.push(server.url());
+        }
+        let mock_rejected_propose = server
+            .mock("POST", "/propose")
+            .match_body(Matcher::Json(
+                serde_json::to_value(Envelope::new(Prepare::new(Ballot::new(1, 1), 0))).unwrap(),
+            ))
+            .with_status(StatusCode::NOT_ACCEPTABLE.as_u16() as usize)
+            .with_body(
+                Encoding::Json
+                    .encode(&Nack {
+                        promised: Ballot::new(5, 2),
+                    })
+                    .unwrap(),
+            )
+            .create();
+        let mock_propose = server
+            .mock("POST", "/propose")
+            .match_body(Matcher::Json(
+                serde_json::to_value(Envelope::new(Prepare::new(Ballot::new(6, 1), 0))).unwrap(),
+            ))
+            .with_status(StatusCode::OK.as_u16() as usize)
+            .with_body(Encoding::Json.encode(&Promise::default()).unwrap())
+            .create();
+        let mock_accept = server
+            .mock("POST", "/accept")
+            .match_body(Matcher::Json(
+                serde_json::to_value(Envelope::new(Accept::new(
+                    0,
+                    Ballot::new(6, 1),
+                    "this is a value",
+                )))
+                .unwrap(),
+            ))
+            .with_status(StatusCode::ACCEPTED.as_u16() as usize)
+            .create();
+        let app =
+            test::init_service(App::new().app_data(node.clone()).service(consensus_start)).await;
+        let req = test::TestRequest::post()
+            .uri("/consensus")
+            .set_payload("this is a value")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        mock_rejected_propose.assert();
+        mock_propose.assert();
+        mock_accept.assert();
+        assert_eq!(
+            *node.leader_proposal.read().unwrap(),
+            Some(Ballot::new(6, 1))
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_stop_retrying_after_deadline() {
+        let node = new_node(&[Role::Proposer]);
+        node.retry_deadline_ms.store(200, Ordering::Release);
+        node.retry_backoff_ms.store(10, Ordering::Release);
+        let mut server = mockito::Server::new();
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.push(server.url());
+        }
+        let mock_propose = server
+            .mock("POST", "/propose")
+            .with_status(StatusCode::NOT_ACCEPTABLE.as_u16() as usize)
+            .with_body(
+                Encoding::Json
+                    .encode(&Nack {
+                        promised: Ballot::new(100, 0),
+                    })
+                    .unwrap(),
+            )
+            .expect_at_least(2)
+            .create();
+        let app =
+            test::init_service(App::new().app_data(node.clone()).service(consensus_start)).await;
+        let started = std::time::Instant::now();
+        let req = test::TestRequest::post()
+            .uri("/consensus")
+            .set_payload("this is a value")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+        assert!(started.elapsed() < std::time::Duration::from_millis(400));
+        mock_propose.assert();
+        assert!(node.round.load(Ordering::Acquire) > 100);
+    }
+
+    /// Acceptor that takes longer to answer than any request timeout used by the tests.
+    fn start_hung_acceptor() -> actix_test::TestServer {
+        actix_test::start(|| {
+            App::new().default_service(web::to(|| async {
+                actix_web::rt::time::sleep(std::time::Duration::from_secs(2)).await;
+                HttpResponse::Ok().finish()
+            }))
+        })
+    }
+
+    #[actix_web::test]
+    async fn should_proceed_with_majority_without_waiting_for_hung_acceptor() {
+        let node = new_node_with_id(1, &[Role::Proposer]);
+        node.request_timeout_ms.store(5000, Ordering::Release);
+        let first_acceptor = new_node(&[Role::Acceptor]);
+        let second_acceptor = new_node(&[Role::Acceptor]);
+        let servers = [start_node(&first_acceptor), start_node(&second_acceptor)];
+        let hung_acceptor = start_hung_acceptor();
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.extend(servers.iter().map(|s| format!("http://{}", s.addr())));
+            paxos_acceptor_nodes.push(format!("http://{}", hung_acceptor.addr()));
+        }
+        let started = std::time::Instant::now();
+        assert_eq!(propose_value(&node, "value").await.unwrap(), 0);
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(
+            node.decided_values.read().unwrap().get(&0),
+            Some(&"value".to_owned())
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_give_up_requests_after_timeout() {
+        let node = new_node_with_id(1, &[Role::Proposer]);
+        node.request_timeout_ms.store(100, Ordering::Release);
+        node.retry_deadline_ms.store(300, Ordering::Release);
+        let acceptor = new_node(&[Role::Acceptor]);
+        let server = start_node(&acceptor);
+        let hung_acceptors = [start_hung_acceptor(), start_hung_acceptor()];
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.push(format!("http://{}", server.addr()));
+            paxos_acceptor_nodes.extend(
+                hung_acceptors
+                    .iter()
+                    .map(|s| format!("http://{}", s.addr())),
+            );
+        }
+        let started = std::time::Instant::now();
+        assert!(matches!(
+            propose_value(&node, "value").await,
+            Err(ProposeError::NotAccepted(None))
+        ));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[actix_web::test]
+    async fn should_recover_values_with_highest_accepted_proposal() {
+        let node = new_node_with_id(1, &[Role::Proposer]);
+        node.round.store(9, Ordering::Release);
+        // With two acceptors the majority needs both promises, with more acceptors phase 1 stops
+        // at the first majority
+        let mut servers = [mockito::Server::new(), mockito::Server::new()];
+        if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+            paxos_acceptor_nodes.extend(servers.iter().map(|s| s.url()));
+        }
+        let entry = |slot, ballot, value: &str| AcceptedEntry {
+            slot,
+            ballot,
+            value: value.to_owned(),
+        };
+        let promises = [
+            Promise {
+                accepted: vec![entry(0, Ballot::new(4, 2), "older value")],
+            },
+            Promise {
+                accepted: vec![
+                    entry(0, Ballot::new(7, 3), "newer value"),
+                    entry(2, Ballot::new(7, 3), "third value"),
+                ],
+            },
+        ];
+        let expected_accepts = [
+            Accept::new(0, Ballot::new(10, 1), "newer value"),
+            Accept::new(1, Ballot::new(10, 1), NO_OP),
+            Accept::new(2, Ballot::new(10, 1), "third value"),
+            Accept::new(3, Ballot::new(10, 1), "client value"),
+        ];
+        let mocks = servers
+            .iter_mut()
+            .zip(promises)
+            .flat_map(|(server, promise)| {
+                let mut mocks = vec![server
+                    .mock("POST", "/propose")
+                    .match_body(Matcher::Json(
+                        serde_json::to_value(Envelope::new(Prepare::new(Ballot::new(10, 1), 0)))
+                            .unwrap(),
+                    ))
+                    .with_status(StatusCode::OK.as_u16() as usize)
+                    .with_body(Encoding::Json.encode(&promise).unwrap())
+                    .create()];
+                mocks.extend(expected_accepts.iter().map(|expected_accept| {
+                    server
+                        .mock("POST", "/accept")
+                        .match_body(Matcher::Json(
+                            serde_json::to_value(Envelope::new(expected_accept)).unwrap(),
+                        ))
+                        .with_status(StatusCode::ACCEPTED.as_u16() as usize)
+                        .create()
+                }));
+                mocks
+            })
+            .collect::<Vec<_>>();
+        let app =
+            test::init_service(App::new().app_data(node.clone()).service(consensus_start)).await;
+        let req = test::TestRequest::post()
+            .uri("/consensus")
+            .set_payload("client value")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "Value client value accepted in slot 3!");
+        for mock in mocks {
+            mock.assert();
+        }
+    }
+
+    #[actix_web::test]
+    async fn should_never_change_chosen_value_with_competing_proposers() {
+        let acceptor_node = new_node(&[Role::Acceptor, Role::Learner]);
+        let data = acceptor_node.clone();
+        let acceptor = actix_test::start(move || {
+            App::new()
+                .app_data(data.clone())
+                .service(propose)
+                .service(accept)
+                .service(update_value)
+                .service(get_log)
+        });
+        let acceptor_url = format!("http://{}", acceptor.addr());
+        if let Ok(mut paxos_learner_nodes) = acceptor_node.learner_nodes.write() {
+            paxos_learner_nodes.push(acceptor_url.clone());
+        }
+        let first_node = new_node_with_id(1, &[Role::Proposer]);
+        let second_node = new_node_with_id(2, &[Role::Proposer]);
+        for node in [&first_node, &second_node] {
+            if let Ok(mut paxos_acceptor_nodes) = node.acceptor_nodes.write() {
+                paxos_acceptor_nodes.push(acceptor_url.clone());
+            }
+        }
+        let client = reqwest::Client::new();
+
+        // First proposer completes phase 1 only
+        let first_proposal = next_ballot(&first_node);
+        let resp = client
+            .post(acceptor.url("/propose"))
+            .json(&Envelope::new(Prepare::new(first_proposal, 0)))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Second proposer runs a full round with a higher proposal and gets its value chosen
+        let app = test::init_service(
+            App::new()
+                .app_data(second_node.clone())
+                .service(consensus_start),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/consensus")
+            .set_payload("second value")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "Value second value accepted in slot 0!");
+
+        // First proposer phase 2 arrives late and is refused
+        let resp = client
+            .post(acceptor.url("/accept"))
+            .json(&Envelope::new(Accept::new(
+                0,
+                first_proposal,
+                "first value",
+            )))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+
+        // The first proposer, unaware of what was decided, takes over and keeps the chosen
+        // value in its slot
+        let app = test::init_service(
+            App::new()
+                .app_data(first_node.clone())
+                .service(consensus_start),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/consensus")
+            .set_payload("first value")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "Value first value accepted in slot 1!");
+
+        let log: Vec<Chosen> = client
+            .get(acceptor.url("/log"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            log,
+            vec![
+                Chosen {
+                    slot: 0,
+                    value: "second value".to_owned()
+                },
+                Chosen {
+                    slot: 1,
+                    value: "first value".to_owned()
+                }
+            ]
+        );
     }
 
     #[test]
-    async fn should_generate_next_id() {
-        let mut ids = (0..10).map(|_| get_next_id()).collect::<Vec<_>>();
-        ids.dedup();
-        assert_eq!(ids, vec![0, 10, 20, 30, 40, 50, 60, 70, 80, 90]);
-
-        NODE_ID.store(1, Ordering::Release);
-        PROPOSAL_ID.store(0, Ordering::Release);
-        let mut ids = (0..10).map(|_| get_next_id()).collect::<Vec<_>>();
-        ids.dedup();
-        assert_eq!(ids, vec![1, 11, 21, 31, 41, 51, 61, 71, 81, 91]);
+    async fn should_generate_next_ballot() {
+        let node = new_node(&[Role::Proposer]);
+        let ballots = (0..3).map(|_| next_ballot(&node)).collect::<Vec<_>>();
+        assert_eq!(
+            ballots,
+            vec![Ballot::new(1, 0), Ballot::new(2, 0), Ballot::new(3, 0)]
+        );
+        assert!(ballots[0] > Ballot::default());
+
+        let node = new_node_with_id(12, &[Role::Proposer]);
+        let ballot = next_ballot(&node);
+        assert_eq!(ballot, Ballot::new(1, 12));
+        assert!(ballot > Ballot::new(1, 2));
+        assert!(ballot < Ballot::new(2, 0));
+
+        restore_ballot(&node, Ballot::new(41, 3));
+        assert_eq!(next_ballot(&node), Ballot::new(42, 12));
     }
 }
@@ -0,0 +1,245 @@
+use std::{collections::HashSet, fmt, io::ErrorKind};
+
+use crate::PaxosNode;
+
+/// Sets of acceptors that complete each phase (Flexible Paxos). Safety only needs every phase 1
+/// quorum to intersect every phase 2 quorum, so phase 2, run for every slot, can use fewer
+/// acceptors than a majority as long as phase 1, run once per leader, uses more.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Quorums {
+    /// More than half of the acceptors in both phases
+    #[default]
+    Majority,
+    /// At least `phase1` acceptors in phase 1 and `phase2` in phase 2
+    Sizes { phase1: usize, phase2: usize },
+    /// Acceptors laid out row by row in a grid of `rows` rows, in the order of the membership:
+    /// phase 1 needs a whole column and phase 2 a whole row
+    Grid { rows: usize },
+}
+
+impl fmt::Display for Quorums {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Majority => write!(f, "majority"),
+            Self::Sizes { phase1, phase2 } => {
+                write!(
+                    f,
+                    "phase 1: {} acceptors, phase 2: {} acceptors",
+                    phase1, phase2
+                )
+            }
+            Self::Grid { rows } => write!(f, "grid of {} rows", rows),
+        }
+    }
+}
+
+impl Quorums {
+    /// Quorums of the environment: `PAXOS_PHASE1_QUORUM` and `PAXOS_PHASE2_QUORUM` for sizes,
+    /// `PAXOS_GRID_ROWS` for a grid, majorities when none is set.
+    pub fn from_env() -> std::io::Result<Self> {
+        let size = |name: &str| {
+            std::env::var(name).ok().map(|size| {
+                size.parse::<usize>()
+                    .map_err(|_| invalid(format!("{} should be a number", name)))
+            })
+        };
+        let phase1 = size("PAXOS_PHASE1_QUORUM").transpose()?;
+        let phase2 = size("PAXOS_PHASE2_QUORUM").transpose()?;
+        let rows = size("PAXOS_GRID_ROWS").transpose()?;
+        match (phase1, phase2, rows) {
+            (None, None, None) => Ok(Self::Majority),
+            (Some(phase1), Some(phase2), None) => Ok(Self::Sizes { phase1, phase2 }),
+            (None, None, Some(rows)) => Ok(Self::Grid { rows }),
+            (_, _, None) => Err(invalid(
+                "PAXOS_PHASE1_QUORUM and PAXOS_PHASE2_QUORUM should be set together".to_owned(),
+            )),
+            (_, _, Some(_)) => Err(invalid(
+                "PAXOS_GRID_ROWS cannot be used with quorum sizes".to_owned(),
+            )),
+        }
+    }
+
+    /// Checks that every phase 1 quorum intersects every phase 2 quorum of `acceptors`
+    /// acceptors and that both phases can complete when every acceptor answers.
+    pub fn validate(&self, acceptors: usize) -> Result<(), String> {
+        match *self {
+            Self::Majority => Ok(()),
+            Self::Sizes { phase1, phase2 } => {
+                if phase1 == 0 || phase2 == 0 || phase1 > acceptors || phase2 > acceptors {
+                    Err(format!(
+                        "Quorum sizes {} and {} should be between 1 and {} acceptors",
+                        phase1, phase2, acceptors
+                    ))
+                } else if phase1 + phase2 <= acceptors {
+                    Err(format!(
+                        "Quorum sizes {} and {} do not intersect with {} acceptors",
+                        phase1, phase2, acceptors
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+            Self::Grid { rows } => {
+                if rows == 0 || acceptors == 0 || !acceptors.is_multiple_of(rows) {
+                    Err(format!(
+                        "{} acceptors cannot be laid out in {} rows",
+                        acceptors, rows
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Whether the acceptors that promised complete phase 1.
+    pub fn is_phase1(&self, acceptors: &[String], promised: &HashSet<&String>) -> bool {
+        match *self {
+            Self::Majority => count(acceptors, promised) > acceptors.len() / 2,
+            Self::Sizes { phase1, .. } => count(acceptors, promised) >= phase1,
+            Self::Grid { rows } => {
+                let columns = columns(acceptors.len(), rows);
+                (0..columns).any(|column| {
+                    acceptors
+                        .iter()
+                        .skip(column)
+                        .step_by(columns)
+                        .all(|n| promised.contains(n))
+                })
+            }
+        }
+    }
+
+    /// Whether the acceptors that accepted complete phase 2.
+    pub fn is_phase2(&self, acceptors: &[String], accepted: &HashSet<&String>) -> bool {
+        match *self {
+            Self::Majority => count(acceptors, accepted) > acceptors.len() / 2,
+            Self::Sizes { phase2, .. } => count(acceptors, accepted) >= phase2,
+            Self::Grid { rows } => acceptors
+                .chunks(columns(acceptors.len(), rows))
+                .any(|row| row.iter().all(|n| accepted.contains(n))),
+        }
+    }
+
+    /// Acceptors that choose a value in a fast window. Any two fast quorums and any phase 1
+    /// quorum share an acceptor, so a leader running phase 1 after a collision finds the value
+    /// that may have been chosen accepted by most of the acceptors that promised.
+    pub fn fast_size(&self, acceptors: usize) -> usize {
+        let phase1 = match *self {
+            Self::Majority => acceptors / 2 + 1,
+            Self::Sizes { phase1, .. } => phase1,
+            Self::Grid { rows } => rows,
+        };
+        ((2 * acceptors).saturating_sub(phase1) / 2 + 1).min(acceptors)
+    }
+
+    /// How many of `acceptors` acceptors complete phase 2 whichever they are, for the learners
+    /// that only count the acceptances. With a grid every set that large holds a whole row.
+    pub fn phase2_size(&self, acceptors: usize) -> usize {
+        match *self {
+            Self::Majority => acceptors / 2 + 1,
+            Self::Sizes { phase2, .. } => phase2,
+            Self::Grid { rows } => acceptors - rows.min(acceptors) + 1,
+        }
+    }
+}
+
+pub fn quorums(node: &PaxosNode) -> Quorums {
+    node.quorums
+        .read()
+        .map_or(Quorums::default(), |quorums| *quorums)
+}
+
+fn count(acceptors: &[String], answered: &HashSet<&String>) -> usize {
+    acceptors.iter().filter(|n| answered.contains(n)).count()
+}
+
+fn columns(acceptors: usize, rows: usize) -> usize {
+    (acceptors / rows.max(1)).max(1)
+}
+
+fn invalid(error: String) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidInput, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acceptors(amount: usize) -> Vec<String> {
+        (0..amount)
+            .map(|i| format!("http://acceptor-{}", i))
+            .collect()
+    }
+
+    fn answered<'a>(acceptors: &'a [String], indexes: &[usize]) -> HashSet<&'a String> {
+        indexes.iter().map(|&i| &acceptors[i]).collect()
+    }
+
+    #[test]
+    fn should_refuse_quorums_that_do_not_intersect() {
+        assert!(Quorums::Sizes {
+            phase1: 4,
+            phase2: 2
+        }
+        .validate(5)
+        .is_ok());
+        assert!(Quorums::Sizes {
+            phase1: 3,
+            phase2: 2
+        }
+        .validate(5)
+        .is_err());
+        assert!(Quorums::Sizes {
+            phase1: 6,
+            phase2: 1
+        }
+        .validate(5)
+        .is_err());
+        assert!(Quorums::Grid { rows: 2 }.validate(6).is_ok());
+        assert!(Quorums::Grid { rows: 4 }.validate(6).is_err());
+    }
+
+    #[test]
+    fn should_complete_phases_with_flexible_sizes() {
+        let acceptors = acceptors(5);
+        let quorums = Quorums::Sizes {
+            phase1: 4,
+            phase2: 2,
+        };
+        assert!(quorums.is_phase2(&acceptors, &answered(&acceptors, &[1, 3])));
+        assert!(!quorums.is_phase1(&acceptors, &answered(&acceptors, &[0, 1, 3])));
+        assert!(quorums.is_phase1(&acceptors, &answered(&acceptors, &[0, 1, 2, 3])));
+        assert_eq!(quorums.phase2_size(5), 2);
+    }
+
+    #[test]
+    fn should_size_fast_quorums_to_intersect_phase1_quorums() {
+        assert_eq!(Quorums::Majority.fast_size(3), 3);
+        assert_eq!(Quorums::Majority.fast_size(5), 4);
+        assert_eq!(Quorums::Majority.fast_size(7), 6);
+        assert_eq!(
+            Quorums::Sizes {
+                phase1: 5,
+                phase2: 1
+            }
+            .fast_size(5),
+            3
+        );
+    }
+
+    #[test]
+    fn should_complete_phases_with_grid_rows_and_columns() {
+        // 0 1 2
+        // 3 4 5
+        let acceptors = acceptors(6);
+        let quorums = Quorums::Grid { rows: 2 };
+        assert!(quorums.is_phase2(&acceptors, &answered(&acceptors, &[3, 4, 5])));
+        assert!(!quorums.is_phase2(&acceptors, &answered(&acceptors, &[0, 1, 5])));
+        assert!(quorums.is_phase1(&acceptors, &answered(&acceptors, &[1, 4])));
+        assert!(!quorums.is_phase1(&acceptors, &answered(&acceptors, &[0, 4])));
+        // Any 5 of the 6 acceptors hold a whole row, 4 may not
+        assert_eq!(quorums.phase2_size(6), 5);
+        assert!(!quorums.is_phase2(&acceptors, &answered(&acceptors, &[0, 1, 3, 4])));
+    }
+}
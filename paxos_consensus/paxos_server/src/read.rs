@@ -0,0 +1,254 @@
+use std::time::Duration;
+
+use actix_web::{
+    http::header::{HeaderName, HeaderValue},
+    rt::time::Instant,
+    HttpRequest, HttpResponse,
+};
+use reqwest::Method;
+use serde::Deserialize;
+
+use crate::{
+    leader::{forward, holds_lease, leadership, Leadership},
+    messages::ErrorBody,
+    proposer::propose_value,
+    Ballot, PaxosNode, Role, NO_OP,
+};
+
+const APPLIED_POLL: Duration = Duration::from_millis(1);
+
+/// Set on the reads, the read reflects every slot before this one.
+pub const READ_SLOT_HEADER: &str = "x-paxos-read-slot";
+/// Set on the linearizable reads, ballot of the leader the read went through.
+pub const READ_BALLOT_HEADER: &str = "x-paxos-read-ballot";
+
+/// How a read is served, selected with the `read` query parameter.
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadMode {
+    /// Local state of the node, it can miss the latest writes
+    #[default]
+    Local,
+    /// Linearizable, a no-op is decided through the log and applied before reading
+    Log,
+    /// Linearizable, the leader reads while it holds its lease once every value it decided is
+    /// applied, no round trip to the acceptors is needed
+    Lease,
+}
+
+#[derive(Deserialize)]
+pub struct ReadQuery {
+    #[serde(default)]
+    pub read: ReadMode,
+}
+
+fn unavailable(error: &str) -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(ErrorBody {
+        error: error.to_owned(),
+    })
+}
+
+/// Brings the local state up to every write acknowledged before the read started, returns the
+/// ballot of the leader the read went through (none for local reads). When the read can not be
+/// served by this node the response to send is returned instead, followers forward the read to
+/// the leader.
+pub async fn prepare_read(
+    node: &PaxosNode,
+    req: &HttpRequest,
+    mode: ReadMode,
+) -> Result<Option<Ballot>, HttpResponse> {
+    if mode == ReadMode::Local {
+        return Ok(None);
+    }
+    if !node.has_role(Role::Proposer) {
+        return Err(HttpResponse::Forbidden().json(ErrorBody {
+            error: "Linearizable reads are served by the proposers".to_owned(),
+        }));
+    }
+    match leadership(node, req) {
+        Leadership::Local => {}
+        Leadership::Remote(leader) => {
+            let path = req
+                .uri()
+                .path_and_query()
+                .map_or(req.path(), |path| path.as_str());
+            return Err(forward(node, req, &leader, Method::GET, path, Vec::new()).await);
+        }
+        Leadership::Unknown => return Err(HttpResponse::ServiceUnavailable().finish()),
+    }
+    if mode == ReadMode::Lease && !holds_lease(node) {
+        return Err(unavailable("Leader lease not held"));
+    }
+    let leader_proposal = node.leader_proposal.read().map_or(None, |l| *l);
+    let (ballot, slot) = match (mode, leader_proposal) {
+        (ReadMode::Lease, Some(ballot)) => {
+            // Every value decided by the leader has to be applied
+            let slot = node.decided_values.read().map_or(0, |decided_values| {
+                decided_values.keys().next_back().map_or(0, |slot| slot + 1)
+            });
+            (Some(ballot), slot)
+        }
+        // Without phase 1 completed the no-op runs it as well
+        _ => {
+            let slot = propose_value(node, NO_OP).await?;
+            (node.leader_proposal.read().map_or(None, |l| *l), slot + 1)
+        }
+    };
+    let deadline = Instant::now() + node.request_timeout();
+    while node
+        .state_machine
+        .read()
+        .map_or(0, |state_machine| state_machine.applied())
+        < slot
+    {
+        if Instant::now() >= deadline {
+            return Err(unavailable("Decided values not applied in time"));
+        }
+        actix_web::rt::time::sleep(APPLIED_POLL).await;
+    }
+    if mode == ReadMode::Lease && !holds_lease(node) {
+        return Err(unavailable("Leader lease expired during the read"));
+    }
+    Ok(ballot)
+}
+
+/// Adds to the response where the read was served.
+pub fn served_at(mut response: HttpResponse, slot: u64, ballot: Option<Ballot>) -> HttpResponse {
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static(READ_SLOT_HEADER),
+        HeaderValue::from(slot),
+    );
+    if let Some(Ok(ballot)) = ballot.map(|ballot| HeaderValue::from_str(&ballot.to_string())) {
+        headers.insert(HeaderName::from_static(READ_BALLOT_HEADER), ballot);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use crate::{
+        leader::tick,
+        tests::{new_node_with_id, start_node},
+    };
+
+    use super::*;
+
+    fn start_single_node(
+        proposers: &[&str],
+    ) -> (
+        actix_test::TestServer,
+        actix_web::web::Data<PaxosNode>,
+        String,
+    ) {
+        let node = new_node_with_id(1, &[Role::Proposer, Role::Acceptor, Role::Learner]);
+        let server = start_node(&node);
+        let url = format!("http://{}", server.addr());
+        for nodes in [&node.acceptor_nodes, &node.learner_nodes] {
+            nodes.write().unwrap().push(url.clone());
+        }
+        if !proposers.is_empty() {
+            let mut proposer_nodes = node.proposer_nodes.write().unwrap();
+            proposer_nodes.push(url.clone());
+            proposer_nodes.extend(proposers.iter().map(|p| p.to_string()));
+            node.node_url.write().unwrap().clone_from(&url);
+        }
+        (server, node, url)
+    }
+
+    fn header(response: &reqwest::Response, name: &str) -> Option<String> {
+        response
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap().to_owned())
+    }
+
+    #[actix_web::test]
+    async fn should_read_through_the_log() {
+        let (_server, node, url) = start_single_node(&[]);
+        let client = reqwest::Client::new();
+        let resp = client
+            .put(format!("{}/kv/key", url))
+            .body("value")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = client
+            .get(format!("{}/kv/key?read=log", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(header(&resp, READ_SLOT_HEADER), Some("2".to_owned()));
+        assert_eq!(header(&resp, READ_BALLOT_HEADER), Some("1.1".to_owned()));
+        assert_eq!(resp.text().await.unwrap(), "value");
+        assert_eq!(
+            node.decided_values.read().unwrap().get(&1),
+            Some(&NO_OP.to_owned())
+        );
+
+        let resp = client
+            .get(format!("{}/value?read=log", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(header(&resp, READ_SLOT_HEADER), Some("3".to_owned()));
+        let value_state: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(value_state["slot"], 0);
+    }
+
+    #[actix_web::test]
+    async fn should_read_with_leader_lease() {
+        let (_server, node, url) = start_single_node(&["http://127.0.0.1:1"]);
+        let client = reqwest::Client::new();
+        let resp = client
+            .get(format!("{}/kv/key?read=lease", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        tick(&node).await;
+        let resp = client
+            .put(format!("{}/kv/key", url))
+            .body("value")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client
+            .get(format!("{}/kv/key?read=lease", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(header(&resp, READ_SLOT_HEADER), Some("1".to_owned()));
+        assert_eq!(resp.text().await.unwrap(), "value");
+        // Served without going through the log
+        assert_eq!(node.decided_values.read().unwrap().len(), 1);
+
+        *node.lease.write().unwrap() = None;
+        let resp = client
+            .get(format!("{}/kv/key?read=lease", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn should_read_local_state_by_default() {
+        let (_server, node, url) = start_single_node(&[]);
+        crate::learner::learn(&node, 0, NO_OP).unwrap();
+        let resp = reqwest::get(format!("{}/kv/key", url)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(header(&resp, READ_SLOT_HEADER), Some("1".to_owned()));
+        assert_eq!(header(&resp, READ_BALLOT_HEADER), None);
+        assert_eq!(node.decided_values.read().unwrap().len(), 1);
+    }
+}
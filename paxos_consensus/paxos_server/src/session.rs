@@ -0,0 +1,191 @@
+use actix_web::{HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{kv::CommandResult, messages::ErrorBody, PaxosNode};
+
+/// Set by the clients that want their requests decided at most once, with the sequence number
+/// of the request. A retry keeps the sequence number of the request it retries.
+pub const CLIENT_ID_HEADER: &str = "x-paxos-client-id";
+pub const SEQUENCE_HEADER: &str = "x-paxos-sequence";
+
+/// Value sent by a client, decided as `{"ClientRequest":{...}}` so every node drops the
+/// duplicates the same way when applying it.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ClientRequest {
+    pub client: String,
+    pub sequence: u64,
+    pub value: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Tagged {
+    #[serde(rename = "ClientRequest")]
+    request: ClientRequest,
+}
+
+/// Last request of a client applied to the state machine, with the slot it was decided in and
+/// the result of its command, given back to the retries of the request.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Session {
+    pub sequence: u64,
+    pub slot: u64,
+    pub result: Option<CommandResult>,
+}
+
+impl ClientRequest {
+    /// Request of the client sending `value`, none when the client did not set its id.
+    pub fn of(req: &HttpRequest, value: String) -> Result<Option<Self>, HttpResponse> {
+        let Some(client) = req.headers().get(CLIENT_ID_HEADER) else {
+            return Ok(None);
+        };
+        let sequence = req
+            .headers()
+            .get(SEQUENCE_HEADER)
+            .and_then(|sequence| sequence.to_str().ok())
+            .and_then(|sequence| sequence.parse::<u64>().ok());
+        match (client.to_str(), sequence) {
+            (Ok(client), Some(sequence)) => Ok(Some(Self {
+                client: client.to_owned(),
+                sequence,
+                value,
+            })),
+            _ => Err(HttpResponse::BadRequest().json(ErrorBody {
+                error: format!(
+                    "{} needs a valid {} number",
+                    CLIENT_ID_HEADER, SEQUENCE_HEADER
+                ),
+            })),
+        }
+    }
+
+    /// Value to propose for the request.
+    pub fn encode(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&Tagged {
+            request: self.clone(),
+        })
+    }
+
+    /// Request carried by a decided value, if any.
+    pub fn decode(value: &str) -> Option<Self> {
+        serde_json::from_str::<Tagged>(value)
+            .ok()
+            .map(|tagged| tagged.request)
+    }
+}
+
+/// What the node knows of an earlier request with the same client id: the session when the
+/// request was already applied, the response to send when a later request of the client was.
+pub fn applied(node: &PaxosNode, request: &ClientRequest) -> Result<Option<Session>, HttpResponse> {
+    let session = node.state_machine.read().map_or(None, |state_machine| {
+        state_machine.session(&request.client).cloned()
+    });
+    match session {
+        Some(session) if session.sequence == request.sequence => Ok(Some(session)),
+        Some(session) if session.sequence > request.sequence => {
+            Err(HttpResponse::Conflict().json(ErrorBody {
+                error: format!(
+                    "Request {} superseded by request {} of the client",
+                    request.sequence, session.sequence
+                ),
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        kv::Command,
+        learner::learn,
+        tests::{new_node, start_node},
+        Role,
+    };
+
+    use super::*;
+
+    fn request(client: &str, sequence: u64, key: &str, value: &str) -> String {
+        ClientRequest {
+            client: client.to_owned(),
+            sequence,
+            value: serde_json::to_string(&Command::Put {
+                key: key.to_owned(),
+                value: value.to_owned(),
+            })
+            .unwrap(),
+        }
+        .encode()
+        .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn should_apply_request_decided_twice_once() {
+        let node = new_node(&[Role::Learner]);
+        learn(&node, 0, &request("a", 1, "key", "first")).unwrap();
+        learn(&node, 1, &request("b", 1, "key", "second")).unwrap();
+        // Retry of the first request decided again after the request of the other client
+        learn(&node, 2, &request("a", 1, "key", "first")).unwrap();
+        let state_machine = node.state_machine.read().unwrap();
+        assert_eq!(state_machine.applied(), 3);
+        assert_eq!(state_machine.get("key"), Some(&"second".to_owned()));
+        assert_eq!(
+            state_machine.session("a"),
+            Some(&Session {
+                sequence: 1,
+                slot: 0,
+                result: Some(CommandResult {
+                    slot: 0,
+                    success: true,
+                    value: None,
+                }),
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_answer_retries_from_session() {
+        let node = new_node(&[Role::Proposer, Role::Acceptor, Role::Learner]);
+        let server = start_node(&node);
+        node.acceptor_nodes
+            .write()
+            .unwrap()
+            .push(format!("http://{}", server.addr()));
+        let client = reqwest::Client::new();
+        let send = |sequence: u64, value: &str| {
+            client
+                .put(server.url("/kv/key"))
+                .header(CLIENT_ID_HEADER, "client")
+                .header(SEQUENCE_HEADER, sequence)
+                .body(value.to_owned())
+                .send()
+        };
+
+        let first = send(1, "first").await.unwrap();
+        assert_eq!(first.status(), reqwest::StatusCode::OK);
+        let first = first.text().await.unwrap();
+        let retry = send(1, "first").await.unwrap();
+        assert_eq!(retry.status(), reqwest::StatusCode::OK);
+        assert_eq!(retry.text().await.unwrap(), first);
+        assert_eq!(node.decided_values.read().unwrap().len(), 1);
+
+        let second = send(2, "second").await.unwrap();
+        assert_eq!(second.status(), reqwest::StatusCode::OK);
+        let late = send(1, "first").await.unwrap();
+        assert_eq!(late.status(), reqwest::StatusCode::CONFLICT);
+        assert_eq!(node.decided_values.read().unwrap().len(), 2);
+        assert_eq!(
+            node.state_machine.read().unwrap().get("key"),
+            Some(&"second".to_owned())
+        );
+
+        let invalid = client
+            .put(server.url("/kv/key"))
+            .header(CLIENT_ID_HEADER, "client")
+            .body("value")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(invalid.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,617 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use actix_web::{
+    body::to_bytes, http::header::CONTENT_TYPE, rt::task::JoinHandle, web, HttpResponse,
+};
+use futures::future::{AbortHandle, Abortable};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use reqwest::StatusCode;
+
+use crate::{
+    acceptor::{receive_accept, receive_any, receive_prepare},
+    batch::{submit, DEFAULT_BATCH_SIZE, DEFAULT_PIPELINE},
+    catch_up::{catch_up, serve_decided, serve_snapshot, DecidedQuery},
+    epaxos::{receive_instance, Status},
+    learner::receive_accepted,
+    messages::Encoding,
+    proposer::propose_value,
+    quorum::Quorums,
+    transport::{Reply, Sent, Transport},
+    wal::{self, Record},
+    Ballot, PaxosNode, Role,
+};
+
+const RETRY_DEADLINE_MS: u64 = 2000;
+const RETRY_BACKOFF_MS: u64 = 5;
+
+static NEXT_SIMULATION: AtomicU64 = AtomicU64::new(0);
+
+/// Faults injected by the simulated network, probabilities are between 0 and 1.
+#[derive(Clone, Copy, Debug)]
+pub struct Faults {
+    /// Probability of losing a message, and separately its reply
+    pub drop: f64,
+    /// Probability of delivering a message a second time
+    pub duplicate: f64,
+    /// Every delivery is delayed up to this, so messages overtake each other
+    pub max_delay: Duration,
+}
+
+impl Faults {
+    pub fn none() -> Self {
+        Faults {
+            drop: 0.0,
+            duplicate: 0.0,
+            max_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// What happens to a single message, drawn from the seeded generator.
+struct Fate {
+    delay: Duration,
+    lost: bool,
+    duplicate: Option<Duration>,
+    reply_lost: bool,
+}
+
+struct Running {
+    node: Arc<PaxosNode>,
+    // Work of the node in progress, aborted when it crashes
+    tasks: Vec<AbortHandle>,
+}
+
+struct Network {
+    faults: Faults,
+    rng: Mutex<StdRng>,
+    running: RwLock<HashMap<String, Running>>,
+}
+
+impl Network {
+    fn fate(&self) -> Fate {
+        let faults = self.faults;
+        let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        let mut delay = || rng.gen_range(Duration::ZERO..=faults.max_delay);
+        let (delay, duplicate_delay) = (delay(), delay());
+        Fate {
+            delay,
+            lost: rng.gen_bool(faults.drop),
+            duplicate: rng.gen_bool(faults.duplicate).then_some(duplicate_delay),
+            reply_lost: rng.gen_bool(faults.drop),
+        }
+    }
+
+    /// Registers work of the running node at `url`, it stops at its next await point when the
+    /// node crashes.
+    fn track<F: std::future::Future>(&self, url: &str, future: F) -> Option<Abortable<F>> {
+        let (handle, registration) = AbortHandle::new_pair();
+        let mut running = self.running.write().ok()?;
+        running.get_mut(url)?.tasks.push(handle);
+        Some(Abortable::new(future, registration))
+    }
+
+    async fn send(
+        self: Arc<Self>,
+        url: String,
+        path: String,
+        encoding: Encoding,
+        body: Vec<u8>,
+    ) -> std::io::Result<Reply> {
+        let fate = self.fate();
+        if let Some(delay) = fate.duplicate {
+            let network = self.clone();
+            let (url, path, body) = (url.clone(), path.clone(), body.clone());
+            actix_web::rt::spawn(async move {
+                actix_web::rt::time::sleep(delay).await;
+                let _ = network.deliver(&url, &path, encoding, &body).await;
+            });
+        }
+        actix_web::rt::time::sleep(fate.delay).await;
+        if fate.lost {
+            return Err(std::io::Error::new(ErrorKind::TimedOut, "message lost"));
+        }
+        let reply = self.deliver(&url, &path, encoding, &body).await?;
+        if fate.reply_lost {
+            return Err(std::io::Error::new(ErrorKind::TimedOut, "reply lost"));
+        }
+        Ok(reply)
+    }
+
+    async fn deliver(
+        &self,
+        url: &str,
+        path: &str,
+        encoding: Encoding,
+        body: &[u8],
+    ) -> std::io::Result<Reply> {
+        let node = self
+            .running
+            .read()
+            .ok()
+            .and_then(|running| running.get(url).map(|running| running.node.clone()))
+            .ok_or_else(|| std::io::Error::new(ErrorKind::ConnectionRefused, "node crashed"))?;
+        let response = self
+            .track(url, handle(&node, path, encoding, body))
+            .ok_or_else(|| std::io::Error::new(ErrorKind::ConnectionRefused, "node crashed"))?
+            .await
+            .map_err(|_| std::io::Error::new(ErrorKind::ConnectionReset, "node crashed"))?;
+        let status = StatusCode::from_u16(response.status().as_u16())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let encoding = Encoding::from_content_type(
+            response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok()),
+        )
+        .unwrap_or(encoding);
+        let body = to_bytes(response.into_body())
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        Ok(Reply {
+            status,
+            encoding,
+            body: body.to_vec(),
+        })
+    }
+}
+
+async fn handle(node: &PaxosNode, path: &str, encoding: Encoding, body: &[u8]) -> HttpResponse {
+    let (route, query) = path.split_once('?').unwrap_or((path, ""));
+    match route {
+        "/propose" => receive_prepare(node, encoding, body).await,
+        "/accept" => receive_accept(node, encoding, body).await,
+        "/any" => receive_any(node, encoding, body).await,
+        "/update_value" => receive_accepted(node, encoding, body).await,
+        "/epaxos/pre_accept" => receive_instance(node, encoding, body, Status::PreAccepted).await,
+        "/epaxos/accept" => receive_instance(node, encoding, body, Status::Accepted).await,
+        "/epaxos/commit" => receive_instance(node, encoding, body, Status::Committed).await,
+        "/decided" => match web::Query::<DecidedQuery>::from_query(query) {
+            Ok(query) => serve_decided(node, encoding, query.from.unwrap_or_default()),
+            Err(_) => HttpResponse::BadRequest().finish(),
+        },
+        "/snapshot" => serve_snapshot(node, encoding),
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+struct SimulatedTransport {
+    network: Arc<Network>,
+}
+
+impl Transport for SimulatedTransport {
+    fn send(&self, url: &str, path: &str, encoding: Encoding, body: Vec<u8>) -> Sent<'_> {
+        Box::pin(
+            self.network
+                .clone()
+                .send(url.to_owned(), path.to_owned(), encoding, body),
+        )
+    }
+
+    fn get(&self, url: &str, path: &str, encoding: Encoding) -> Sent<'_> {
+        self.send(url, path, encoding, Vec::new())
+    }
+}
+
+/// Cluster of nodes with every role running in the current process, messages go through a
+/// network that loses, delays, duplicates and reorders them. Every fault and retry jitter is
+/// drawn from the seed: on a paused clock (`tokio::time::pause`) in a single-threaded runtime
+/// the same seed replays the same run.
+pub struct Simulation {
+    network: Arc<Network>,
+    urls: Vec<String>,
+    wal_paths: Vec<PathBuf>,
+    proposals: Vec<JoinHandle<Option<u64>>>,
+    batching: (u64, u64),
+    quorums: Quorums,
+}
+
+impl Simulation {
+    pub fn new(seed: u64, size: usize, faults: Faults) -> std::io::Result<Self> {
+        let id = NEXT_SIMULATION.fetch_add(1, Ordering::SeqCst);
+        let simulation = Simulation {
+            network: Arc::new(Network {
+                faults,
+                rng: Mutex::new(StdRng::seed_from_u64(seed)),
+                running: RwLock::new(HashMap::new()),
+            }),
+            urls: (0..size).map(|i| format!("sim://node-{}", i)).collect(),
+            wal_paths: (0..size)
+                .map(|i| {
+                    std::env::temp_dir().join(format!(
+                        "paxos_sim_{}_{}_{}.wal",
+                        std::process::id(),
+                        id,
+                        i
+                    ))
+                })
+                .collect(),
+            proposals: Vec::new(),
+            batching: (DEFAULT_BATCH_SIZE, DEFAULT_PIPELINE),
+            quorums: Quorums::default(),
+        };
+        for index in 0..size {
+            let _ = std::fs::remove_file(&simulation.wal_paths[index]);
+            simulation.start(index)?;
+        }
+        Ok(simulation)
+    }
+
+    /// Starts the node from its write-ahead log.
+    fn start(&self, index: usize) -> std::io::Result<()> {
+        let mut node = PaxosNode::new(
+            index as u64 + 1,
+            // Nothing is logged, the events would hide the results and slow the cluster down
+            String::new(),
+            [Role::Proposer, Role::Acceptor, Role::Learner].into(),
+            Box::new(SimulatedTransport {
+                network: self.network.clone(),
+            }),
+        );
+        node.rng = Mutex::new(StdRng::seed_from_u64(
+            self.network
+                .rng
+                .lock()
+                .map_err(|_| std::io::Error::other("network not available"))?
+                .gen(),
+        ));
+        node.acceptor_nodes
+            .write()
+            .map_err(|_| std::io::Error::other("acceptors not available"))?
+            .clone_from(&self.urls);
+        node.learner_nodes
+            .write()
+            .map_err(|_| std::io::Error::other("learners not available"))?
+            .clone_from(&self.urls);
+        node.encoding = Encoding::Bincode;
+        node.batch_size.store(self.batching.0, Ordering::SeqCst);
+        node.pipeline.store(self.batching.1, Ordering::SeqCst);
+        *node
+            .quorums
+            .write()
+            .map_err(|_| std::io::Error::other("quorums not available"))? = self.quorums;
+        node.retry_deadline_ms
+            .store(RETRY_DEADLINE_MS, Ordering::SeqCst);
+        node.retry_backoff_ms
+            .store(RETRY_BACKOFF_MS, Ordering::SeqCst);
+        wal::open(&node, &self.wal_paths[index])?;
+        self.network
+            .running
+            .write()
+            .map_err(|_| std::io::Error::other("network not available"))?
+            .insert(
+                self.urls[index].clone(),
+                Running {
+                    node: Arc::new(node),
+                    tasks: Vec::new(),
+                },
+            );
+        Ok(())
+    }
+
+    /// Batches at most `batch_size` values per slot with at most `pipeline` slots in flight on
+    /// every node, used by the values submitted with `submit`.
+    pub fn set_batching(&mut self, batch_size: u64, pipeline: u64) {
+        self.batching = (batch_size, pipeline);
+        for index in 0..self.urls.len() {
+            if let Some(node) = self.node(index) {
+                node.batch_size.store(batch_size, Ordering::SeqCst);
+                node.pipeline.store(pipeline, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Quorums of every node, also used by `check` to tell which values were chosen.
+    pub fn set_quorums(&mut self, quorums: Quorums) -> Result<(), String> {
+        quorums.validate(self.urls.len())?;
+        self.quorums = quorums;
+        for index in 0..self.urls.len() {
+            if let Some(node) = self.node(index) {
+                if let Ok(mut node_quorums) = node.quorums.write() {
+                    *node_quorums = quorums;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Proposes the value from the node in the background, ignored while the node is crashed.
+    pub fn propose(&mut self, index: usize, value: &str) {
+        let value = value.to_owned();
+        self.spawn(index, move |node| async move {
+            propose_value(&node, &value).await.ok()
+        });
+    }
+
+    /// Like `propose`, but the value is batched with the others submitted to the node.
+    pub fn submit(&mut self, index: usize, value: &str) {
+        let value = value.to_owned();
+        self.spawn(index, move |node| async move {
+            submit(&node, &value).await.ok().map(|(slot, _)| slot)
+        });
+    }
+
+    fn spawn<F, P>(&mut self, index: usize, proposal: P)
+    where
+        F: std::future::Future<Output = Option<u64>> + 'static,
+        P: FnOnce(Arc<PaxosNode>) -> F,
+    {
+        let node = match self.node(index) {
+            Some(node) => node,
+            None => return,
+        };
+        let proposal = self.network.track(&self.urls[index], proposal(node));
+        if let Some(proposal) = proposal {
+            self.proposals.push(actix_web::rt::spawn(async move {
+                proposal.await.ok().flatten()
+            }));
+        }
+    }
+
+    /// Waits for every proposal, returns the slot of each one in order of proposal, `None`
+    /// when it was not decided or its node crashed.
+    pub async fn settle(&mut self) -> Vec<Option<u64>> {
+        let mut slots = Vec::new();
+        for proposal in self.proposals.drain(..) {
+            slots.push(proposal.await.ok().flatten());
+        }
+        slots
+    }
+
+    /// Stops the node where it is, only what it wrote in its write-ahead log survives.
+    pub fn crash(&self, index: usize) {
+        let crashed = self
+            .network
+            .running
+            .write()
+            .ok()
+            .and_then(|mut running| running.remove(&self.urls[index]));
+        if let Some(crashed) = crashed {
+            for task in crashed.tasks {
+                task.abort();
+            }
+        }
+    }
+
+    pub fn restart(&self, index: usize) -> std::io::Result<()> {
+        self.crash(index);
+        self.start(index)
+    }
+
+    fn node(&self, index: usize) -> Option<Arc<PaxosNode>> {
+        self.network
+            .running
+            .read()
+            .ok()?
+            .get(&self.urls[index])
+            .map(|running| running.node.clone())
+    }
+
+    /// Lets the node pull the values it missed from the other nodes, ignored while the node is
+    /// crashed.
+    pub async fn catch_up(&self, index: usize) {
+        let Some(node) = self.node(index) else {
+            return;
+        };
+        if let Some(catching_up) = self
+            .network
+            .track(&self.urls[index], async move { catch_up(&node).await })
+        {
+            let _ = catching_up.await;
+        }
+    }
+
+    /// Values decided by the node, `None` while it is crashed.
+    pub fn decided(&self, index: usize) -> Option<BTreeMap<u64, String>> {
+        self.node(index)?
+            .decided_values
+            .read()
+            .ok()
+            .map(|decided_values| decided_values.clone())
+    }
+
+    /// Checks, from the write-ahead logs, that at most one value was chosen per slot (accepted
+    /// by a phase 2 quorum in the same ballot) and that learners only learned chosen values.
+    /// Returns the chosen values.
+    pub fn check(&self) -> Result<BTreeMap<u64, String>, String> {
+        let mut accepted: HashMap<(u64, Ballot, String), HashSet<usize>> = HashMap::new();
+        let mut learned = Vec::new();
+        for (index, path) in self.wal_paths.iter().enumerate() {
+            let (records, _) = wal::read(path).map_err(|e| e.to_string())?;
+            for record in records {
+                match record {
+                    Record::Accept {
+                        slot,
+                        ballot,
+                        value,
+                    } => {
+                        accepted
+                            .entry((slot, ballot, value))
+                            .or_default()
+                            .insert(index);
+                    }
+                    Record::Learn { slot, value } => learned.push((index, slot, value)),
+                    Record::Proposal(_)
+                    | Record::Promise(_)
+                    | Record::Snapshot(_)
+                    | Record::Instance { .. } => {}
+                }
+            }
+        }
+        let mut chosen = BTreeMap::new();
+        for ((slot, _, value), acceptors) in accepted {
+            let acceptors = acceptors.iter().map(|&i| &self.urls[i]).collect();
+            if !self.quorums.is_phase2(&self.urls, &acceptors) {
+                continue;
+            }
+            if let Some(other) = chosen.insert(slot, value.clone()) {
+                if other != value {
+                    return Err(format!("Slot {} chose {} and {}", slot, other, value));
+                }
+            }
+        }
+        for (index, slot, value) in learned {
+            if chosen.get(&slot) != Some(&value) {
+                return Err(format!(
+                    "Node {} learned {} in slot {} but {:?} was chosen",
+                    index,
+                    value,
+                    slot,
+                    chosen.get(&slot)
+                ));
+            }
+        }
+        Ok(chosen)
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        // Running nodes hold the network through their transport
+        for index in 0..self.urls.len() {
+            self.crash(index);
+            let _ = std::fs::remove_file(&self.wal_paths[index]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn faults() -> Faults {
+        Faults {
+            drop: 0.1,
+            duplicate: 0.1,
+            max_delay: Duration::from_millis(3),
+        }
+    }
+
+    #[actix_web::test]
+    async fn should_decide_every_value_without_faults() {
+        let mut simulation = Simulation::new(0, 3, Faults::none()).unwrap();
+        for value in ["a", "b", "c"] {
+            simulation.propose(0, value);
+            simulation.settle().await;
+        }
+        let chosen = simulation.check().unwrap();
+        assert_eq!(chosen.values().collect::<Vec<_>>(), ["a", "b", "c"]);
+        for index in 0..3 {
+            assert_eq!(simulation.decided(index), Some(chosen.clone()));
+        }
+    }
+
+    #[actix_web::test]
+    async fn should_choose_at_most_one_value_per_slot() {
+        tokio::time::pause();
+        for seed in 0..10 {
+            let mut simulation = Simulation::new(seed, 3, faults()).unwrap();
+            for round in 0..3 {
+                for index in 0..3 {
+                    simulation.propose(index, &format!("value {} from {}", round, index));
+                }
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+            simulation.crash(seed as usize % 3);
+            actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+            simulation.restart(seed as usize % 3).unwrap();
+            let slots = simulation.settle().await;
+            let chosen = simulation
+                .check()
+                .unwrap_or_else(|e| panic!("Seed {}: {}", seed, e));
+            for slot in slots.into_iter().flatten() {
+                assert!(chosen.contains_key(&slot), "Seed {}: slot {}", seed, slot);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn should_decide_with_a_crashed_minority() {
+        tokio::time::pause();
+        let mut simulation = Simulation::new(1, 3, faults()).unwrap();
+        simulation.crash(2);
+        simulation.propose(0, "value");
+        // Lost messages can make the proposer move to a later slot
+        let slot = simulation.settle().await[0].unwrap();
+        simulation.restart(2).unwrap();
+        assert_eq!(
+            simulation.check().unwrap().get(&slot),
+            Some(&"value".to_owned())
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_choose_at_most_one_value_per_slot_with_flexible_quorums() {
+        tokio::time::pause();
+        for (seed, quorums) in [
+            Quorums::Sizes {
+                phase1: 3,
+                phase2: 2,
+            },
+            Quorums::Grid { rows: 2 },
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut simulation = Simulation::new(seed as u64, 4, faults()).unwrap();
+            simulation.set_quorums(quorums).unwrap();
+            for round in 0..3 {
+                for index in 0..4 {
+                    simulation.propose(index, &format!("value {} from {}", round, index));
+                }
+            }
+            let slots = simulation.settle().await;
+            let chosen = simulation
+                .check()
+                .unwrap_or_else(|e| panic!("{}: {}", quorums, e));
+            for slot in slots.into_iter().flatten() {
+                assert!(chosen.contains_key(&slot), "{}: slot {}", quorums, slot);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn should_catch_up_after_restart_without_new_proposal() {
+        let mut simulation = Simulation::new(2, 3, Faults::none()).unwrap();
+        simulation.crash(2);
+        for value in ["a", "b", "c"] {
+            simulation.propose(0, value);
+            simulation.settle().await;
+        }
+        simulation.restart(2).unwrap();
+        assert_eq!(simulation.decided(2), Some(BTreeMap::new()));
+        simulation.catch_up(2).await;
+        let chosen = simulation.check().unwrap();
+        assert_eq!(chosen.len(), 3);
+        assert_eq!(simulation.decided(2), Some(chosen));
+    }
+
+    #[actix_web::test]
+    async fn should_replay_run_from_seed() {
+        tokio::time::pause();
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            let mut simulation = Simulation::new(4, 3, faults()).unwrap();
+            for round in 0..3 {
+                for index in 0..3 {
+                    simulation.propose(index, &format!("value {} from {}", round, index));
+                }
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+            simulation.crash(1);
+            actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+            simulation.restart(1).unwrap();
+            let slots = simulation.settle().await;
+            runs.push((slots, simulation.check()));
+        }
+        assert_eq!(runs[0], runs[1]);
+    }
+}
@@ -0,0 +1,70 @@
+use std::{future::Future, pin::Pin};
+
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    messages::{Encoding, MessageError},
+    CLIENT,
+};
+
+pub const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 1000;
+
+/// Reply of a peer to a message.
+pub struct Reply {
+    pub status: StatusCode,
+    pub encoding: Encoding,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, MessageError> {
+        self.encoding.decode(&self.body)
+    }
+}
+
+pub type Sent<'a> = Pin<Box<dyn Future<Output = std::io::Result<Reply>> + 'a>>;
+
+/// How a node reaches the endpoints of its peers, `body` is the message in `encoding`.
+pub trait Transport: Send + Sync {
+    fn send(&self, url: &str, path: &str, encoding: Encoding, body: Vec<u8>) -> Sent<'_>;
+
+    /// Reads an endpoint, `path` can carry a query, the answer is asked in `encoding`.
+    fn get(&self, url: &str, path: &str, encoding: Encoding) -> Sent<'_>;
+}
+
+/// Messages sent as HTTP requests, used by every node outside of the simulator.
+pub struct HttpTransport;
+
+impl Transport for HttpTransport {
+    fn send(&self, url: &str, path: &str, encoding: Encoding, body: Vec<u8>) -> Sent<'_> {
+        let request = CLIENT.post(format!("{}{}", url, path)).body(body);
+        Box::pin(reply(request, encoding))
+    }
+
+    fn get(&self, url: &str, path: &str, encoding: Encoding) -> Sent<'_> {
+        Box::pin(reply(CLIENT.get(format!("{}{}", url, path)), encoding))
+    }
+}
+
+async fn reply(request: RequestBuilder, encoding: Encoding) -> std::io::Result<Reply> {
+    let response = request
+        .header(reqwest::header::CONTENT_TYPE, encoding.content_type())
+        .send()
+        .await
+        .map_err(std::io::Error::other)?;
+    let status = response.status();
+    let encoding = Encoding::from_content_type(
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok()),
+    )
+    .unwrap_or(encoding);
+    let body = response.bytes().await.map_err(std::io::Error::other)?;
+    Ok(Reply {
+        status,
+        encoding,
+        body: body.to_vec(),
+    })
+}
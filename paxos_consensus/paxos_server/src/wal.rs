@@ -0,0 +1,294 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    catch_up,
+    epaxos::{self, Status},
+    kv, leader, membership,
+    messages::{Instance, Snapshot},
+    proposer::{promise, restore_ballot},
+    Ballot, PaxosNode,
+};
+
+/// Every state change that has to survive a restart, written before answering to the caller.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub enum Record {
+    Proposal(Ballot),
+    Promise(Ballot),
+    Accept {
+        slot: u64,
+        ballot: Ballot,
+        value: String,
+    },
+    Learn {
+        slot: u64,
+        value: String,
+    },
+    Snapshot(Snapshot),
+    Instance {
+        instance: Instance,
+        status: Status,
+    },
+}
+
+/// Reads the records of the log at `path` (if present), returns them with the length of the
+/// part of the file holding them. Only a partial last line is left out, any other record that
+/// cannot be read fails the whole read as the records after it were acknowledged.
+pub fn read(path: impl AsRef<Path>) -> std::io::Result<(Vec<Record>, u64)> {
+    let mut records = Vec::new();
+    let mut valid_len = 0;
+    match File::open(path) {
+        Ok(file) => {
+            let mut reader = BufReader::new(file);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                let read = reader.read_until(b'\n', &mut line)?;
+                // A crash while appending can leave a partial last line, it was never
+                // acknowledged so it is dropped
+                if read == 0 || line.last() != Some(&b'\n') {
+                    break;
+                }
+                let record = serde_json::from_slice::<Record>(&line).map_err(|e| {
+                    std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Record at byte {} not valid: {}", valid_len, e),
+                    )
+                })?;
+                records.push(record);
+                valid_len += read as u64;
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok((records, valid_len))
+}
+
+/// Replays the log at `path` (if present) into the node state and keeps the file open for
+/// the following appends.
+pub fn open(node: &PaxosNode, path: impl AsRef<Path>) -> std::io::Result<usize> {
+    let path = path.as_ref();
+    let (records, valid_len) = read(path)?;
+    let replayed = records.len();
+    if replayed > 0 {
+        leader::fence(node);
+    }
+    for record in records {
+        apply(node, record);
+    }
+    kv::apply_decided(node);
+    epaxos::execute(node);
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.set_len(valid_len)?;
+    if let Ok(mut wal) = node.wal.lock() {
+        *wal = Some((file, path.to_owned()));
+        Ok(replayed)
+    } else {
+        Err(std::io::Error::other("write-ahead log not available"))
+    }
+}
+
+/// Appends the record and waits until it is on disk.
+pub fn append(node: &PaxosNode, record: &Record) -> std::io::Result<()> {
+    let mut wal = node
+        .wal
+        .lock()
+        .map_err(|_| std::io::Error::other("write-ahead log not available"))?;
+    if let Some((file, _)) = wal.as_mut() {
+        write(file, record)?;
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+fn write(file: &mut File, record: &Record) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    file.write_all(&line)
+}
+
+/// Rewrites the log with the snapshot in place of the records of the slots it covers, only the
+/// highest ballots proposed and promised are kept. The new log replaces the old one with a
+/// rename, a crash leaves one of the two.
+pub fn compact(node: &PaxosNode, snapshot: &Snapshot) -> std::io::Result<()> {
+    let mut wal = node
+        .wal
+        .lock()
+        .map_err(|_| std::io::Error::other("write-ahead log not available"))?;
+    let Some((file, path)) = wal.as_mut() else {
+        return Ok(());
+    };
+    let (records, _) = read(&*path)?;
+    let mut proposal = None;
+    let mut promised = None;
+    let mut kept = Vec::new();
+    for record in records {
+        match record {
+            Record::Proposal(ballot) => proposal = proposal.max(Some(ballot)),
+            Record::Promise(ballot) => promised = promised.max(Some(ballot)),
+            // Accepting promises the ballot as well
+            Record::Accept { slot, ballot, .. } if slot < snapshot.slot => {
+                promised = promised.max(Some(ballot))
+            }
+            Record::Learn { slot, .. } if slot < snapshot.slot => {}
+            Record::Snapshot(_) => {}
+            record => kept.push(record),
+        }
+    }
+    let compacted_path = path.with_extension("compacted");
+    let mut compacted = File::create(&compacted_path)?;
+    let ballots = proposal
+        .map(Record::Proposal)
+        .into_iter()
+        .chain(promised.map(Record::Promise));
+    for record in ballots
+        .chain([Record::Snapshot(snapshot.clone())])
+        .chain(kept)
+    {
+        write(&mut compacted, &record)?;
+    }
+    compacted.sync_all()?;
+    std::fs::rename(&compacted_path, &*path)?;
+    *file = OpenOptions::new().append(true).open(&*path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub fn close(node: &PaxosNode) {
+    if let Ok(mut wal) = node.wal.lock() {
+        *wal = None;
+    }
+}
+
+fn apply(node: &PaxosNode, record: Record) {
+    match record {
+        Record::Proposal(ballot) => restore_ballot(node, ballot),
+        Record::Promise(ballot) => {
+            promise(node, ballot);
+        }
+        Record::Accept {
+            slot,
+            ballot,
+            value,
+        } => {
+            promise(node, ballot);
+            if let Ok(mut accepted) = node.accepted.write() {
+                accepted.insert(slot, (ballot, value));
+            }
+        }
+        Record::Learn { slot, value } => {
+            membership::record(node, slot, &value);
+            if let Ok(mut decided_values) = node.decided_values.write() {
+                decided_values.insert(slot, value);
+            }
+        }
+        Record::Snapshot(snapshot) => catch_up::restore(node, snapshot),
+        Record::Instance { instance, status } => epaxos::restore(node, instance, status),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::PathBuf;
+
+    use std::sync::atomic::Ordering;
+
+    use crate::{tests::new_node, Role};
+
+    use super::*;
+
+    pub fn wal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("paxos_{}_{}.wal", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn should_replay_records() {
+        let node = new_node(&[Role::Acceptor]);
+        let path = wal_path("replay");
+        open(&node, &path).unwrap();
+        append(&node, &Record::Proposal(Ballot::new(3, 1))).unwrap();
+        append(&node, &Record::Promise(Ballot::new(4, 2))).unwrap();
+        append(
+            &node,
+            &Record::Accept {
+                slot: 3,
+                ballot: Ballot::new(4, 2),
+                value: "value".to_owned(),
+            },
+        )
+        .unwrap();
+        append(
+            &node,
+            &Record::Learn {
+                slot: 3,
+                value: "value".to_owned(),
+            },
+        )
+        .unwrap();
+
+        let node = new_node(&[Role::Acceptor]);
+        assert_eq!(open(&node, &path).unwrap(), 4);
+        assert_eq!(*node.promised_ballot.read().unwrap(), Ballot::new(4, 2));
+        assert_eq!(
+            node.accepted.read().unwrap().get(&3),
+            Some(&(Ballot::new(4, 2), "value".to_owned()))
+        );
+        assert_eq!(
+            node.decided_values.read().unwrap().get(&3),
+            Some(&"value".to_owned())
+        );
+        assert_eq!(node.round.load(Ordering::Acquire), 3);
+        close(&node);
+    }
+
+    #[test]
+    fn should_ignore_partial_last_record() {
+        let node = new_node(&[Role::Acceptor]);
+        let path = wal_path("partial");
+        open(&node, &path).unwrap();
+        append(&node, &Record::Promise(Ballot::new(4, 2))).unwrap();
+        close(&node);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"Promise\":").unwrap();
+
+        let node = new_node(&[Role::Acceptor]);
+        assert_eq!(open(&node, &path).unwrap(), 1);
+        assert_eq!(*node.promised_ballot.read().unwrap(), Ballot::new(4, 2));
+        append(&node, &Record::Promise(Ballot::new(4, 3))).unwrap();
+
+        let node = new_node(&[Role::Acceptor]);
+        assert_eq!(open(&node, &path).unwrap(), 2);
+        assert_eq!(*node.promised_ballot.read().unwrap(), Ballot::new(4, 3));
+        close(&node);
+    }
+
+    #[test]
+    fn should_refuse_log_with_corrupt_record() {
+        let node = new_node(&[Role::Acceptor]);
+        let path = wal_path("corrupt");
+        open(&node, &path).unwrap();
+        append(&node, &Record::Promise(Ballot::new(4, 2))).unwrap();
+        close(&node);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"Promise\":\n").unwrap();
+        write(&mut file, &Record::Promise(Ballot::new(5, 2))).unwrap();
+        drop(file);
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        // The records after the corrupt one were acknowledged, they are neither dropped nor
+        // replayed
+        let node = new_node(&[Role::Acceptor]);
+        let error = open(&node, &path).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(*node.promised_ballot.read().unwrap(), Ballot::default());
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use actix_web::{post, web, Error, HttpResponse};
+use futures::future::join_all;
+use rand::Rng;
+
+use crate::{
+    messages::{Entry, RequestVote, Vote},
+    replication,
+    wal::{self, Record},
+    Leader, RaftNode, Role, State,
+};
+
+pub const DEFAULT_ELECTION_TIMEOUT_MS: u64 = 300;
+const TICK: Duration = Duration::from_millis(10);
+
+/// When a follower that heard from no leader runs for leadership, randomized so the followers
+/// rarely run at the same time.
+pub fn deadline(election_timeout_ms: u64) -> Instant {
+    let timeout = rand::thread_rng().gen_range(election_timeout_ms..=election_timeout_ms * 2);
+    Instant::now() + Duration::from_millis(timeout)
+}
+
+/// Moves to a newer term as a follower, the vote of the previous term no longer holds.
+pub fn observe_term(node: &RaftNode, state: &mut State, term: u64) -> Option<Record> {
+    if term <= state.term {
+        return None;
+    }
+    state.term = term;
+    state.voted_for = None;
+    state.role = Role::Follower;
+    state.leader = None;
+    state.election_deadline = deadline(node.election_timeout_ms.load(Ordering::Acquire));
+    Some(Record::Vote {
+        term,
+        voted_for: None,
+    })
+}
+
+#[post("/raft/request_vote")]
+async fn request_vote(
+    node: web::Data<RaftNode>,
+    request: web::Json<RequestVote>,
+) -> Result<HttpResponse, Error> {
+    match receive_vote(&node, request.into_inner()) {
+        Ok(vote) => {
+            node.log("Election: Vote", &format!("{:?}", vote)).await;
+            Ok(HttpResponse::Ok().json(vote))
+        }
+        Err(e) => {
+            node.log("Election: Vote not persisted", &e.to_string())
+                .await;
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Grants the vote to the first candidate of the term whose log is at least as up to date as
+/// the log of this node, so a leader always holds every committed entry.
+pub fn receive_vote(node: &RaftNode, request: RequestVote) -> std::io::Result<Vote> {
+    let mut state = node
+        .state
+        .lock()
+        .map_err(|_| std::io::Error::other("state not available"))?;
+    let mut records = observe_term(node, &mut state, request.term)
+        .into_iter()
+        .collect::<Vec<_>>();
+    let up_to_date = (request.last_log_term, request.last_log_index)
+        >= (state.term_at(state.last_index()), state.last_index());
+    let granted = request.term == state.term
+        && state.voted_for.is_none_or(|id| id == request.candidate_id)
+        && up_to_date;
+    if granted && state.voted_for.is_none() {
+        state.voted_for = Some(request.candidate_id);
+        state.election_deadline = deadline(node.election_timeout_ms.load(Ordering::Acquire));
+        records = vec![Record::Vote {
+            term: state.term,
+            voted_for: state.voted_for,
+        }];
+    }
+    wal::append(node, &records)?;
+    Ok(Vote {
+        term: state.term,
+        granted,
+    })
+}
+
+/// Runs for leadership whenever the election timeout expires without hearing from a leader.
+pub async fn run(node: web::Data<RaftNode>) {
+    loop {
+        actix_web::rt::time::sleep(TICK).await;
+        let expired = node.state.lock().is_ok_and(|state| {
+            state.role != Role::Leader && Instant::now() >= state.election_deadline
+        });
+        if expired {
+            campaign(&node).await;
+        }
+    }
+}
+
+/// Starts a new term as candidate and asks every peer for its vote, with a majority of the
+/// votes the node becomes the leader and appends an empty entry of its term, which commits the
+/// entries of the previous terms. Returns whether the node was elected.
+pub async fn campaign(node: &RaftNode) -> bool {
+    let request = {
+        let Ok(mut state) = node.state.lock() else {
+            return false;
+        };
+        state.term += 1;
+        state.voted_for = Some(node.node_id);
+        state.role = Role::Candidate;
+        state.leader = None;
+        state.election_deadline = deadline(node.election_timeout_ms.load(Ordering::Acquire));
+        let record = Record::Vote {
+            term: state.term,
+            voted_for: state.voted_for,
+        };
+        if wal::append(node, &[record]).is_err() {
+            return false;
+        }
+        RequestVote {
+            term: state.term,
+            candidate_id: node.node_id,
+            last_log_index: state.last_index(),
+            last_log_term: state.term_at(state.last_index()),
+        }
+    };
+    node.log("Election: Campaign started", &request.term.to_string())
+        .await;
+    let peers = node.peers();
+    let replies = join_all(
+        peers
+            .iter()
+            .map(|peer| node.send::<_, Vote>(peer, "/raft/request_vote", &request)),
+    )
+    .await;
+    let mut votes = 1;
+    let mut highest_term = request.term;
+    for reply in replies {
+        match reply {
+            Ok(vote) => {
+                votes += usize::from(vote.granted);
+                highest_term = highest_term.max(vote.term);
+            }
+            Err(e) => node.log("Election: Error", &e.to_string()).await,
+        }
+    }
+    let elected = {
+        let Ok(mut state) = node.state.lock() else {
+            return false;
+        };
+        if let Some(record) = observe_term(node, &mut state, highest_term) {
+            let _ = wal::append(node, &[record]);
+        }
+        if state.term != request.term || state.role != Role::Candidate || votes < node.majority() {
+            false
+        } else {
+            let entry = Entry {
+                term: state.term,
+                value: paxos_server::NO_OP.to_owned(),
+            };
+            let index = state.last_index() + 1;
+            if wal::append(
+                node,
+                &[Record::Append {
+                    index,
+                    entry: entry.clone(),
+                }],
+            )
+            .is_err()
+            {
+                false
+            } else {
+                state.log.push(entry);
+                state.role = Role::Leader;
+                state.leader = Some(Leader {
+                    node_id: node.node_id,
+                    url: node
+                        .node_url
+                        .read()
+                        .map_or(String::new(), |url| url.clone()),
+                });
+                state.next_index = peers.iter().map(|peer| (peer.clone(), index)).collect();
+                state.match_index = peers.iter().map(|peer| (peer.clone(), 0)).collect();
+                true
+            }
+        }
+    };
+    if elected {
+        node.log("Election: Elected", &request.term.to_string())
+            .await;
+        replication::replicate(node).await;
+    }
+    elected
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{new_node_with_id, start_cluster};
+
+    use super::*;
+
+    fn request(
+        term: u64,
+        candidate_id: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    ) -> RequestVote {
+        RequestVote {
+            term,
+            candidate_id,
+            last_log_index,
+            last_log_term,
+        }
+    }
+
+    #[test]
+    fn should_vote_once_per_term_for_up_to_date_candidate() {
+        let node = new_node_with_id(1);
+        node.state.lock().unwrap().log = vec![
+            Entry {
+                term: 1,
+                value: "a".to_owned(),
+            },
+            Entry {
+                term: 2,
+                value: "b".to_owned(),
+            },
+        ];
+        // Longer log of an older term
+        let vote = receive_vote(&node, request(3, 2, 5, 1)).unwrap();
+        assert_eq!((vote.term, vote.granted), (3, false));
+        assert!(receive_vote(&node, request(3, 3, 2, 2)).unwrap().granted);
+        assert!(receive_vote(&node, request(3, 3, 2, 2)).unwrap().granted);
+        assert!(!receive_vote(&node, request(3, 2, 3, 2)).unwrap().granted);
+        // A stale candidate learns the newer term
+        let vote = receive_vote(&node, request(2, 2, 9, 9)).unwrap();
+        assert_eq!((vote.term, vote.granted), (3, false));
+    }
+
+    #[actix_web::test]
+    async fn should_step_down_when_newer_leader_is_elected() {
+        let (nodes, _servers, _urls) = start_cluster(3);
+        assert!(campaign(&nodes[0]).await);
+        assert!(campaign(&nodes[2]).await);
+
+        // The entry of the first leader is committed by the second one
+        replication::replicate(&nodes[2]).await;
+        for node in &nodes {
+            let state = node.state.lock().unwrap();
+            assert_eq!(state.term, 2);
+            assert_eq!(state.leader.as_ref().map(|leader| leader.node_id), Some(3));
+            assert_eq!(state.commit_index, 2);
+        }
+        assert_eq!(nodes[0].state.lock().unwrap().role, Role::Follower);
+    }
+}
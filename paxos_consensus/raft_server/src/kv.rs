@@ -0,0 +1,410 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use actix_web::{delete, get, post, put, web, Error, HttpRequest, HttpResponse};
+use futures_util::StreamExt as _;
+use paxos_server::{
+    kv::{Command, CommandResult},
+    session::{ClientRequest, Session, CLIENT_ID_HEADER, SEQUENCE_HEADER},
+    NO_OP,
+};
+use reqwest::Method;
+use serde::Deserialize;
+
+use crate::{
+    messages::{Entry, ErrorBody},
+    replication::replicate,
+    wal::{self, Record},
+    Leader, RaftNode, Role, CLIENT,
+};
+
+/// Header set on the requests forwarded to the leader, they are never forwarded again.
+const FORWARDED_HEADER: &str = "x-raft-forwarded";
+const COMMIT_POLL: Duration = Duration::from_millis(1);
+
+/// How `GET /kv/{key}` reads, the modes of the Paxos server a Raft leader can serve.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadMode {
+    /// Local state of the node, it can miss the latest writes
+    #[default]
+    Local,
+    /// Linearizable, a no-op is committed through the log and applied before reading
+    Log,
+}
+
+#[derive(Deserialize)]
+pub struct ReadQuery {
+    #[serde(default)]
+    pub read: ReadMode,
+}
+
+/// Reasons an entry was not committed.
+#[derive(Debug)]
+pub enum CommitError {
+    /// This node is not the leader, or stopped being it before the entry was committed
+    NotLeader,
+    /// A majority did not store the entry before the commit timeout
+    TimedOut,
+    Storage(std::io::Error),
+}
+
+impl From<CommitError> for HttpResponse {
+    fn from(error: CommitError) -> Self {
+        match error {
+            CommitError::NotLeader => HttpResponse::ServiceUnavailable().json(ErrorBody {
+                error: "Leadership lost before the entry was committed".to_owned(),
+            }),
+            CommitError::TimedOut => HttpResponse::ServiceUnavailable().json(ErrorBody {
+                error: "Entry not stored by a majority of the nodes".to_owned(),
+            }),
+            CommitError::Storage(e) => HttpResponse::InternalServerError().json(ErrorBody {
+                error: format!("Entry not persisted: {}", e),
+            }),
+        }
+    }
+}
+
+/// Where a request that needs the leader has to be handled.
+enum Leadership {
+    Local,
+    Remote(Leader),
+    /// No leader is known, one is being elected
+    Unknown,
+}
+
+fn leadership(node: &RaftNode, req: &HttpRequest) -> Leadership {
+    if req.headers().contains_key(FORWARDED_HEADER) {
+        return Leadership::Local;
+    }
+    let Ok(state) = node.state.lock() else {
+        return Leadership::Unknown;
+    };
+    match (&state.leader, state.role) {
+        (_, Role::Leader) => Leadership::Local,
+        (Some(leader), _) => Leadership::Remote(leader.clone()),
+        (None, _) => Leadership::Unknown,
+    }
+}
+
+/// Sends the request to the leader and returns its response as is.
+async fn forward(
+    node: &RaftNode,
+    req: &HttpRequest,
+    leader: &Leader,
+    method: Method,
+    path: &str,
+    body: Vec<u8>,
+) -> HttpResponse {
+    node.log("Leader: Forwarding to", &leader.url).await;
+    let timeout = node.request_timeout()
+        + Duration::from_millis(node.commit_timeout_ms.load(Ordering::Acquire));
+    let mut request = CLIENT
+        .request(method, format!("{}{}", leader.url, path))
+        .timeout(timeout)
+        .header(FORWARDED_HEADER, node.node_id.to_string())
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    // The leader drops the duplicates of the client as well
+    for name in [CLIENT_ID_HEADER, SEQUENCE_HEADER] {
+        if let Some(Ok(value)) = req.headers().get(name).map(|value| value.to_str()) {
+            request = request.header(name, value);
+        }
+    }
+    match request.body(body).send().await {
+        Ok(response) => {
+            let status = actix_web::http::StatusCode::from_u16(response.status().as_u16())
+                .unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR);
+            let mut builder = HttpResponse::build(status);
+            if let Some(Ok(content_type)) = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .map(|value| value.to_str())
+            {
+                builder.content_type(content_type);
+            }
+            match response.bytes().await {
+                Ok(body) => builder.body(body),
+                Err(_) => HttpResponse::BadGateway().finish(),
+            }
+        }
+        Err(e) => {
+            node.log("Leader: Forward failed", &e.to_string()).await;
+            if e.is_timeout() {
+                HttpResponse::GatewayTimeout().finish()
+            } else {
+                HttpResponse::BadGateway().finish()
+            }
+        }
+    }
+}
+
+/// Appends the value to the log of the leader and waits until it is committed and applied,
+/// returns the slot it was applied in and the result of its command, if it is one.
+pub async fn submit(
+    node: &RaftNode,
+    value: &str,
+) -> Result<(u64, Option<CommandResult>), CommitError> {
+    let (index, term) = {
+        let mut state = node.state.lock().map_err(|_| CommitError::NotLeader)?;
+        if state.role != Role::Leader {
+            return Err(CommitError::NotLeader);
+        }
+        let entry = Entry {
+            term: state.term,
+            value: value.to_owned(),
+        };
+        let index = state.last_index() + 1;
+        wal::append(
+            node,
+            &[Record::Append {
+                index,
+                entry: entry.clone(),
+            }],
+        )
+        .map_err(CommitError::Storage)?;
+        state.log.push(entry);
+        (index, state.term)
+    };
+    let deadline =
+        Instant::now() + Duration::from_millis(node.commit_timeout_ms.load(Ordering::Acquire));
+    loop {
+        replicate(node).await;
+        {
+            let mut state = node.state.lock().map_err(|_| CommitError::NotLeader)?;
+            if state.term_at(index) != term {
+                return Err(CommitError::NotLeader);
+            }
+            if state.state_machine.applied() >= index {
+                return Ok((index - 1, state.state_machine.take_result(index - 1, 0)));
+            }
+        }
+        if Instant::now() >= deadline {
+            return Err(CommitError::TimedOut);
+        }
+        actix_web::rt::time::sleep(COMMIT_POLL).await;
+    }
+}
+
+/// What the leader knows of an earlier request with the same client id: the session when the
+/// request was already applied, the response to send when a later request of the client was.
+fn applied(node: &RaftNode, request: &ClientRequest) -> Result<Option<Session>, HttpResponse> {
+    let session = node.state.lock().map_or(None, |state| {
+        state.state_machine.session(&request.client).cloned()
+    });
+    match session {
+        Some(session) if session.sequence == request.sequence => Ok(Some(session)),
+        Some(session) if session.sequence > request.sequence => {
+            Err(HttpResponse::Conflict().json(ErrorBody {
+                error: format!(
+                    "Request {} superseded by request {} of the client",
+                    request.sequence, session.sequence
+                ),
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Commits the value of a client through the leader, followers send it to the leader. The
+/// requests carrying a client id are applied at most once, a retry gets the first answer.
+async fn write(
+    node: &RaftNode,
+    req: &HttpRequest,
+    path: &str,
+    value: String,
+) -> Result<(u64, Option<CommandResult>), HttpResponse> {
+    match leadership(node, req) {
+        Leadership::Local => {}
+        Leadership::Remote(leader) => {
+            return Err(forward(node, req, &leader, Method::POST, path, value.into_bytes()).await)
+        }
+        Leadership::Unknown => return Err(HttpResponse::ServiceUnavailable().finish()),
+    }
+    let request = ClientRequest::of(req, value.clone())?;
+    let value = match &request {
+        Some(request) => match applied(node, request)? {
+            Some(session) => return Ok((session.slot, session.result)),
+            None => request
+                .encode()
+                .map_err(|_| HttpResponse::InternalServerError().finish())?,
+        },
+        None => value,
+    };
+    node.log("KV: Command started", &value).await;
+    match submit(node, &value).await {
+        // A retry committed again keeps the slot and the result of the first attempt
+        Ok((slot, None)) => match request.map(|request| applied(node, &request)) {
+            Some(Ok(Some(session))) => Ok((session.slot, session.result)),
+            _ => Ok((slot, None)),
+        },
+        Ok(applied) => Ok(applied),
+        Err(e) => {
+            node.log("KV: Command not committed", &format!("{:?}", e))
+                .await;
+            Err(e.into())
+        }
+    }
+}
+
+async fn execute(node: &RaftNode, req: &HttpRequest, command: Command) -> HttpResponse {
+    let value = match serde_json::to_string(&command) {
+        Ok(value) => value,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match write(node, req, "/kv", value).await {
+        Ok((_, Some(result))) => HttpResponse::Ok().json(result),
+        Ok((slot, None)) => HttpResponse::Accepted().json(slot),
+        Err(response) => response,
+    }
+}
+
+#[post("/consensus")]
+async fn consensus_start(
+    node: web::Data<RaftNode>,
+    req: HttpRequest,
+    mut value: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let mut bytes = web::BytesMut::new();
+    while let Some(item) = value.next().await {
+        bytes.extend_from_slice(&item?);
+    }
+    let value = match String::from_utf8(bytes.to_vec()) {
+        Ok(value) => value,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(ErrorBody {
+                error: format!("Value not valid UTF-8: {}", e),
+            }))
+        }
+    };
+    match write(&node, &req, "/consensus", value.clone()).await {
+        Ok((slot, _)) => {
+            Ok(HttpResponse::Ok().body(format!("Value {value} accepted in slot {slot}!")))
+        }
+        Err(response) => Ok(response),
+    }
+}
+
+/// Reads the key from the local store, `?read=log` commits a no-op through the leader first.
+#[get("/kv/{key}")]
+async fn get_key(
+    node: web::Data<RaftNode>,
+    req: HttpRequest,
+    key: web::Path<String>,
+    query: web::Query<ReadQuery>,
+) -> Result<HttpResponse, Error> {
+    if query.read == ReadMode::Log {
+        match leadership(&node, &req) {
+            Leadership::Local => {
+                if let Err(e) = submit(&node, NO_OP).await {
+                    return Ok(e.into());
+                }
+            }
+            Leadership::Remote(leader) => {
+                let path = format!("/kv/{}?read=log", key);
+                return Ok(forward(&node, &req, &leader, Method::GET, &path, Vec::new()).await);
+            }
+            Leadership::Unknown => return Ok(HttpResponse::ServiceUnavailable().finish()),
+        }
+    }
+    match node
+        .state
+        .lock()
+        .map(|state| state.state_machine.get(&key).cloned())
+    {
+        Ok(Some(value)) => Ok(HttpResponse::Ok().body(value)),
+        Ok(None) => Ok(HttpResponse::NotFound().finish()),
+        Err(_) => Ok(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+#[put("/kv/{key}")]
+async fn put_key(
+    node: web::Data<RaftNode>,
+    req: HttpRequest,
+    key: web::Path<String>,
+    value: String,
+) -> Result<HttpResponse, Error> {
+    Ok(execute(
+        &node,
+        &req,
+        Command::Put {
+            key: key.into_inner(),
+            value,
+        },
+    )
+    .await)
+}
+
+#[delete("/kv/{key}")]
+async fn delete_key(
+    node: web::Data<RaftNode>,
+    req: HttpRequest,
+    key: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    Ok(execute(
+        &node,
+        &req,
+        Command::Delete {
+            key: key.into_inner(),
+        },
+    )
+    .await)
+}
+
+#[post("/kv")]
+async fn submit_command(
+    node: web::Data<RaftNode>,
+    req: HttpRequest,
+    command: web::Json<Command>,
+) -> Result<HttpResponse, Error> {
+    Ok(execute(&node, &req, command.into_inner()).await)
+}
+
+/// Leader of the current term known by the node.
+#[get("/leader")]
+async fn get_leader(node: web::Data<RaftNode>) -> Result<HttpResponse, Error> {
+    match node.state.lock().map(|state| state.leader.clone()) {
+        Ok(Some(leader)) => Ok(HttpResponse::Ok().json(leader)),
+        Ok(None) => Ok(HttpResponse::ServiceUnavailable().finish()),
+        Err(_) => Ok(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use paxos_server::client::Client;
+
+    use crate::tests::start_cluster;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn should_apply_retried_request_once() {
+        let (nodes, _servers, urls) = start_cluster(3);
+        assert!(crate::election::campaign(&nodes[0]).await);
+        let http = reqwest::Client::new();
+        let send = |url: &String| {
+            http.post(format!("{}/kv", url))
+                .header(CLIENT_ID_HEADER, "client")
+                .header(SEQUENCE_HEADER, 1)
+                .json(&Command::Put {
+                    key: "counter".to_owned(),
+                    value: "1".to_owned(),
+                })
+                .send()
+        };
+        let first: CommandResult = send(&urls[0]).await.unwrap().json().await.unwrap();
+        // The retry sent to a follower gets the answer of the first attempt
+        let retry: CommandResult = send(&urls[2]).await.unwrap().json().await.unwrap();
+        assert_eq!(first, retry);
+        assert_eq!(first.slot, 1);
+        assert_eq!(nodes[0].state.lock().unwrap().last_index(), 2);
+
+        // A client restarted without its last sequence number is answered from its session
+        let client = Client::with_client_id(urls.clone(), "client".to_owned());
+        assert_eq!(client.put("counter", "0").await.unwrap(), first);
+        assert_eq!(client.get("counter").await.unwrap(), Some("1".to_owned()));
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use actix_web::web;
+use lazy_static::lazy_static;
+use paxos_server::kv::StateMachine;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    election::{request_vote, DEFAULT_ELECTION_TIMEOUT_MS},
+    kv::{consensus_start, delete_key, get_key, get_leader, put_key, submit_command},
+    messages::Entry,
+    replication::{append_entries, DEFAULT_HEARTBEAT_MS},
+};
+
+mod election;
+mod kv;
+mod messages;
+mod replication;
+mod wal;
+
+pub const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 1000;
+pub const DEFAULT_COMMIT_TIMEOUT_MS: u64 = 5000;
+
+lazy_static! {
+    static ref CLIENT: Client = reqwest::Client::new();
+}
+
+/// State of a Raft node, every handler gets it from the app data so several nodes can run in the
+/// same process.
+pub struct RaftNode {
+    node_id: u64,
+    log_server: String,
+    // Every node of the cluster, this one included, and the address the others reach it at
+    nodes: RwLock<Vec<String>>,
+    node_url: RwLock<String>,
+    state: Mutex<State>,
+    // A follower that hears from no leader for this long, up to twice as long, runs for
+    // leadership
+    election_timeout_ms: AtomicU64,
+    // How often the leader sends its entries, empty ones as heartbeats
+    heartbeat_ms: AtomicU64,
+    // How long a request to a peer can take before it is given up
+    request_timeout_ms: AtomicU64,
+    // How long a client request waits for its entry to be committed
+    commit_timeout_ms: AtomicU64,
+    // Append-only log of the node, when not opened nothing is persisted
+    wal: Mutex<Option<File>>,
+}
+
+/// What a node knows of the cluster, changed under a single lock.
+pub struct State {
+    // Persisted: latest term seen, candidate voted for in it and the log, the entry at index
+    // `i` is `log[i - 1]`
+    term: u64,
+    voted_for: Option<u64>,
+    log: Vec<Entry>,
+    // Highest entry known to be stored by a majority
+    commit_index: u64,
+    role: Role,
+    leader: Option<Leader>,
+    election_deadline: Instant,
+    // Leader only, next entry to send to every peer and highest one known stored by it
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    // Key-value store of the Paxos server, the entry at index `i` is applied as slot `i - 1`
+    state_machine: StateMachine,
+}
+
+impl State {
+    fn last_index(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    /// Term of the entry at the index, 0 before the first one and after the last one.
+    fn term_at(&self, index: u64) -> u64 {
+        index
+            .checked_sub(1)
+            .and_then(|i| self.log.get(i as usize))
+            .map_or(0, |entry| entry.term)
+    }
+
+    /// Stores the entry at the index, in place of the entries from there on.
+    fn store(&mut self, index: u64, entry: Entry) {
+        self.log.truncate(index.saturating_sub(1) as usize);
+        self.log.push(entry);
+    }
+
+    /// Applies, in order, every committed entry not applied yet.
+    fn apply_committed(&mut self) {
+        while self.state_machine.applied() < self.commit_index {
+            let Some(entry) = self.log.get(self.state_machine.applied() as usize) else {
+                break;
+            };
+            self.state_machine.apply_next(&entry.value);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Leader of the current term, followers send it the client requests.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Leader {
+    node_id: u64,
+    url: String,
+}
+
+impl RaftNode {
+    fn new(node_id: u64, log_server: String) -> Self {
+        Self {
+            node_id,
+            log_server,
+            nodes: RwLock::new(Vec::new()),
+            node_url: RwLock::new(String::new()),
+            state: Mutex::new(State {
+                term: 0,
+                voted_for: None,
+                log: Vec::new(),
+                commit_index: 0,
+                role: Role::Follower,
+                leader: None,
+                election_deadline: election::deadline(DEFAULT_ELECTION_TIMEOUT_MS),
+                next_index: HashMap::new(),
+                match_index: HashMap::new(),
+                state_machine: StateMachine::default(),
+            }),
+            election_timeout_ms: AtomicU64::new(DEFAULT_ELECTION_TIMEOUT_MS),
+            heartbeat_ms: AtomicU64::new(DEFAULT_HEARTBEAT_MS),
+            request_timeout_ms: AtomicU64::new(DEFAULT_REQUEST_TIMEOUT_MS),
+            commit_timeout_ms: AtomicU64::new(DEFAULT_COMMIT_TIMEOUT_MS),
+            wal: Mutex::new(None),
+        }
+    }
+
+    /// Configures the node from the environment, the write-ahead log (if any) is replayed.
+    pub fn from_env() -> std::io::Result<Self> {
+        let node_id_value = std::env::var("NODE_ID").expect("Node id not set");
+        let node_id = node_id_value
+            .parse::<u64>()
+            .expect("Node id should be a number");
+        let log_server = std::env::var("LOG_SERVER").expect("Log server not set");
+        println!("Log server used: {}", log_server);
+        let node = RaftNode::new(node_id, log_server);
+
+        let raft_nodes_values = std::env::var("RAFT_NODES").expect("Raft nodes are not set");
+        if let Ok(mut raft_nodes) = node.nodes.write() {
+            raft_nodes.extend(raft_nodes_values.split(',').map(|v| v.to_string()));
+            println!("Raft nodes: {:?}", raft_nodes);
+        }
+        let node_url_value = std::env::var("RAFT_NODE_URL").expect("Raft node url not set");
+        if let Ok(mut node_url) = node.node_url.write() {
+            *node_url = node_url_value;
+        }
+
+        if let Ok(election_timeout) = std::env::var("RAFT_ELECTION_TIMEOUT_MS") {
+            node.election_timeout_ms.store(
+                election_timeout
+                    .parse::<u64>()
+                    .expect("Election timeout should be a number"),
+                Ordering::Release,
+            );
+        }
+        if let Ok(heartbeat) = std::env::var("RAFT_HEARTBEAT_MS") {
+            node.heartbeat_ms.store(
+                heartbeat
+                    .parse::<u64>()
+                    .expect("Heartbeat interval should be a number"),
+                Ordering::Release,
+            );
+        }
+        if let Ok(request_timeout) = std::env::var("RAFT_REQUEST_TIMEOUT_MS") {
+            node.request_timeout_ms.store(
+                request_timeout
+                    .parse::<u64>()
+                    .expect("Request timeout should be a number"),
+                Ordering::Release,
+            );
+        }
+        if let Ok(commit_timeout) = std::env::var("RAFT_COMMIT_TIMEOUT_MS") {
+            node.commit_timeout_ms.store(
+                commit_timeout
+                    .parse::<u64>()
+                    .expect("Commit timeout should be a number"),
+                Ordering::Release,
+            );
+        }
+
+        if let Ok(wal_path) = std::env::var("RAFT_WAL_PATH") {
+            let replayed = wal::open(&node, &wal_path)?;
+            println!(
+                "Write-ahead log used: {}, records replayed: {}",
+                wal_path, replayed
+            );
+        }
+        Ok(node)
+    }
+
+    /// Every node of the cluster but this one.
+    fn peers(&self) -> Vec<String> {
+        let node_url = self
+            .node_url
+            .read()
+            .map_or(String::new(), |url| url.clone());
+        self.nodes.read().map_or(vec![], |nodes| {
+            nodes.iter().filter(|n| **n != node_url).cloned().collect()
+        })
+    }
+
+    /// How many nodes, this one included, have to store an entry or vote for a candidate.
+    fn majority(&self) -> usize {
+        self.nodes.read().map_or(1, |nodes| nodes.len() / 2 + 1)
+    }
+
+    fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout_ms.load(Ordering::Acquire))
+    }
+
+    /// Sends the message to an endpoint of a peer and reads its answer, fails when the peer does
+    /// not answer within the request timeout.
+    async fn send<T: Serialize, R: for<'de> Deserialize<'de>>(
+        &self,
+        url: &str,
+        path: &str,
+        message: &T,
+    ) -> Result<R, reqwest::Error> {
+        CLIENT
+            .post(format!("{}{}", url, path))
+            .timeout(self.request_timeout())
+            .json(message)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    async fn log(&self, message: &str, value: &str) {
+        paxos_server::post_log(&self.log_server, self.request_timeout(), message, value).await
+    }
+}
+
+/// Registers every endpoint of the node, the node itself has to be in the app data.
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(request_vote)
+        .service(append_entries)
+        .service(consensus_start)
+        .service(get_key)
+        .service(put_key)
+        .service(delete_key)
+        .service(submit_command)
+        .service(get_leader);
+}
+
+/// Starts what the node runs in background, the followers wait for the leader to time out and
+/// the leader sends its entries.
+pub fn spawn_tasks(node: &web::Data<RaftNode>) {
+    actix_web::rt::spawn(election::run(node.clone()));
+    actix_web::rt::spawn(replication::run(node.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+    use paxos_server::client::Client;
+
+    use super::*;
+
+    pub fn new_node_with_id(node_id: u64) -> web::Data<RaftNode> {
+        web::Data::new(RaftNode::new(node_id, "invalid-server".to_owned()))
+    }
+
+    /// Serves every endpoint of the node on a random port.
+    pub fn start_node(node: &web::Data<RaftNode>) -> actix_test::TestServer {
+        let data = node.clone();
+        actix_test::start(move || App::new().app_data(data.clone()).configure(services))
+    }
+
+    /// Nodes knowing each other, served on random ports, none is the leader yet.
+    pub fn start_cluster(
+        amount: u64,
+    ) -> (
+        Vec<web::Data<RaftNode>>,
+        Vec<actix_test::TestServer>,
+        Vec<String>,
+    ) {
+        let nodes = (1..=amount).map(new_node_with_id).collect::<Vec<_>>();
+        let servers = nodes.iter().map(start_node).collect::<Vec<_>>();
+        let urls = servers
+            .iter()
+            .map(|server| format!("http://{}", server.addr()))
+            .collect::<Vec<_>>();
+        for (node, url) in nodes.iter().zip(&urls) {
+            node.nodes.write().unwrap().extend_from_slice(&urls);
+            *node.node_url.write().unwrap() = url.clone();
+        }
+        (nodes, servers, urls)
+    }
+
+    #[actix_web::test]
+    async fn should_serve_paxos_client_api() {
+        let (nodes, _servers, urls) = start_cluster(3);
+        assert!(election::campaign(&nodes[1]).await);
+
+        // The client of the Paxos server works as it is, the first node forwards to the leader
+        let client = Client::new(urls.clone());
+        let result = client.put("key", "value").await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.value, None);
+        assert_eq!(client.get("key").await.unwrap(), Some("value".to_owned()));
+        assert_eq!(client.get("missing").await.unwrap(), None);
+        let result = client.delete("key").await.unwrap();
+        assert_eq!(result.value, Some("value".to_owned()));
+        // Slot 0 is the entry of the leader starting its term, the reads used slots 2 and 3
+        assert_eq!(
+            client.propose("value").await.unwrap(),
+            "Value value accepted in slot 5!"
+        );
+
+        // The next heartbeat tells the followers what is committed
+        replication::replicate(&nodes[1]).await;
+        for node in &nodes {
+            let state = node.state.lock().unwrap();
+            assert_eq!(state.commit_index, 6);
+            assert_eq!(state.state_machine.applied(), 6);
+            assert_eq!(state.state_machine.get("key"), None);
+            assert_eq!(
+                state
+                    .state_machine
+                    .session(client.client_id())
+                    .map(|session| session.sequence),
+                Some(3)
+            );
+        }
+    }
+}
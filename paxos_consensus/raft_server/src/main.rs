@@ -0,0 +1,33 @@
+use actix_web::{web, App, HttpServer};
+use paxos_server::admin::probe;
+use raft_server::{services, spawn_tasks, RaftNode};
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let port = std::env::var("PORT")
+        .expect("Port not set")
+        .parse::<u16>()
+        .expect("Port should be a number");
+    // `raft_server probe /leader` checks the node running in the same container
+    let args = std::env::args().collect::<Vec<_>>();
+    if let [_, command, path] = args.as_slice() {
+        if command == "probe" {
+            return probe(port, path).await;
+        }
+    }
+
+    let node = web::Data::new(RaftNode::from_env()?);
+    spawn_tasks(&node);
+
+    println!("Starting server...");
+    let mut server = HttpServer::new(move || App::new().app_data(node.clone()).configure(services));
+    // One worker per core by default
+    if let Ok(workers) = std::env::var("RAFT_WORKERS") {
+        server = server.workers(
+            workers
+                .parse::<usize>()
+                .expect("Workers should be a number"),
+        );
+    }
+    server.bind(("0.0.0.0", port))?.run().await
+}
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+/// Entry of the replicated log, `value` is decided like the value of a Paxos slot: a key-value
+/// command, a client request, a plain value or a no-op.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub term: u64,
+    pub value: String,
+}
+
+/// Body of `/raft/request_vote`, sent by a candidate with the position of its last entry.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RequestVote {
+    pub term: u64,
+    pub candidate_id: u64,
+    pub last_log_index: u64,
+    pub last_log_term: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Vote {
+    pub term: u64,
+    pub granted: bool,
+}
+
+/// Body of `/raft/append_entries`, the entries following `prev_log_index`, none for a
+/// heartbeat.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AppendEntries {
+    pub term: u64,
+    pub leader_id: u64,
+    pub leader_url: String,
+    pub prev_log_index: u64,
+    pub prev_log_term: u64,
+    pub entries: Vec<Entry>,
+    pub leader_commit: u64,
+}
+
+/// Answer to `/raft/append_entries`, `last_index` is the last entry matching the leader log
+/// when the entries were stored, otherwise the last one the follower can match from.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Appended {
+    pub term: u64,
+    pub success: bool,
+    pub last_index: u64,
+}
+
+/// Body of the responses to requests that could not be handled.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ErrorBody {
+    pub error: String,
+}
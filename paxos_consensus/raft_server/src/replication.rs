@@ -0,0 +1,244 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
+use actix_web::{post, web, Error, HttpResponse};
+use futures::future::join_all;
+
+use crate::{
+    election::{deadline, observe_term},
+    messages::{AppendEntries, Appended},
+    wal::{self, Record},
+    Leader, RaftNode, Role,
+};
+
+pub const DEFAULT_HEARTBEAT_MS: u64 = 50;
+
+#[post("/raft/append_entries")]
+async fn append_entries(
+    node: web::Data<RaftNode>,
+    request: web::Json<AppendEntries>,
+) -> Result<HttpResponse, Error> {
+    match receive_append(&node, request.into_inner()) {
+        Ok(appended) => Ok(HttpResponse::Ok().json(appended)),
+        Err(e) => {
+            node.log("Replication: Entries not persisted", &e.to_string())
+                .await;
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Stores the entries of the leader once the log of this node matches the leader log up to
+/// `prev_log_index`, an entry conflicting with the leader log is replaced with every entry
+/// after it. The entries committed by the leader are applied.
+pub fn receive_append(node: &RaftNode, request: AppendEntries) -> std::io::Result<Appended> {
+    let mut state = node
+        .state
+        .lock()
+        .map_err(|_| std::io::Error::other("state not available"))?;
+    if request.term < state.term {
+        return Ok(Appended {
+            term: state.term,
+            success: false,
+            last_index: state.last_index(),
+        });
+    }
+    let mut records = observe_term(node, &mut state, request.term)
+        .into_iter()
+        .collect::<Vec<_>>();
+    state.role = Role::Follower;
+    state.leader = Some(Leader {
+        node_id: request.leader_id,
+        url: request.leader_url,
+    });
+    state.election_deadline = deadline(node.election_timeout_ms.load(Ordering::Acquire));
+    if request.prev_log_index > state.last_index()
+        || state.term_at(request.prev_log_index) != request.prev_log_term
+    {
+        wal::append(node, &records)?;
+        return Ok(Appended {
+            term: state.term,
+            success: false,
+            last_index: state
+                .last_index()
+                .min(request.prev_log_index.saturating_sub(1)),
+        });
+    }
+    let last_index = request.prev_log_index + request.entries.len() as u64;
+    // Entries already stored are kept, a late message must not drop the ones after them
+    let mut conflict = false;
+    for (index, entry) in (request.prev_log_index + 1..).zip(request.entries) {
+        conflict = conflict || state.term_at(index) != entry.term;
+        if conflict {
+            records.push(Record::Append { index, entry });
+        }
+    }
+    wal::append(node, &records)?;
+    for record in records {
+        if let Record::Append { index, entry } = record {
+            state.store(index, entry);
+        }
+    }
+    if request.leader_commit > state.commit_index {
+        state.commit_index = request.leader_commit.min(last_index);
+        state.apply_committed();
+    }
+    Ok(Appended {
+        term: state.term,
+        success: true,
+        last_index,
+    })
+}
+
+/// Sends the leader log to every peer, from the next entry it needs, and commits the entries of
+/// the current term stored by a majority. A peer that does not match the log is sent older
+/// entries on the next call.
+pub async fn replicate(node: &RaftNode) {
+    let requests = {
+        let Ok(state) = node.state.lock() else {
+            return;
+        };
+        if state.role != Role::Leader {
+            return;
+        }
+        let leader_url = node
+            .node_url
+            .read()
+            .map_or(String::new(), |url| url.clone());
+        node.peers()
+            .into_iter()
+            .map(|peer| {
+                let next_index = state.next_index.get(&peer).copied().unwrap_or(1).max(1);
+                let request = AppendEntries {
+                    term: state.term,
+                    leader_id: node.node_id,
+                    leader_url: leader_url.clone(),
+                    prev_log_index: next_index - 1,
+                    prev_log_term: state.term_at(next_index - 1),
+                    entries: state.log[(next_index - 1) as usize..].to_vec(),
+                    leader_commit: state.commit_index,
+                };
+                (peer, request)
+            })
+            .collect::<Vec<_>>()
+    };
+    let replies =
+        join_all(requests.iter().map(|(peer, request)| {
+            node.send::<_, Appended>(peer, "/raft/append_entries", request)
+        }))
+        .await;
+    let mut answers = Vec::new();
+    for ((peer, request), reply) in requests.iter().zip(replies) {
+        match reply {
+            Ok(appended) => answers.push((peer, request, appended)),
+            Err(e) => node.log("Replication: Error", &e.to_string()).await,
+        }
+    }
+    let Ok(mut state) = node.state.lock() else {
+        return;
+    };
+    for (peer, request, appended) in answers {
+        if let Some(record) = observe_term(node, &mut state, appended.term) {
+            let _ = wal::append(node, &[record]);
+            return;
+        }
+        if state.role != Role::Leader || state.term != request.term {
+            return;
+        }
+        if appended.success {
+            let match_index = state.match_index.entry(peer.clone()).or_default();
+            *match_index = (*match_index).max(appended.last_index);
+            let next_index = *match_index + 1;
+            state.next_index.insert(peer.clone(), next_index);
+        } else {
+            let next_index = (request.prev_log_index).min(appended.last_index + 1).max(1);
+            state.next_index.insert(peer.clone(), next_index);
+        }
+    }
+    // The highest index stored by a majority, the leader stores every entry
+    let mut matched = state.match_index.values().copied().collect::<Vec<_>>();
+    matched.push(state.last_index());
+    matched.sort_unstable_by(|a, b| b.cmp(a));
+    let majority_index = matched
+        .get(node.majority() - 1)
+        .copied()
+        .unwrap_or_default();
+    // Entries of older terms are only committed with an entry of the current term
+    if majority_index > state.commit_index && state.term_at(majority_index) == state.term {
+        state.commit_index = majority_index;
+        state.apply_committed();
+    }
+}
+
+/// Sends the entries of the leader to its peers every heartbeat interval, they keep the
+/// followers from running for leadership.
+pub async fn run(node: web::Data<RaftNode>) {
+    loop {
+        let heartbeat = node.heartbeat_ms.load(Ordering::Acquire);
+        actix_web::rt::time::sleep(Duration::from_millis(heartbeat)).await;
+        replicate(&node).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{messages::Entry, tests::new_node_with_id};
+
+    use super::*;
+
+    fn entry(term: u64, value: &str) -> Entry {
+        Entry {
+            term,
+            value: value.to_owned(),
+        }
+    }
+
+    fn append(
+        term: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry>,
+    ) -> AppendEntries {
+        AppendEntries {
+            term,
+            leader_id: 2,
+            leader_url: "http://leader".to_owned(),
+            prev_log_index,
+            prev_log_term,
+            entries,
+            leader_commit: 2,
+        }
+    }
+
+    #[test]
+    fn should_replace_entries_conflicting_with_leader_log() {
+        let node = new_node_with_id(1);
+        {
+            let mut state = node.state.lock().unwrap();
+            state.term = 2;
+            state.log = vec![entry(1, "a"), entry(2, "b"), entry(2, "c")];
+        }
+        // A stale leader is refused
+        let appended = receive_append(&node, append(1, 3, 2, vec![])).unwrap();
+        assert!(!appended.success);
+        assert_eq!(appended.term, 2);
+
+        // The log does not go as far as the previous entry of the leader
+        let appended = receive_append(&node, append(3, 5, 3, vec![entry(3, "e")])).unwrap();
+        assert_eq!((appended.success, appended.last_index), (false, 3));
+
+        let appended =
+            receive_append(&node, append(3, 1, 1, vec![entry(2, "b"), entry(3, "d")])).unwrap();
+        assert_eq!((appended.success, appended.last_index), (true, 3));
+        let state = node.state.lock().unwrap();
+        assert_eq!(state.log, vec![entry(1, "a"), entry(2, "b"), entry(3, "d")]);
+        assert_eq!(state.commit_index, 2);
+        assert_eq!(state.state_machine.applied(), 2);
+        assert_eq!(
+            state.leader,
+            Some(Leader {
+                node_id: 2,
+                url: "http://leader".to_owned()
+            })
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{messages::Entry, RaftNode};
+
+/// Every state change that has to survive a restart, written before answering to the caller.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub enum Record {
+    Vote {
+        term: u64,
+        voted_for: Option<u64>,
+    },
+    /// The entry is stored at the index, replacing it and every entry after it
+    Append {
+        index: u64,
+        entry: Entry,
+    },
+}
+
+/// Reads the records of the log at `path` (if present), returns them with the length of the
+/// part of the file holding them. Only a partial last line is left out, any other record that
+/// cannot be read fails the whole read as the records after it were acknowledged.
+fn read(path: &Path) -> std::io::Result<(Vec<Record>, u64)> {
+    let mut records = Vec::new();
+    let mut valid_len = 0;
+    match File::open(path) {
+        Ok(file) => {
+            let mut reader = BufReader::new(file);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                let read = reader.read_until(b'\n', &mut line)?;
+                // A crash while appending can leave a partial last line, it was never
+                // acknowledged so it is dropped
+                if read == 0 || line.last() != Some(&b'\n') {
+                    break;
+                }
+                let record = serde_json::from_slice::<Record>(&line).map_err(|e| {
+                    std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Record at byte {} not valid: {}", valid_len, e),
+                    )
+                })?;
+                records.push(record);
+                valid_len += read as u64;
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok((records, valid_len))
+}
+
+/// Replays the log at `path` (if present) into the node state and keeps the file open for
+/// the following appends. The commit index is not persisted, the leader tells it again.
+pub fn open(node: &RaftNode, path: impl AsRef<Path>) -> std::io::Result<usize> {
+    let path = path.as_ref();
+    let (records, valid_len) = read(path)?;
+    let replayed = records.len();
+    if let Ok(mut state) = node.state.lock() {
+        for record in records {
+            match record {
+                Record::Vote { term, voted_for } => {
+                    state.term = term;
+                    state.voted_for = voted_for;
+                }
+                Record::Append { index, entry } => state.store(index, entry),
+            }
+        }
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.set_len(valid_len)?;
+    if let Ok(mut wal) = node.wal.lock() {
+        *wal = Some(file);
+        Ok(replayed)
+    } else {
+        Err(std::io::Error::other("write-ahead log not available"))
+    }
+}
+
+/// Appends the records and waits until they are on disk.
+pub fn append(node: &RaftNode, records: &[Record]) -> std::io::Result<()> {
+    let mut wal = node
+        .wal
+        .lock()
+        .map_err(|_| std::io::Error::other("write-ahead log not available"))?;
+    if let Some(file) = wal.as_mut() {
+        for record in records {
+            let mut line = serde_json::to_vec(record)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{messages::AppendEntries, replication::receive_append, tests::new_node_with_id};
+
+    use super::*;
+
+    #[test]
+    fn should_restore_term_vote_and_log() {
+        let path = std::env::temp_dir().join(format!("raft_{}.wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let node = new_node_with_id(1);
+        assert_eq!(open(&node, &path).unwrap(), 0);
+        let entry = |term, value: &str| Entry {
+            term,
+            value: value.to_owned(),
+        };
+        let append = |term, prev_log_index, prev_log_term, entries| AppendEntries {
+            term,
+            leader_id: 2,
+            leader_url: "http://leader".to_owned(),
+            prev_log_index,
+            prev_log_term,
+            entries,
+            leader_commit: 0,
+        };
+        receive_append(&node, append(1, 0, 0, vec![entry(1, "a"), entry(1, "b")])).unwrap();
+        // The leader of term 2 replaces the second entry
+        receive_append(&node, append(2, 1, 1, vec![entry(2, "c")])).unwrap();
+
+        let restarted = new_node_with_id(1);
+        assert_eq!(open(&restarted, &path).unwrap(), 5);
+        let state = restarted.state.lock().unwrap();
+        assert_eq!(state.term, 2);
+        assert_eq!(state.voted_for, None);
+        assert_eq!(state.log, vec![entry(1, "a"), entry(2, "c")]);
+        // Nothing is applied before the leader tells what is committed
+        assert_eq!(state.state_machine.applied(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn should_refuse_log_with_corrupt_record() {
+        let path = std::env::temp_dir().join(format!("raft_corrupt_{}.wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let node = new_node_with_id(1);
+        open(&node, &path).unwrap();
+        let vote = |term| Record::Vote {
+            term,
+            voted_for: Some(2),
+        };
+        append(&node, &[vote(1)]).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"Vote\":\n").unwrap();
+        file.write_all(&serde_json::to_vec(&vote(2)).unwrap())
+            .unwrap();
+        file.write_all(b"\n").unwrap();
+        drop(file);
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        // The records after the corrupt one were acknowledged, they are neither dropped nor
+        // replayed
+        let restarted = new_node_with_id(1);
+        let error = open(&restarted, &path).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(restarted.state.lock().unwrap().term, 0);
+        let _ = std::fs::remove_file(&path);
+    }
+}